@@ -19,6 +19,13 @@ void area_end(void);
 
 void* area_register_alloc(void* ptr);
 void* area_alloc(size_t size);
+/* Allocates in an enclosing area, identified by the index `area_current` returned while it was current. */
+void* area_alloc_in(int area, size_t size);
+int area_current(void);
 void* area_memdup(const void* src, size_t size);
+void* area_memdup_in(int area, const void* src, size_t size);
+char* area_strdup_in(int area, const char* str);
+/* Moves a block allocated in a nested area to an enclosing one, so it lives as long as that area. */
+void area_move_in(int area, void* ptr);
 
 #endif // !LAMP_AREA_H
\ No newline at end of file
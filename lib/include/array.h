@@ -0,0 +1,27 @@
+#ifndef LAMP_ARRAY_H
+#define LAMP_ARRAY_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+/* A growable array. Its elements live in the area that was current when it was created, so growing
+   it inside a nested block does not free them at the end of that block. */
+typedef struct LampArray {
+    char* data;
+    int64_t len;
+    int64_t cap;
+    size_t elem_size;
+    int area;
+} LampArray;
+
+LampArray* array_new(size_t elem_size, int64_t len, const void* items);
+/* Returns a pointer to an element, or aborts with the Lamp source location if the index is out of bounds. */
+void* array_at(LampArray* array, int64_t index, const char* location);
+void array_push(LampArray* array, const void* item);
+int64_t array_len(const LampArray* array);
+/* Moves an array that belongs to a nested area, and its elements but not what they point to, to an
+   enclosing area without changing its address. Returns whether it moved; an array that already
+   belongs to that area or an outer one holds only values that live as long as it does. */
+int array_move_in(int area, LampArray* array);
+
+#endif // !LAMP_ARRAY_H
\ No newline at end of file
@@ -0,0 +1,52 @@
+#ifndef LAMP_INTEGER_H
+#define LAMP_INTEGER_H
+
+#include <stdint.h>
+#include <string.h>
+
+#include "io.h"
+
+/* Integer operations that C leaves undefined or implementation specific, with the semantics of the
+   interpreter: arithmetic wraps around, and dividing by zero stops the program. Narrower integers are
+   widened to 64 bits and the result is cast back, which wraps it. */
+
+static inline int64_t integer_div(int64_t a, int64_t b, const char* location) {
+    if (b == 0) runtime_error(location, "division by zero");
+    /* The smallest value divided by -1 overflows; negating wraps instead. */
+    if (b == -1) return (int64_t)(0 - (uint64_t)a);
+    return a / b;
+}
+
+static inline int64_t integer_rem(int64_t a, int64_t b, const char* location) {
+    if (b == 0) runtime_error(location, "division by zero");
+    if (b == -1) return 0;
+    return a % b;
+}
+
+static inline uint64_t integer_udiv(uint64_t a, uint64_t b, const char* location) {
+    if (b == 0) runtime_error(location, "division by zero");
+    return a / b;
+}
+
+static inline uint64_t integer_urem(uint64_t a, uint64_t b, const char* location) {
+    if (b == 0) runtime_error(location, "division by zero");
+    return a % b;
+}
+
+/* Truncates a number towards zero and wraps it to 64 bits. Casting a double that is out of range
+   is undefined, so large values are taken apart instead. Like a Rust `as i128` cast, NaN becomes 0
+   and values beyond 128 bits saturate. */
+static inline uint64_t integer_from_number(double n) {
+    if (n != n) return 0;
+    if (n >= 0x1p127) return UINT64_MAX;
+    if (n <= -0x1p127) return 0;
+    if (n > -0x1p63 && n < 0x1p63) return (uint64_t)(int64_t)n;
+    uint64_t bits;
+    memcpy(&bits, &n, sizeof bits);
+    int exponent = (int)((bits >> 52) & 0x7ff) - 1075;
+    uint64_t mantissa = (bits & ((UINT64_C(1) << 52) - 1)) | (UINT64_C(1) << 52);
+    uint64_t magnitude = exponent >= 64 ? 0 : mantissa << exponent;
+    return n < 0 ? 0 - magnitude : magnitude;
+}
+
+#endif // !LAMP_INTEGER_H
@@ -4,4 +4,6 @@ char* format(const char* fmt, ...);
 void print(const char* fmt, ...);
 void println(const char* fmt, ...);
 void input(const char* fmt, ...);
+/* Reports an error at a Lamp source location, such as a division by zero, and exits. */
+void runtime_error(const char* location, const char* message);
 #endif // LAMP_IO_H
\ No newline at end of file
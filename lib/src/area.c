@@ -51,8 +51,52 @@ void* area_alloc(size_t size) {
     return area_register_alloc(block);
 }
 
+void* area_alloc_in(int area, size_t size) {
+    if (area < 0 || area >= area_depth) {
+        fprintf(stderr, "Area %d is not open!\n", area);
+        exit(1);
+    }
+
+    AllocNode* node = malloc(sizeof(AllocNode));
+    node->ptr = malloc(size);
+    node->next = area_stack[area].allocations;
+
+    area_stack[area].allocations = node;
+    return node->ptr;
+}
+
+int area_current() {
+    return area_depth - 1;
+}
+
 void* area_memdup(const void* src, size_t size) {
     void* block = area_alloc(size);
     memcpy(block, src, size);
     return block;
+}
+
+void* area_memdup_in(int area, const void* src, size_t size) {
+    void* block = area_alloc_in(area, size);
+    memcpy(block, src, size);
+    return block;
+}
+
+char* area_strdup_in(int area, const char* str) {
+    return area_memdup_in(area, str, strlen(str) + 1);
+}
+
+void area_move_in(int area, void* ptr) {
+    for (int i = area_depth - 1; i > area; i--) {
+        AllocNode** link = &area_stack[i].allocations;
+        while (*link) {
+            AllocNode* node = *link;
+            if (node->ptr == ptr) {
+                *link = node->next;
+                node->next = area_stack[area].allocations;
+                area_stack[area].allocations = node;
+                return;
+            }
+            link = &node->next;
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,49 @@
+#include "array.h"
+#include "area.h"
+
+LampArray* array_new(size_t elem_size, int64_t len, const void* items) {
+    LampArray* array = area_alloc(sizeof(LampArray));
+    array->len = len;
+    array->cap = len > 0 ? len : 4;
+    array->elem_size = elem_size;
+    array->area = area_current();
+    array->data = area_alloc(array->cap * elem_size);
+    if (len > 0) {
+        memcpy(array->data, items, len * elem_size);
+    }
+    return array;
+}
+
+void* array_at(LampArray* array, int64_t index, const char* location) {
+    if (index < 0 || index >= array->len) {
+        fprintf(stderr, "%s: index %lld is out of bounds for an array of length %lld\n",
+                location, (long long)index, (long long)array->len);
+        exit(1);
+    }
+    return array->data + index * array->elem_size;
+}
+
+void array_push(LampArray* array, const void* item) {
+    if (array->len == array->cap) {
+        array->cap *= 2;
+        char* data = area_alloc_in(array->area, array->cap * array->elem_size);
+        memcpy(data, array->data, array->len * array->elem_size);
+        array->data = data;
+    }
+    memcpy(array->data + array->len * array->elem_size, item, array->elem_size);
+    array->len++;
+}
+
+int64_t array_len(const LampArray* array) {
+    return array->len;
+}
+
+int array_move_in(int area, LampArray* array) {
+    if (array->area <= area) {
+        return 0;
+    }
+    area_move_in(area, array);
+    area_move_in(area, array->data);
+    array->area = area;
+    return 1;
+}
\ No newline at end of file
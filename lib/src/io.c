@@ -3,71 +3,70 @@
 #include <stdarg.h>
 #include <string.h>
 
+#include "area.h"
 #include "io.h"
 
-int count_placeholders(const char* fmt) {
-    int count = 0;
+/* Writes `fmt` with its placeholders replaced into `out`, like `snprintf`: at most `size` bytes
+ * including the terminating NUL, and returns the length of the whole result. */
+static size_t format_into(char* out, size_t size, const char* fmt, va_list args) {
+    size_t len = 0;
     const char* p = fmt;
 
-    while (*p) {
-        if (*p == '{' && *(p + 1) && *(p + 2) == '}') {
-            count++;
-            p += 3;
-        } else {
-            p++;
-        }
-    }
-
-    return count;
-}
-
-char* format_internal(const char* fmt, va_list args) {
-    if (!fmt) return NULL;
-
-    int placeholder_count = count_placeholders(fmt);
-    size_t estimated_size = strlen(fmt) + placeholder_count * 64;
-
-    char* result = (char*)malloc(estimated_size);
-    if (!result) return NULL;
-
-    const char* p = fmt;
-    char* out = result;
-    size_t remaining = estimated_size;
-
     while (*p) {
         if (*p == '{' && *(p + 1) && *(p + 2) == '}') {
             char type = *(p + 1);
+            int written = 0;
 
             if (type == 's') {
                 const char* str = va_arg(args, const char*);
                 if (str) {
-                    size_t len = strlen(str);
-                    if (len < remaining) {
-                        strcpy(out, str);
-                        out += len;
-                        remaining -= len;
-                        printf("%s", out);
+                    size_t str_len = strlen(str);
+                    if (len < size) {
+                        size_t available = size - len - 1;
+                        memcpy(out + len, str, str_len < available ? str_len : available);
                     }
+                    len += str_len;
                 }
+            } else if (type == 'i') {
+                long long val = va_arg(args, long long);
+                written = snprintf(len < size ? out + len : NULL, len < size ? size - len : 0, "%lld", val);
             } else if (type == 'f') {
                 double val = va_arg(args, double);
-                int written = snprintf(out, remaining, "%g", val);
-                if (written > 0 && written < remaining) {
-                    out += written;
-                    remaining -= written;
-                }
+                written = snprintf(len < size ? out + len : NULL, len < size ? size - len : 0, "%g", val);
+            }
+            if (written > 0) {
+                len += written;
             }
 
             p += 3;
         } else {
-            if (remaining > 1) {
-                *out++ = *p;
-                remaining--;
+            if (len + 1 < size) {
+                out[len] = *p;
             }
+            len++;
             p++;
         }
     }
-    *out = '\0';
+    if (size > 0) {
+        out[len < size ? len : size - 1] = '\0';
+    }
+
+    return len;
+}
+
+/* Measures the result first, so arguments of any length fit. */
+char* format_internal(const char* fmt, va_list args) {
+    if (!fmt) return NULL;
+
+    va_list measure;
+    va_copy(measure, args);
+    size_t len = format_into(NULL, 0, fmt, measure);
+    va_end(measure);
+
+    char* result = (char*)malloc(len + 1);
+    if (!result) return NULL;
+
+    format_into(result, len + 1, fmt, args);
 
     return result;
 }
@@ -80,7 +79,7 @@ char* format(const char* fmt, ...) {
 
     va_end(args);
 
-    return result;
+    return area_register_alloc(result);
 }
 
 void print(const char* fmt, ...) {
@@ -128,7 +127,7 @@ void input(const char* fmt, ...) {
                 if (str_ptr) {
                     char buffer[256];
                     if (scanf("%255s", buffer) == 1) {
-                        *str_ptr = (char*)malloc(strlen(buffer) + 1);
+                        *str_ptr = (char*)area_alloc(strlen(buffer) + 1);
                         if (*str_ptr) {
                             strcpy(*str_ptr, buffer);
                         }
@@ -148,4 +147,9 @@ void input(const char* fmt, ...) {
     }
 
     va_end(args);
+}
+
+void runtime_error(const char* location, const char* message) {
+    fprintf(stderr, "%s: %s\n", location, message);
+    exit(1);
 }
\ No newline at end of file
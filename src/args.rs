@@ -1,4 +1,4 @@
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 
 #[derive(Parser)]
 #[command(version = "0.1", about = "Lamp lang compiler", long_about = None)]
@@ -14,8 +14,35 @@ pub enum Commands {
         #[arg(short, long)]
         output: String,
         #[arg(short, long, default_value_t=false)]
-        compile: bool
+        compile: bool,
+        #[arg(long, value_enum, default_value_t=Backend::C)]
+        backend: Backend,
+        /// Where the program runs; `wasm` writes a WebAssembly text module instead of using a backend.
+        #[arg(long, value_enum, default_value_t=Target::Native)]
+        target: Target,
+        /// Stop after this many errors; 0 reports every error.
+        #[arg(long, default_value_t=lamplang::DEFAULT_MAX_ERRORS)]
+        max_errors: usize
     },
+    /// Runs a `.lamp` program, or a `.lampc` file compiled with `--backend bytecode`.
+    Run {
+        input: String,
+        /// Compile to bytecode and run it on the VM instead of the interpreter.
+        #[arg(long, default_value_t=false)]
+        vm: bool,
+        /// Stop after this many errors; 0 reports every error.
+        #[arg(long, default_value_t=lamplang::DEFAULT_MAX_ERRORS)]
+        max_errors: usize
+    },
+    /// Prints the bytecode of a `.lamp` program or a `.lampc` file.
+    Disasm {
+        input: String,
+        /// Stop after this many errors; 0 reports every error.
+        #[arg(long, default_value_t=lamplang::DEFAULT_MAX_ERRORS)]
+        max_errors: usize
+    },
+    /// Starts an interactive session that runs each input as it is entered.
+    Repl,
     Init {
         #[arg(short, long)]
         name: Option<String>,
@@ -24,5 +51,22 @@ pub enum Commands {
     }
 }
 
+/// What `compile` writes to the output file.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum Backend {
+    /// C source, built against the runtime in `lib`.
+    C,
+    /// A `.lampc` file of bytecode for `run`.
+    Bytecode,
+    /// Textual LLVM IR, for `llc` or `clang` to build against the runtime in `lib`.
+    Llvm,
+}
 
-
+/// What `compile` generates code for.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum Target {
+    /// The machine the output of the backend is built on.
+    Native,
+    /// A WebAssembly text (`.wat`) module for browsers and other WebAssembly hosts.
+    Wasm,
+}
\ No newline at end of file
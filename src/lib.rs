@@ -1,24 +1,154 @@
-use crate::translator::{ast::Stmt, lexer::{Token, Lexer}, parser::Parser, codegen::CCodeGenerator};
+use std::io::{BufRead, Write};
+
+use crate::translator::{lexer::{Token, Lexer}, parser::Parser, resolver::Resolver, checker::TypeChecker, codegen::CCodeGenerator, diagnostic};
+use crate::translator::interpreter::Interpreter;
+use crate::translator::{bytecode::Program, compiler::BytecodeCompiler, vm::Vm};
+use crate::translator::llvm::LlvmGenerator;
+use crate::translator::wasm::WasmGenerator;
+use crate::translator::symbols::SymbolTable;
+
+pub use crate::translator::bytecode;
+pub use crate::translator::ast::{Expr, ExprKind, Stmt, StmtKind, TypedArgument};
+pub use crate::translator::error::{CompileError, ErrorKind, Span};
+pub use crate::translator::source_map::{Location, SourceMap};
+pub use crate::translator::value::{IntType, Value, ValueType};
 
 mod translator;
+pub mod repl;
 
-pub fn lex(input: &str) -> Vec<Token> {
+pub fn lex(input: &str) -> Result<Vec<Token>, CompileError> {
     let mut lexer = Lexer::new(input.to_string());
-    lexer.lex();
-    lexer.tokens
+    lexer.lex()?;
+    Ok(lexer.tokens)
+}
+
+pub const DEFAULT_MAX_ERRORS: usize = 20;
+
+pub fn parse_tokens(tokens: &[Token]) -> Result<Stmt, Vec<CompileError>> {
+    parse_tokens_with_limit(tokens, DEFAULT_MAX_ERRORS)
 }
 
-pub fn parse_tokens(tokens: &Vec<Token>) -> Stmt {
-    let mut parser = Parser::new(tokens.clone());
+pub fn parse_tokens_with_limit(tokens: &[Token], max_errors: usize) -> Result<Stmt, Vec<CompileError>> {
+    let mut parser = Parser::new(tokens.to_vec()).with_max_errors(max_errors);
     parser.parse()
 }
 
-pub fn generate_c_code(stmt: Stmt) -> String {
-    let mut generator = CCodeGenerator::new(stmt);
-    let c_code = generator.generate();
-    c_code
+/// Resolves names, annotating the AST with symbol ids, and type checks the program.
+pub fn check(stmt: &mut Stmt) -> Result<SymbolTable, Vec<CompileError>> {
+    let (symbols, mut errors) = Resolver::new().resolve(stmt);
+    if let Err(type_errors) = TypeChecker::new(&symbols).check(stmt) {
+        errors.extend(type_errors);
+    }
+    if !errors.is_empty() {
+        errors.sort_by_key(|err| err.span.start);
+        return Err(errors);
+    }
+    Ok(symbols)
+}
+
+/// Generates C for a checked program. `file_name` and `source` are used for the source locations
+/// reported by runtime checks, such as array bounds checks.
+pub fn generate_c_code(stmt: Stmt, symbols: SymbolTable, file_name: &str, source: &str) -> Result<String, CompileError> {
+    let mut generator = CCodeGenerator::new(stmt, symbols).with_source(file_name, source);
+    generator.generate()
+}
+
+pub fn translate(input: &str) -> Result<String, Vec<CompileError>> {
+    translate_with_limit(input, DEFAULT_MAX_ERRORS)
+}
+
+pub fn translate_with_limit(input: &str, max_errors: usize) -> Result<String, Vec<CompileError>> {
+    translate_file(input, "<input>", max_errors)
+}
+
+/// Translates the source of the file `file_name`, which runtime errors of the program refer to.
+pub fn translate_file(input: &str, file_name: &str, max_errors: usize) -> Result<String, Vec<CompileError>> {
+    let (stmt, symbols) = parse_and_check(input, max_errors)?;
+    generate_c_code(stmt, symbols, file_name, input).map_err(|err| vec![err])
+}
+
+/// Generates textual LLVM IR for a checked program, like `generate_c_code`.
+pub fn generate_llvm_ir(stmt: Stmt, symbols: SymbolTable, file_name: &str, source: &str) -> Result<String, CompileError> {
+    let mut generator = LlvmGenerator::new(stmt, symbols).with_source(file_name, source);
+    generator.generate()
+}
+
+/// Like `translate_file`, but generates LLVM IR, which `llc` or `clang` build against the runtime.
+pub fn translate_file_to_llvm(input: &str, file_name: &str, max_errors: usize) -> Result<String, Vec<CompileError>> {
+    let (stmt, symbols) = parse_and_check(input, max_errors)?;
+    generate_llvm_ir(stmt, symbols, file_name, input).map_err(|err| vec![err])
+}
+
+/// Generates a WebAssembly text module for a checked program.
+pub fn generate_wat(stmt: Stmt, symbols: SymbolTable) -> Result<String, CompileError> {
+    WasmGenerator::new(stmt, symbols).generate()
+}
+
+/// Translates a program to a WebAssembly text module, which hosts run with `print` and `println`
+/// imported from the module `lamp`.
+pub fn translate_to_wat(input: &str, max_errors: usize) -> Result<String, Vec<CompileError>> {
+    let (stmt, symbols) = parse_and_check(input, max_errors)?;
+    generate_wat(stmt, symbols).map_err(|err| vec![err])
+}
+
+/// Runs a checked program with the interpreter, which reads `input` and prints to `output`.
+pub fn interpret(stmt: &Stmt, input: &mut dyn BufRead, output: &mut dyn Write) -> Result<(), CompileError> {
+    Interpreter::new(input, output).run(stmt)
+}
+
+/// The interpreter recurses for every call, so programs run on a thread with a stack this large.
+pub const RUN_STACK_SIZE: usize = 256 * 1024 * 1024;
+
+/// Runs a program with the interpreter, feeding it `stdin`, and returns what it printed.
+pub fn run(input: &str, stdin: &str) -> Result<String, Vec<CompileError>> {
+    std::thread::scope(|scope| {
+        let program = std::thread::Builder::new().stack_size(RUN_STACK_SIZE).spawn_scoped(scope, || {
+            let mut output = vec![];
+            run_with_io(input, DEFAULT_MAX_ERRORS, &mut stdin.as_bytes(), &mut output)?;
+            Ok(String::from_utf8_lossy(&output).into_owned())
+        });
+        program.expect("cannot start the interpreter thread").join().expect("the interpreter panicked")
+    })
+}
+
+/// Checks and runs a program. Returns the compile errors, or the runtime error that stopped it.
+/// Deeply recursive programs need a stack of `RUN_STACK_SIZE`.
+pub fn run_with_io(input: &str, max_errors: usize, stdin: &mut dyn BufRead, stdout: &mut dyn Write) -> Result<(), Vec<CompileError>> {
+    let (stmt, _) = parse_and_check(input, max_errors)?;
+    interpret(&stmt, stdin, stdout).map_err(|err| vec![err])
+}
+
+/// Compiles a program to bytecode for the VM.
+pub fn compile_bytecode(input: &str, max_errors: usize) -> Result<Program, Vec<CompileError>> {
+    let (stmt, _) = parse_and_check(input, max_errors)?;
+    BytecodeCompiler::new().compile(&stmt).map_err(|err| vec![err])
+}
+
+/// Runs bytecode on the VM, which reads `input` and prints to `output`.
+pub fn run_bytecode(program: &Program, input: &mut dyn BufRead, output: &mut dyn Write) -> Result<(), CompileError> {
+    Vm::new(program, input, output).run()
+}
+
+/// Like `run`, but compiles the program to bytecode and runs it on the VM.
+pub fn run_vm(input: &str, stdin: &str) -> Result<String, Vec<CompileError>> {
+    let program = compile_bytecode(input, DEFAULT_MAX_ERRORS)?;
+    let mut output = vec![];
+    run_bytecode(&program, &mut stdin.as_bytes(), &mut output).map_err(|err| vec![err])?;
+    Ok(String::from_utf8_lossy(&output).into_owned())
+}
+
+fn parse_and_check(input: &str, max_errors: usize) -> Result<(Stmt, SymbolTable), Vec<CompileError>> {
+    let tokens = lex(input).map_err(|err| vec![err])?;
+    let mut stmt = parse_tokens_with_limit(&tokens, max_errors)?;
+    let symbols = check(&mut stmt).map_err(|mut errors| {
+        if max_errors > 0 {
+            errors.truncate(max_errors);
+        }
+        errors
+    })?;
+    Ok((stmt, symbols))
 }
 
-pub fn translate(input: &str) -> String {
-    generate_c_code(parse_tokens(&lex(input)))
+pub fn render_error(error: &CompileError, file_name: &str, source: &str) -> String {
+    diagnostic::render(error, file_name, &SourceMap::new(source))
 }
\ No newline at end of file
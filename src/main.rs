@@ -1,22 +1,65 @@
-use std::{fs, process::{Command}};
+use std::{fs, io::{self, BufRead, Write}, process::{self, Command}, thread};
 
-use clap::Parser;
-use args::Commands;
+use clap::{error::ErrorKind, CommandFactory, Parser, ValueEnum};
+use args::{Backend, Commands, Target};
 
 mod args;
 
 const BOM: &str = "\u{FEFF}";
+/// The extension of compiled bytecode files.
+const LAMPC: &str = ".lampc";
 
 fn main() {
     let cli = args::Cli::parse();
 
     match cli.command.unwrap() {
-        Commands::Compile { input, output, compile } => {
-            let mut code = fs::read_to_string(&input).unwrap();
-            if code.starts_with(BOM) {
-                code = code.trim_start_matches(BOM).to_string();
-            }
-            let translated_c = lamplang::translate(&code);
+        // The wasm target generates its module itself, so only the default backend goes with it.
+        Commands::Compile { target: Target::Wasm, backend, .. } if backend != Backend::C => {
+            let backend = backend.to_possible_value().expect("every backend has a name");
+            let mut cli = args::Cli::command();
+            cli.build();
+            cli.find_subcommand_mut("compile").expect("`compile` is a subcommand")
+                .error(ErrorKind::ArgumentConflict, format!("`--target wasm` cannot be used with `--backend {}`", backend.get_name()))
+                .exit();
+        }
+        Commands::Compile { input, output, backend: Backend::Bytecode, max_errors, .. } => {
+            let program = compile_bytecode(&input, max_errors);
+            fs::write(&output, program.to_bytes()).expect("Cannot write to output file");
+            println!("Your code was successful compiled to bytecode!");
+        }
+        Commands::Compile { input, output, target: Target::Wasm, max_errors, .. } => {
+            let code = read_source(&input);
+            let wat = match lamplang::translate_to_wat(&code, error_limit(max_errors)) {
+                Ok(wat) => wat,
+                Err(errors) => {
+                    report_errors(&errors, &input, &code, max_errors);
+                    process::exit(1);
+                }
+            };
+            fs::write(&output, wat).expect("Cannot write to output file");
+            println!("Your code was successful translated to WebAssembly!");
+        }
+        Commands::Compile { input, output, backend: Backend::Llvm, max_errors, .. } => {
+            let code = read_source(&input);
+            let ir = match lamplang::translate_file_to_llvm(&code, &input, error_limit(max_errors)) {
+                Ok(ir) => ir,
+                Err(errors) => {
+                    report_errors(&errors, &input, &code, max_errors);
+                    process::exit(1);
+                }
+            };
+            fs::write(&output, ir).expect("Cannot write to output file");
+            println!("Your code was successful translated to LLVM IR!");
+        }
+        Commands::Compile { input, output, compile, max_errors, .. } => {
+            let code = read_source(&input);
+            let translated_c = match lamplang::translate_file(&code, &input, error_limit(max_errors)) {
+                Ok(translated_c) => translated_c,
+                Err(errors) => {
+                    report_errors(&errors, &input, &code, max_errors);
+                    process::exit(1);
+                }
+            };
             fs::write(&output, translated_c).expect("Cannot write to output file");
             println!("Your code was successful translated!");
             if compile {
@@ -45,10 +88,143 @@ fn main() {
                 }
             }
         }
+        Commands::Run { input, .. } if input.ends_with(LAMPC) => {
+            let program = read_bytecode(&input);
+            // The source is not shipped with the bytecode, so errors have no snippet.
+            if let Err(err) = lamplang::run_bytecode(&program, &mut io::stdin().lock(), &mut io::stdout().lock()) {
+                eprintln!("{}", err);
+                process::exit(1);
+            }
+        }
+        Commands::Run { input, vm: true, max_errors } => {
+            let code = read_source(&input);
+            let program = compile_bytecode(&input, max_errors);
+            if let Err(err) = lamplang::run_bytecode(&program, &mut io::stdin().lock(), &mut io::stdout().lock()) {
+                report_errors(&[err], &input, &code, max_errors);
+                process::exit(1);
+            }
+        }
+        Commands::Run { input, max_errors, .. } => {
+            let code = read_source(&input);
+            let program = thread::Builder::new().stack_size(lamplang::RUN_STACK_SIZE).spawn({
+                let code = code.clone();
+                move || lamplang::run_with_io(&code, error_limit(max_errors), &mut io::stdin().lock(), &mut io::stdout().lock())
+            });
+            let result = program.expect("Cannot start the interpreter").join().expect("The interpreter panicked");
+            if let Err(errors) = result {
+                report_errors(&errors, &input, &code, max_errors);
+                process::exit(1);
+            }
+        }
+        Commands::Disasm { input, max_errors } => {
+            let program = if input.ends_with(LAMPC) { read_bytecode(&input) } else { compile_bytecode(&input, max_errors) };
+            print!("{}", lamplang::bytecode::disassemble(&program));
+        }
+        Commands::Repl => {
+            let session = thread::Builder::new().stack_size(lamplang::RUN_STACK_SIZE).spawn(repl);
+            session.expect("Cannot start the interpreter").join().expect("The interpreter panicked");
+        }
         Commands::Init { .. } => (),
     }
 }
 
+/// Reads inputs until the end of stdin. An input goes on over more lines while it has unclosed
+/// braces, and lines starting with `:type`, `:ast` or `:c` show what an input compiles to.
+fn repl() {
+    let mut session = lamplang::repl::Session::new();
+    let mut stdin = io::stdin().lock();
+    let mut input = String::new();
+    loop {
+        print!("{}", if input.is_empty() { "> " } else { "... " });
+        io::stdout().flush().expect("Cannot write to stdout");
+        let mut line = String::new();
+        if stdin.read_line(&mut line).expect("Cannot read from stdin") == 0 {
+            println!();
+            return;
+        }
+        input += &line;
+        if !lamplang::repl::is_complete(&input) {
+            continue;
+        }
+        let text = std::mem::take(&mut input);
+        let text = text.trim();
+        let (command, source) = match text.split_once(char::is_whitespace) {
+            _ if !text.starts_with(':') => ("", text),
+            Some((command, rest)) => (command, rest.trim()),
+            None => (text, ""),
+        };
+        let result = match command {
+            "" if source.is_empty() => continue,
+            "" => session.eval(source, &mut stdin, &mut io::stdout()),
+            ":type" => session.type_of(source).map(|typ| Some(typ.to_string())),
+            ":ast" => session.ast(source).map(Some),
+            ":c" => session.c_code(source).map(Some),
+            _ => {
+                eprintln!("unknown command `{}`; the commands are `:type`, `:ast` and `:c`", command);
+                continue;
+            }
+        };
+        match result {
+            Ok(Some(output)) => println!("{}", output),
+            Ok(None) => {}
+            Err(errors) => {
+                for err in errors {
+                    eprintln!("{}", session.render_error(&err));
+                }
+            }
+        }
+    }
+}
+
+fn read_source(input: &str) -> String {
+    let code = fs::read_to_string(input).unwrap();
+    code.strip_prefix(BOM).map(str::to_string).unwrap_or(code)
+}
+
+/// Compiles a source file to bytecode, exiting with its errors if it does not compile.
+fn compile_bytecode(input: &str, max_errors: usize) -> lamplang::bytecode::Program {
+    let code = read_source(input);
+    lamplang::compile_bytecode(&code, error_limit(max_errors)).unwrap_or_else(|errors| {
+        report_errors(&errors, input, &code, max_errors);
+        process::exit(1);
+    })
+}
+
+fn read_bytecode(input: &str) -> lamplang::bytecode::Program {
+    let bytes = fs::read(input).unwrap();
+    lamplang::bytecode::Program::from_bytes(&bytes).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        process::exit(1);
+    })
+}
+
+/// The limit on errors passed to the compiler, one more than `--max-errors`, so that
+/// `report_errors` can tell whether errors were left out. 0 means no limit.
+fn error_limit(max_errors: usize) -> usize {
+    if max_errors == 0 { 0 } else { max_errors.saturating_add(1) }
+}
+
+fn report_errors(errors: &[lamplang::CompileError], input: &str, code: &str, max_errors: usize) {
+    eprint!("{}", error_report(errors, input, code, max_errors));
+}
+
+/// Renders up to `max_errors` errors, or all of them if it is 0, followed by a summary.
+fn error_report(errors: &[lamplang::CompileError], input: &str, code: &str, max_errors: usize) -> String {
+    let shown = if max_errors == 0 { errors.len() } else { errors.len().min(max_errors) };
+    let mut report = String::new();
+    for err in &errors[..shown] {
+        report += &format!("{}\n", lamplang::render_error(err, input, code));
+    }
+    // A runtime error stops a program that compiled.
+    if errors.iter().any(|err| err.kind == lamplang::ErrorKind::Runtime) {
+        return report;
+    }
+    if shown < errors.len() {
+        report += &format!("stopped after {} errors (raise the limit with --max-errors)\n", shown);
+    }
+    report + &format!("could not compile `{}` due to {} error(s)\n", input, shown)
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -60,7 +236,1324 @@ mod tests {
         var name: string = \"something\"\n
         print(\"{s}\", name)\n
         ".to_string();
-        let c_code = lamplang::translate(input.as_ref());
+        let c_code = lamplang::translate(input.as_ref()).unwrap();
         print!("{}", c_code);
     }
+
+    #[test]
+    fn test_errors_instead_of_panics() {
+        let err = &lamplang::translate("var a: number = 2 @ 3").unwrap_err()[0];
+        assert_eq!(err.kind, lamplang::ErrorKind::Lexical);
+        assert_eq!((err.span.start, err.span.end), (18, 19));
+
+        let err = &lamplang::translate("print(\"unterminated)").unwrap_err()[0];
+        assert_eq!(err.kind, lamplang::ErrorKind::Lexical);
+
+        let err = &lamplang::translate("var a: number 2").unwrap_err()[0];
+        assert_eq!(err.kind, lamplang::ErrorKind::Syntax);
+        assert_eq!(err.message, "expected `=`, found number");
+    }
+
+    #[test]
+    fn test_render_error_with_caret() {
+        let source = "use io\nvar a: number 2\n";
+        let err = &lamplang::translate(source).unwrap_err()[0];
+        let rendered = lamplang::render_error(err, "main.lamp", source);
+        assert_eq!(rendered, "\
+syntax error: expected `=`, found number
+ --> main.lamp:2:15
+  |
+2 | var a: number 2
+  |               ^
+");
+
+        let source = "var name: text = \"ab\"";
+        let err = &lamplang::translate(source).unwrap_err()[0];
+        let rendered = lamplang::render_error(err, "main.lamp", source);
+        assert!(rendered.contains("1 | var name: text = \"ab\"\n  |           ^^^^\n"));
+        assert!(rendered.ends_with("  = help: expected one of `number`, `int`, `i8`, `i16`, `i32`, `i64`, `u8`, `u16`, `u32`, `u64`, `string`, `bool`, `unit`\n"));
+    }
+
+    #[test]
+    fn test_parser_reports_every_error() {
+        let source = "\
+var a: number 1
+if a > 0 {
+    var b: number = )
+    print(\"{f}\", b)
+}
+}
+var c: number = 3
+while c > {
+    c = c - 1
+}
+func f(x: number): number {
+    return x +
+}
+";
+        let errors = lamplang::translate(source).unwrap_err();
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages, vec![
+            "expected `=`, found number",
+            "expected expression, found `)`",
+            "expected statement, found `}`",
+            "expected expression, found `{`",
+            "expected expression, found `}`",
+        ]);
+
+        let errors = lamplang::translate_with_limit(source, 2).unwrap_err();
+        assert_eq!(errors.len(), 2);
+
+        // A limit of 0 reports every error, and the note about the limit only appears when errors
+        // were left out.
+        let errors = lamplang::translate_with_limit(source, crate::error_limit(0)).unwrap_err();
+        assert_eq!(errors.len(), 5);
+        let report = crate::error_report(&errors, "test.lamp", source, 0);
+        assert!(!report.contains("stopped after") && report.ends_with("\ncould not compile `test.lamp` due to 5 error(s)\n"));
+        let errors = lamplang::translate_with_limit(source, crate::error_limit(2)).unwrap_err();
+        assert!(crate::error_report(&errors, "test.lamp", source, 2)
+            .ends_with("\nstopped after 2 errors (raise the limit with --max-errors)\ncould not compile `test.lamp` due to 2 error(s)\n"));
+        let errors = lamplang::translate_with_limit(source, crate::error_limit(5)).unwrap_err();
+        let report = crate::error_report(&errors, "test.lamp", source, 5);
+        assert!(!report.contains("stopped after") && report.ends_with("\ncould not compile `test.lamp` due to 5 error(s)\n"));
+
+        let errors = lamplang::translate("if 1 > 0 {\nvar a: number = 1\n").unwrap_err();
+        assert_eq!(errors[0].message, "unclosed block");
+        assert_eq!(errors[0].span.start, 9);
+    }
+
+    #[test]
+    fn test_type_checker() {
+        let source = "\
+use io
+var a: number = \"text\"
+b = 2
+var c: number = 1 + \"x\"
+func add(x: number, y: number): number {
+    return x + y
+}
+var d: number = add(1)
+var e: string = add(1, \"2\")
+func sign(x: number): number {
+    if x > 0 {
+        return 1
+    }
+}
+print(\"{f}\", a)
+sqrt(4)
+";
+        let errors = lamplang::translate(source).unwrap_err();
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages, vec![
+            "mismatched types: expected `number`, found `string`",
+            "cannot assign to undefined variable `b`",
+            "cannot apply `+` to a value of type `string`",
+            "function `add` takes 2 argument(s) but 1 were supplied",
+            "mismatched types: expected `string`, found `number`",
+            "mismatched types: expected `number`, found `string`",
+            "function `sign` does not return a value on every path",
+            "cannot find function `sqrt`",
+        ]);
+        assert_eq!(errors[7].help.as_deref(), Some("add `use math` to import it"));
+
+        let source = "\
+var total: number = 0
+func f(): number {
+    return total
+}
+";
+        let errors = lamplang::translate(source).unwrap_err();
+        assert_eq!(errors[0].message, "cannot find variable `total` in this scope");
+
+        let source = "\
+func count(): int {
+    return
+}
+func done(): unit {
+    return
+}
+";
+        let errors = lamplang::translate(source).unwrap_err();
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages, vec!["`return` without a value in a function returning `int`"]);
+
+        // `while true` never ends unless a `break` leaves it, even from an inner loop.
+        let source = "\
+func spin(): int {
+    while true {
+    }
+}
+func leave(): int {
+    while true {
+        break
+    }
+}
+func leave_outer(): int {
+    outer: while true {
+        for i in 0..3 {
+            break outer
+        }
+    }
+}
+func leave_inner(): int {
+    outer: while true {
+        while true {
+            break
+        }
+    }
+}
+";
+        let errors = lamplang::translate(source).unwrap_err();
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages, vec![
+            "function `leave` does not return a value on every path",
+            "function `leave_outer` does not return a value on every path",
+        ]);
+    }
+
+    #[test]
+    fn test_resolver_scopes_and_shadowing() {
+        let source = "\
+use io
+struct Point { x: number }
+var x: number = 1
+var x: number = 2
+if x > 0 {
+    var x: number = x + 1
+    var y: number = x
+}
+y = 3
+var print: number = 0
+func twice(x: number): number {
+    var x: number = 2
+    return x * 2
+}
+func twice(a: number): number {
+    return a
+}
+var p: number = Point(1)
+";
+        let errors = lamplang::translate(source).unwrap_err();
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages, vec![
+            "variable `x` is already defined in this scope",
+            "cannot assign to undefined variable `y`",
+            "`print` is already defined as a function or struct",
+            "variable `x` is already defined in this scope",
+            "`twice` is already defined",
+            "`Point` is a struct, not a function",
+        ]);
+
+        let source = "\
+var x: number = 1
+if x > 0 {
+    var x: number = x + 1
+    x = x * 2
+}
+x = x + 1
+";
+        let c_code = lamplang::translate(source).unwrap();
+        assert!(c_code.contains("double lamp__x = 1.0;if (lamp__x>0.0) {area_start();double lamp_v1_x = lamp__x+1.0;lamp_v1_x = lamp_v1_x*2.0;area_end();}lamp__x = lamp__x+1.0;"));
+    }
+
+    #[test]
+    fn test_operator_precedence() {
+        let cases = [
+            ("number", "1 + 2 * 3", "1.0+2.0*3.0"),
+            ("number", "1 * 2 + 3", "1.0*2.0+3.0"),
+            ("number", "(1 + 2) * 3", "(1.0+2.0)*3.0"),
+            ("number", "6 / (2 * 3)", "6.0/(2.0*3.0)"),
+            ("number", "1 - 2 - 3", "1.0-2.0-3.0"),
+            ("number", "1 - (2 - 3)", "1.0-(2.0-3.0)"),
+            ("number", "8 / 4 / 2", "8.0/4.0/2.0"),
+            ("number", "8 / (4 / 2)", "8.0/(4.0/2.0)"),
+            ("number", "-2 * 3", "-(2.0)*3.0"),
+            ("number", "-(2 * 3)", "-(2.0*3.0)"),
+            ("number", "2 - -3", "2.0-(-(3.0))"),
+            ("bool", "1.5 + 2 > 3 * 4", "1.5+2.0>3.0*4.0"),
+            // Integer arithmetic is done in an unsigned type, so it wraps instead of overflowing.
+            ("int", "1 + 2 * 3", "((int32_t)((uint32_t)1+(uint32_t)((int32_t)((uint32_t)2*(uint32_t)3))))"),
+            ("int", "8 / 4 / 2", "((int32_t)(((int32_t)(8/4))/2))"),
+            ("bool", "(1 < 2) == (3 >= 4)", "1<2==3>=4"),
+            ("bool", "true == (2 != 3)", "true==(2!=3)"),
+            ("bool", "1 == 2 && 3 != 4", "1==2&&3!=4"),
+            ("bool", "!true && false", "!(true)&&false"),
+            ("bool", "!(true && false)", "!(true&&false)"),
+            ("bool", "true || false && true", "true||false&&true"),
+            ("bool", "(true || false) && true", "(true||false)&&true"),
+            ("bool", "true && false || true && true", "true&&false||true&&true"),
+        ];
+        for (typ, expr, c_expr) in cases {
+            let c_code = lamplang::translate(&format!("var a: {} = {}", typ, expr)).unwrap();
+            assert!(c_code.contains(&format!("a = {};", c_expr)), "{} => {}", expr, c_code);
+        }
+    }
+
+    #[test]
+    fn test_bool_type() {
+        let source = "\
+var done: bool = false
+var n: number = 3
+while !done && n > 0 {
+    n = n - 1
+    done = n == 1
+}
+";
+        let c_code = lamplang::translate(source).unwrap();
+        assert!(c_code.starts_with("#include <stdbool.h>\n"));
+        assert!(c_code.contains("bool lamp__done = false;double lamp__n = 3.0;while (!(lamp__done)&&lamp__n>0.0) {area_start();lamp__n = lamp__n-1.0;lamp__done = lamp__n==1.0;area_end();}"));
+
+        let source = "\
+var n: number = 1
+if n {
+    n = 2
+}
+var b: bool = !n
+var c: number = true + 1
+var d: number = 1 < 2
+";
+        let errors = lamplang::translate(source).unwrap_err();
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages, vec![
+            "condition must be `bool`, found `number`",
+            "cannot apply `!` to a value of type `number`",
+            "cannot apply `+` to a value of type `bool`",
+            "mismatched types: expected `number`, found `bool`",
+        ]);
+    }
+
+    #[test]
+    fn test_integer_types() {
+        let source = "\
+var a: int = 7
+var b: int = a / 2 % 3
+var small: u8 = 255
+var big: i64 = 9000000000
+var neg: i8 = -128
+var ratio: number = number(a) / 2
+var back: u32 = u32(ratio)
+var c: int = a / b - -a
+";
+        let c_code = lamplang::translate(source).unwrap();
+        assert!(c_code.contains("#include \"array.h\"\n#include \"integer.h\"\n"));
+        assert!(c_code.contains("int32_t lamp__a = 7;int32_t lamp__b = ((int32_t)(((int32_t)(lamp__a/2))%3));uint8_t lamp__small = 255;int64_t lamp__big = 9000000000LL;int8_t lamp__neg = -(128);"));
+        assert!(c_code.contains("double lamp__ratio = ((double)(lamp__a))/2.0;uint32_t lamp__back = ((uint32_t)integer_from_number(lamp__ratio));"));
+        assert!(c_code.contains("int32_t lamp__c = ((int32_t)((uint32_t)((int32_t)integer_div(lamp__a, lamp__b, \"<input>:8:14\"))-(uint32_t)((int32_t)(0-(uint32_t)lamp__a))));"));
+
+        let source = "\
+var a: int = 1
+var b: number = 2
+var c: number = a + b
+var d: u8 = 256
+var e: number = b % 2
+var f: u8 = 1
+var g: u8 = -f
+var h: int = int(\"3\")
+var i: number = a
+";
+        let errors = lamplang::translate(source).unwrap_err();
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages, vec![
+            "cannot apply `+` to `int` and `number`",
+            "literal out of range for `u8`",
+            "cannot apply `%` to a value of type `number`",
+            "cannot apply `-` to a value of type `u8`",
+            "cannot convert a value of type `string` to `int`",
+            "mismatched types: expected `number`, found `int`",
+        ]);
+        assert_eq!(errors[0].help.as_deref(), Some("convert one side explicitly, e.g. `int(...)`"));
+    }
+
+    #[test]
+    fn test_numeric_literals() {
+        let source = "\
+var a: number = 3.14
+var b: number = 1e-3 + 2.5E10
+var c: int = 1_000_000
+var d: i64 = 0xFFFF_FFFF_FF
+var e: u8 = 0b1010
+var f: int = 0o17
+var g: number = 0.1
+";
+        let c_code = lamplang::translate(source).unwrap();
+        assert!(c_code.contains("double lamp__a = 3.14;double lamp__b = 0.001+25000000000.0;int32_t lamp__c = 1000000;int64_t lamp__d = 1099511627775LL;uint8_t lamp__e = 10;int32_t lamp__f = 15;double lamp__g = 0.1;"));
+
+        let errors = lamplang::translate("var a: number = 0b102").unwrap_err();
+        assert_eq!(errors[0].message, "invalid digit `2` in binary literal");
+        let errors = lamplang::translate("var a: number = 0x").unwrap_err();
+        assert_eq!(errors[0].message, "hex literal has no digits");
+        let errors = lamplang::translate("var a: number = 1e999").unwrap_err();
+        assert_eq!(errors[0].message, "float literal is out of range");
+        let errors = lamplang::translate("var a: int = 2.5").unwrap_err();
+        assert_eq!(errors[0].message, "mismatched types: expected `int`, found `number`");
+
+        // Literals up to `u64::MAX` lex, and the checker decides whether they fit their type.
+        let c_code = lamplang::translate("var a: u64 = 18446744073709551615\nvar b: u64 = 0xFFFF_FFFF_FFFF_FFFF\nvar c: i64 = -9223372036854775808").unwrap();
+        assert!(c_code.contains("uint64_t lamp__a = 18446744073709551615ULL;uint64_t lamp__b = 18446744073709551615ULL;int64_t lamp__c = ((int64_t)(0-(uint64_t)9223372036854775808ULL));"));
+        let errors = lamplang::translate("var a: i64 = 9223372036854775808\nvar b: int = 0xFFFF_FFFF_FFFF_FFFF").unwrap_err();
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages, vec!["literal out of range for `i64`", "literal out of range for `int`"]);
+        let errors = lamplang::translate("var a: u64 = 18446744073709551616").unwrap_err();
+        assert_eq!(errors[0].message, "number literal is too large");
+    }
+
+    #[test]
+    fn test_comments() {
+        let source = "\
+// a line comment
+var a: int = 1 // trailing
+/* a block /* nested */ comment */
+var b: int = a /* inline */ + 2
+/// Adds two numbers.
+///
+/// Used by `main`.
+func add(x: int, y: int): int {
+    return x + y
+}
+//// not a doc comment
+struct Point { x: number }
+/// Dropped: only definitions keep doc comments.
+var c: int = 3
+";
+        let c_code = lamplang::translate(source).unwrap();
+        assert!(c_code.contains("int32_t lamp__a = 1;int32_t lamp__b = ((int32_t)((uint32_t)lamp__a+(uint32_t)2));"));
+
+        let tokens = lamplang::lex(source).unwrap();
+        let program = lamplang::parse_tokens(&tokens).unwrap();
+        let lamplang::StmtKind::Block(stmts) = program.kind else { panic!("program is a block") };
+        let docs: Vec<Option<&str>> = stmts.iter().map(|stmt| stmt.doc.as_deref()).collect();
+        assert_eq!(docs, vec![None, None, Some("Adds two numbers.\n\nUsed by `main`."), None, None]);
+
+        let err = &lamplang::translate("var a: int = 1 /* open /* nested */").unwrap_err()[0];
+        assert_eq!(err.message, "unterminated block comment");
+        assert_eq!((err.span.start, err.span.end), (15, 17));
+    }
+
+    #[test]
+    fn test_string_escapes() {
+        let source = r#"
+use io
+var s: string = "tab\there \"quoted\" back\\slash\n"
+var smile: string = "caf\u{e9} \u{1F600}"
+"#;
+        let c_code = lamplang::translate(source).unwrap();
+        assert!(c_code.contains(r#"char* lamp__s = area_memdup("tab\there \"quoted\" back\\slash\n", 30);"#));
+        assert!(c_code.contains(r#"char* lamp__smile = area_memdup("caf\303\251 \360\237\230\200", 11);"#));
+
+        let err = &lamplang::translate(r#"var s: string = "a\qb""#).unwrap_err()[0];
+        assert_eq!(err.message, "unknown escape sequence `\\q`");
+        assert_eq!((err.span.start, err.span.end), (18, 20));
+        let err = &lamplang::translate(r#"var s: string = "\u{D800}""#).unwrap_err()[0];
+        assert_eq!(err.message, "`D800` is not a valid unicode character");
+        let err = &lamplang::translate(r#"var s: string = "\u41""#).unwrap_err()[0];
+        assert_eq!(err.message, "invalid unicode escape");
+    }
+
+    #[test]
+    fn test_string_interpolation() {
+        let source = r#"
+var name: string = "Lamp"
+var age: int = 3
+var ratio: number = 0.5
+var msg: string = "Hello ${name}, you are ${age + 1} ${ratio} ${age > 2} {literal} \${x}"
+"#;
+        let c_code = lamplang::translate(source).unwrap();
+        assert!(c_code.contains("#include \"area.h\"\n#include \"io.h\"\n"));
+        assert!(c_code.contains(r#"char* lamp__msg = format("Hello {s}, you are {i} {f} {s}{s}", lamp__name, (long long)(((int32_t)((uint32_t)lamp__age+(uint32_t)1))), lamp__ratio, (lamp__age>2) ? "true" : "false", " {literal} ${x}");"#));
+
+        let err = &lamplang::translate(r#"var s: string = "a ${b""#).unwrap_err()[0];
+        assert_eq!(err.message, "unterminated interpolation");
+        assert_eq!((err.span.start, err.span.end), (19, 21));
+        let err = &lamplang::translate(r#"var s: string = "a ${}""#).unwrap_err()[0];
+        assert_eq!(err.message, "empty interpolation");
+        let err = &lamplang::translate(r#"var s: string = "a ${1 2}""#).unwrap_err()[0];
+        assert_eq!(err.message, "expected `}`, found number");
+        assert_eq!((err.span.start, err.span.end), (23, 24));
+        let err = &lamplang::translate(r#"var s: string = "a ${missing}""#).unwrap_err()[0];
+        assert_eq!(err.message, "cannot find variable `missing` in this scope");
+    }
+
+    #[test]
+    fn test_format_strings() {
+        let source = r#"
+use io
+var name: string = "Lamp"
+var age: int = 3
+var height: number = 1.5
+println("{s} is {i} years old and {f} tall", name, age, height)
+input("{s}{f}", name, height)
+println(name)
+"#;
+        let c_code = lamplang::translate(source).unwrap();
+        assert!(c_code.contains(r#"println(area_memdup("{s} is {i} years old and {f} tall", 34), lamp__name, (long long)(lamp__age), lamp__height);"#));
+        assert!(c_code.contains(r#"input(area_memdup("{s}{f}", 7), &lamp__name, &lamp__height);println("{s}", lamp__name);"#));
+
+        let source = r#"
+use io
+var name: string = "Lamp"
+var age: int = 3
+var done: bool = false
+print("{s} {f}", name)
+print("{f}", name)
+print("{i}", done)
+print("{x}", age)
+var fmt: string = "{s}"
+print(fmt, name)
+input("{i}", age)
+input("{s}", "literal")
+"#;
+        let errors = lamplang::translate(source).unwrap_err();
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages, vec![
+            "format string has 2 placeholder(s) but 1 argument(s) were supplied",
+            "placeholder `{f}` expects a `number`, found `string`",
+            "placeholder `{i}` expects an integer, found `bool`",
+            "unknown placeholder `{x}` in format string",
+            "the format string of `print` must be a string literal",
+            "`{i}` is not supported by `input`",
+            "`input` can only read into a variable",
+        ]);
+        assert_eq!(errors[1].help.as_deref(), Some("use `{s}` for `string` values"));
+    }
+
+    #[test]
+    fn test_structs() {
+        let source = "\
+struct Line { from: Point, to: Point }
+struct Point { x: number, y: number }
+func shift(p: Point, dx: number): Point {
+    p.x = p.x + dx
+    return p
+}
+var p: Point = new Point(x: 1, y: 2)
+p.x = 3
+var line: Line = new Line(from: p, to: new Point(x: 0, y: p.y * 2))
+line.to.x = shift(p, 1.5).x
+";
+        let c_code = lamplang::translate(source).unwrap();
+        assert!(c_code.contains("typedef struct {double lamp__x; double lamp__y; } lamp__Point;typedef struct {lamp__Point lamp__from; lamp__Point lamp__to; } lamp__Line;lamp__Point lamp__shift (lamp__Point lamp__p,double lamp__dx);lamp__Point lamp__shift (lamp__Point lamp__p,double lamp__dx) {"));
+        assert!(c_code.contains("lamp__Point lamp__p = ((lamp__Point){.lamp__x = 1.0, .lamp__y = 2.0});lamp__p.lamp__x = 3.0;"));
+        assert!(c_code.contains("lamp__Line lamp__line = ((lamp__Line){.lamp__from = lamp__p, .lamp__to = ((lamp__Point){.lamp__x = 0.0, .lamp__y = lamp__p.lamp__y*2.0})});lamp__line.lamp__to.lamp__x = lamp__shift(lamp__p, 1.5).lamp__x;"));
+
+        let source = "\
+struct Point { x: number, y: number }
+struct Node { next: Node }
+var p: Point = new Point(x: 1, z: 2)
+var q: Point = new Point(x: \"a\", y: 1, y: 2)
+var n: number = p.z
+var m: number = n.x
+var same: bool = p == q
+p.x = true
+";
+        let errors = lamplang::translate(source).unwrap_err();
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages, vec![
+            "recursive struct `Node` has infinite size",
+            "missing field(s) `y` in construction of `Point`",
+            "struct `Point` has no field `z`",
+            "mismatched types: expected `number`, found `string`",
+            "field `y` is specified twice",
+            "struct `Point` has no field `z`",
+            "no field `x` on a value of type `number`",
+            "cannot compare structs with `==`",
+            "mismatched types: expected `number`, found `bool`",
+        ]);
+
+        let err = &lamplang::translate("var p: Pointt = 1").unwrap_err()[0];
+        assert_eq!(err.message, "unknown type `Pointt`");
+        let err = &lamplang::translate("var a: number = 1\nint(a) = 2").unwrap_err()[0];
+        assert_eq!(err.message, "invalid assignment target");
+    }
+
+    #[test]
+    fn test_methods() {
+        let source = "\
+func origin_len(): number {
+    return new Point(x: 0, y: 0).len()
+}
+struct Point { x: number, y: number }
+impl Point {
+    func len(self): number {
+        return self.x * self.x + self.y * self.y
+    }
+    func scale(self, k: number): unit {
+        self.x = self.x * k
+    }
+}
+var p: Point = new Point(x: 3, y: 4)
+p.scale(2)
+var l: number = p.len()
+";
+        let c_code = lamplang::translate(source).unwrap();
+        assert!(c_code.contains("double lamp_method_Point__len (lamp__Point* lamp_self,int lamp_self_area);void lamp_method_Point__scale (lamp__Point* lamp_self,int lamp_self_area,double lamp__k);double lamp__origin_len () {"));
+        assert!(c_code.contains("{double lamp_ret = lamp_method_Point__len(&((lamp__Point[]){((lamp__Point){.lamp__x = 0.0, .lamp__y = 0.0})})[0], area_current());area_end();return lamp_ret;}"));
+        assert!(c_code.contains("double lamp_method_Point__len (lamp__Point* lamp_self,int lamp_self_area) {area_start();{double lamp_ret = (*lamp_self).lamp__x*(*lamp_self).lamp__x+(*lamp_self).lamp__y*(*lamp_self).lamp__y;area_end();return lamp_ret;}area_end();}"));
+        assert!(c_code.contains("lamp_method_Point__scale(&lamp__p, area_current(), 2.0);double lamp__l = lamp_method_Point__len(&lamp__p, area_current());"));
+
+        let source = "\
+struct Point { x: number, y: number }
+impl Point {
+    func len(self): number {
+        return self.x
+    }
+}
+impl Vec {
+    func norm(self): number {
+        return 1
+    }
+}
+var p: Point = new Point(x: 3, y: 4)
+var a: number = p.x()
+var b: number = p.len(1)
+var c: number = a.len()
+";
+        let errors = lamplang::translate(source).unwrap_err();
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages, vec![
+            "cannot find struct `Vec`",
+            "no method `x` found for struct `Point`",
+            "function `len` takes 0 argument(s) but 1 were supplied",
+            "no method `len` on a value of type `number`",
+        ]);
+
+        let err = &lamplang::translate("struct P { x: number }\nimpl P {\n    func get(k: number): number {\n        return k\n    }\n}").unwrap_err()[0];
+        assert_eq!(err.message, "expected `self`, found identifier");
+    }
+
+    #[test]
+    fn test_arrays() {
+        let source = "\
+struct Bag { items: [int] }
+func first(a: [number]): number {
+    return a[0]
+}
+var a: [number] = [1, 2.5]
+a[1] = first(a)
+push(a, 3)
+var n: int = len(a)
+var b: Bag = new Bag(items: [])
+push(b.items, n)
+var grid: [[u8]] = [[1], [2, 3]]
+";
+        let c_code = lamplang::translate(source).unwrap();
+        assert!(c_code.contains("#include \"area.h\"\n#include \"array.h\"\n"));
+        assert!(c_code.contains("typedef struct {LampArray* lamp__items; } lamp__Bag;void lamp_promote_Bag (lamp__Bag* value,int area);"));
+        assert!(c_code.contains("double lamp__first (LampArray* lamp__a) {area_start();{double lamp_ret = (*(double*)array_at(lamp__a, 0, \"<input>:3:12\"));area_end();return lamp_ret;}"));
+        assert!(c_code.contains("LampArray* lamp__a = array_new(sizeof(double), 2, (double[]){1.0, 2.5});(*(double*)array_at(lamp__a, 1, \"<input>:6:1\")) = lamp__first(lamp__a);"));
+        assert!(c_code.contains("array_push(lamp__a, (double[]){3.0});int32_t lamp__n = ((int32_t)array_len(lamp__a));"));
+        assert!(c_code.contains("lamp__Bag lamp__b = ((lamp__Bag){.lamp__items = array_new(sizeof(int32_t), 0, NULL)});array_push(lamp__b.lamp__items, (int32_t[]){lamp__n});"));
+        assert!(c_code.contains("LampArray* lamp__grid = array_new(sizeof(LampArray*), 2, (LampArray*[]){array_new(sizeof(uint8_t), 1, (uint8_t[]){1}), "));
+
+        let source = "\
+var a: [int] = [1, \"two\"]
+var x: number = 1
+var y: int = x[0]
+var z: int = a[x]
+var e: bool = [] == a
+push(x, 1)
+var l: int = len(a, a)
+a = [1.5]
+";
+        let errors = lamplang::translate(source).unwrap_err();
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages, vec![
+            "mismatched types: expected `int`, found `string`",
+            "cannot index into a value of type `number`",
+            "array index must be an integer, found `number`",
+            "cannot infer the type of an empty array",
+            "`push` expects an array, found `number`",
+            "function `len` takes 1 argument(s) but 2 were supplied",
+            "mismatched types: expected `int`, found `number`",
+        ]);
+
+        let source = "\
+var a: [int; 3] = [1, 2]
+var b: [int] = a
+push(a, 4)
+var c: [[int; 2]; 1] = [[1, 2]]
+";
+        let errors = lamplang::translate(source).unwrap_err();
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages, vec![
+            "expected an array of 3 element(s), found 2",
+            "mismatched types: expected `[int]`, found `[int; 3]`",
+            "cannot `push` to a fixed-size array of type `[int; 3]`",
+        ]);
+        let errors = lamplang::translate("var a: [int; n] = []").unwrap_err();
+        assert_eq!(errors[0].message, "expected an array length, found identifier");
+    }
+
+    #[test]
+    fn test_for_loops() {
+        let source = "\
+var a: [number] = [1, 2]
+var total: number = 0
+for x in a {
+    total = total + x
+}
+var n: u8 = 3
+for i in 1..n {
+    var i: u8 = i * 2
+}
+";
+        let c_code = lamplang::translate(source).unwrap();
+        assert!(c_code.contains("{LampArray* lamp_items0 = lamp__a;for (int64_t lamp_index0 = 0, lamp_len0 = array_len(lamp_items0); lamp_index0 < lamp_len0; lamp_index0++) {area_start();double lamp__x = ((double*)lamp_items0->data)[lamp_index0];lamp__total = lamp__total+lamp__x;area_end();}}"));
+        assert!(c_code.contains("for (uint8_t lamp__i = 1, lamp_end1 = lamp__n; lamp__i < lamp_end1; lamp__i++) {area_start();uint8_t lamp_v"));
+
+        let source = "\
+var x: number = 1
+for i in 0..x {
+}
+var n: u8 = 3
+for i in n..10000 {
+}
+for i in 0..n {
+    i = 2
+}
+for c in \"abc\" {
+}
+for j in 0..3 {
+    var y: string = j
+}
+";
+        let errors = lamplang::translate(source).unwrap_err();
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages, vec![
+            "range bounds must be integers, found `number`",
+            "literal out of range for `u8`",
+            "cannot assign to loop variable `i`",
+            "cannot iterate over a value of type `string`",
+            "mismatched types: expected `string`, found `int`",
+        ]);
+    }
+
+    #[test]
+    fn test_break_continue() {
+        let source = "\
+var n: int = 0
+outer: while n < 10 {
+    n = n + 1
+    for i in 0..n {
+        if i == 3 {
+            continue outer
+        }
+        if i == 5 {
+            break outer
+        }
+        if i == n {
+            break
+        }
+    }
+    continue
+}
+";
+        let c_code = lamplang::translate(source).unwrap();
+        assert!(c_code.contains("if (lamp__i==3) {area_start();area_end();area_end();area_end();goto outer__0__continue;area_end();}"));
+        assert!(c_code.contains("if (lamp__i==5) {area_start();area_end();area_end();area_end();goto outer__0__break;area_end();}"));
+        assert!(c_code.contains("if (lamp__i==lamp__n) {area_start();area_end();area_end();break;area_end();}"));
+        assert!(c_code.contains("area_end();continue;area_end();outer__0__continue:;}outer__0__break:;"));
+
+        let source = "\
+func f(): unit {
+    break
+}
+outer: while true {
+    outer: while true {
+    }
+}
+continue
+";
+        let errors = lamplang::translate(source).unwrap_err();
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages, vec![
+            "`break` outside of a loop",
+            "label `outer` is already used by an enclosing loop",
+            "`continue` outside of a loop",
+        ]);
+    }
+
+    #[test]
+    fn test_return_releases_areas() {
+        let source = "\
+struct Named { name: string, n: number }
+func find(a: [int], v: int): int {
+    for i in 0..len(a) {
+        if a[i] == v {
+            return i
+        }
+    }
+    return -1
+}
+func greet(who: string): string {
+    return \"hello ${who}\"
+}
+func named(): Named {
+    return new Named(name: \"x\", n: 1)
+}
+func names(): [string] {
+    return [\"a\"]
+}
+func stop(a: [int]): unit {
+    for i in 0..len(a) {
+        if a[i] < 0 { return }
+    }
+}
+";
+        let c_code = lamplang::translate(source).unwrap();
+        assert!(c_code.contains("if ((*(int32_t*)array_at(lamp__a, lamp__i, \"<input>:4:12\"))==lamp__v) {area_start();{int32_t lamp_ret = lamp__i;area_end();area_end();area_end();return lamp_ret;}area_end();}"));
+        assert!(c_code.contains("{int32_t lamp_ret = -(1);area_end();return lamp_ret;}"));
+        assert!(c_code.contains("{char* lamp_ret = format(\"hello {s}\", lamp__who);int lamp_area = area_current() - 1;lamp_ret = area_strdup_in(lamp_area, lamp_ret);area_end();return lamp_ret;}"));
+        assert!(c_code.contains("void lamp_promote_Named (lamp__Named* value,int area) {value->lamp__name = area_strdup_in(area, value->lamp__name);}"));
+        assert!(c_code.contains("int lamp_area = area_current() - 1;lamp_promote_Named(&lamp_ret, lamp_area);area_end();return lamp_ret;}"));
+        assert!(c_code.contains("if (array_move_in(lamp_area, lamp_ret)) {for (int64_t lamp_i0 = 0; lamp_i0 < lamp_ret->len; lamp_i0++) {((char**)lamp_ret->data)[lamp_i0] = area_strdup_in(lamp_area, ((char**)lamp_ret->data)[lamp_i0]);}}"));
+        assert!(c_code.contains("{area_start();{area_end();area_end();area_end();return;}area_end();}"));
+    }
+
+    #[test]
+    fn test_escaping_values_are_copied() {
+        let source = "\
+use io
+struct Box { label: string }
+var s: string = \"a\"
+var b: Box = new Box(label: \"b\")
+var names: [string] = []
+if true {
+    s = format(\"{s}!\", s)
+    b.label = s
+    push(names, s)
+}
+";
+        let c_code = lamplang::translate(source).unwrap();
+        assert!(c_code.contains("{char* lamp_value = format(area_memdup(\"{s}!\", 5), lamp__s);int lamp_area = area_current() - 1;lamp_value = area_strdup_in(lamp_area, lamp_value);lamp__s = lamp_value;}"));
+        assert!(c_code.contains("int lamp_area = area_current() - 1;lamp_value = area_strdup_in(lamp_area, lamp_value);lamp__b.lamp__label = lamp_value;}"));
+        assert!(c_code.contains("{LampArray* lamp_array = lamp__names;char* lamp_item = lamp__s;lamp_item = area_strdup_in(lamp_array->area, lamp_item);array_push(lamp_array, &lamp_item);}"));
+    }
+
+    /// Compiles every program in `tests/programs` with AddressSanitizer and checks that it runs
+    /// without memory errors or leaks and prints the expected `.out` file. Skipped without `gcc`.
+    #[test]
+    fn test_programs_under_sanitizers() {
+        use std::{fs, path::Path, process::Command};
+
+        if Command::new("gcc").arg("--version").output().is_err() {
+            eprintln!("gcc not found, skipping sanitizer tests");
+            return;
+        }
+        let out_dir = std::env::temp_dir().join(format!("lamplang-sanitizers-{}", std::process::id()));
+        fs::create_dir_all(&out_dir).unwrap();
+        let mut programs: Vec<_> = fs::read_dir("tests/programs").unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "lamp"))
+            .collect();
+        programs.sort();
+        assert!(!programs.is_empty());
+        for program in programs {
+            let name = program.file_stem().unwrap().to_str().unwrap();
+            let source = fs::read_to_string(&program).unwrap();
+            let c_code = lamplang::translate_file(&source, program.to_str().unwrap(), lamplang::DEFAULT_MAX_ERRORS).unwrap();
+            let c_file = out_dir.join(format!("{}.c", name));
+            let binary = out_dir.join(name);
+            fs::write(&c_file, c_code).unwrap();
+            let gcc = Command::new("gcc")
+                .args(["-fsanitize=address,undefined", "-fno-omit-frame-pointer", "-g", "-Ilib/include"])
+                .arg(&c_file)
+                .args(["lib/src/io.c", "lib/src/area.c", "lib/src/array.c", "-o"])
+                .arg(&binary)
+                .output().unwrap();
+            assert!(gcc.status.success(), "{}: {}", name, String::from_utf8_lossy(&gcc.stderr));
+            let run = Command::new(&binary).output().unwrap();
+            let stderr = String::from_utf8_lossy(&run.stderr);
+            assert!(run.status.success() && stderr.is_empty(), "{}: {}", name, stderr);
+            let expected = fs::read_to_string(Path::new("tests/programs").join(format!("{}.out", name))).unwrap();
+            assert_eq!(String::from_utf8_lossy(&run.stdout), expected, "{}", name);
+        }
+        fs::remove_dir_all(&out_dir).unwrap();
+    }
+
+    /// Integer arithmetic in C wraps, converts numbers and divides like `run`, without the undefined
+    /// behaviour that UBSan reports. Skipped without `gcc`.
+    #[test]
+    fn test_c_integers_match_run() {
+        use std::{fs, process::Command};
+
+        if Command::new("gcc").arg("--version").output().is_err() {
+            eprintln!("gcc not found, skipping C integer tests");
+            return;
+        }
+        let out_dir = std::env::temp_dir().join(format!("lamplang-integers-{}", std::process::id()));
+        fs::create_dir_all(&out_dir).unwrap();
+        let run_c = |name: &str, source: &str| {
+            let c_file = out_dir.join(format!("{}.c", name));
+            let binary = out_dir.join(name);
+            fs::write(&c_file, lamplang::translate_file(source, name, lamplang::DEFAULT_MAX_ERRORS).unwrap()).unwrap();
+            let gcc = Command::new("gcc")
+                .args(["-fsanitize=undefined", "-fno-sanitize-recover=all", "-Ilib/include"])
+                .arg(&c_file)
+                .args(["lib/src/io.c", "lib/src/area.c", "lib/src/array.c", "-o"])
+                .arg(&binary)
+                .output().unwrap();
+            assert!(gcc.status.success(), "{}: {}", name, String::from_utf8_lossy(&gcc.stderr));
+            Command::new(&binary).output().unwrap()
+        };
+
+        let source = "\
+use io
+var a: u8 = 250
+var s: i8 = 127
+var m: int = 2147483647
+var minus: int = -1
+var lo: int = -2147483647 - 1
+var f: number = 300.7
+println(\"{i} {i} {i} {i}\", a + 10, s + s, m + 1, m * 2)
+println(\"${a + 10 > 100} ${lo / minus} ${lo % minus} ${-lo} ${a / u8(7)}\")
+println(\"{i} {i} {i} {i}\", u8(f), i8(-f), u32(-1.0), int(1e12))
+println(\"{i} {i}\", u16(65535) * u16(65535), i64(lo) * i64(lo) * i64(4))
+";
+        let run = run_c("wrapping", source);
+        assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+        assert_eq!(String::from_utf8_lossy(&run.stdout), lamplang::run(source, "").unwrap());
+        assert!(String::from_utf8_lossy(&run.stdout).starts_with("4 -2 -2147483648 -2\nfalse -2147483648 0 -2147483648 35\n44 -44 "));
+
+        let source = "use io\nvar zero: u8 = 0\nprintln(\"{i}\", 7 / zero)";
+        let run = run_c("division", source);
+        assert_eq!(run.status.code(), Some(1));
+        assert_eq!(String::from_utf8_lossy(&run.stderr), "division:3:16: division by zero\n");
+        assert_eq!(lamplang::run(source, "").unwrap_err()[0].message, "division by zero");
+        fs::remove_dir_all(&out_dir).unwrap();
+    }
+
+    #[test]
+    fn test_run() {
+        let source = "\
+use io
+use math
+struct Counter { n: u8, seen: [string] }
+impl Counter {
+    func add(self, by: u8, name: string): unit {
+        self.n = self.n + by
+        push(self.seen, name)
+    }
+}
+var name: string = \"\"
+var x: number = 0
+input(\"{s} {f}\", name, x)
+var c: Counter = new Counter(n: 250, seen: [])
+c.add(3, name)
+var cs: [Counter] = [c]
+cs[0].add(4, \"b\")
+println(\"{s} {f} {f}\", name, x * 2, sqrt(16.0))
+println(\"${c.n} ${cs[0].n} ${len(c.seen)} ${x > 1.0} ${1000000.0} ${int(-2.7)}\")
+";
+        assert_eq!(lamplang::run(source, "ada 1.25\n").unwrap(), "ada 2.5 4\n253 1 2 true 1e+06 -2\n");
+    }
+
+    #[test]
+    fn test_runtime_errors() {
+        let err = &lamplang::run("var a: [int] = [1, 2]\nvar i: int = 2\nvar b: int = a[i]", "").unwrap_err()[0];
+        assert_eq!(err.kind, lamplang::ErrorKind::Runtime);
+        assert_eq!(err.message, "index 2 is out of bounds for an array of length 2");
+        assert_eq!(err.span.start, 50);
+
+        let err = &lamplang::run("var zero: int = 0\nvar a: int = 1 / zero", "").unwrap_err()[0];
+        assert_eq!(err.message, "division by zero");
+
+        let source = "\
+func down(n: int): int {
+    return down(n + 1)
+}
+var x: int = down(0)
+";
+        let err = &lamplang::run(source, "").unwrap_err()[0];
+        assert_eq!(err.message, "too many nested calls");
+    }
+
+    /// Runs every program in `tests/programs` with the interpreter, which needs no C toolchain.
+    #[test]
+    fn test_run_programs() {
+        use std::fs;
+
+        for entry in fs::read_dir("tests/programs").unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|ext| ext == "lamp") {
+                let output = lamplang::run(&fs::read_to_string(&path).unwrap(), "").unwrap();
+                assert_eq!(output, fs::read_to_string(path.with_extension("out")).unwrap(), "{}", path.display());
+            }
+        }
+    }
+
+    /// The VM runs the same programs as the interpreter, with the same output and errors.
+    #[test]
+    fn test_vm() {
+        use std::fs;
+
+        for entry in fs::read_dir("tests/programs").unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|ext| ext == "lamp") {
+                let output = lamplang::run_vm(&fs::read_to_string(&path).unwrap(), "").unwrap();
+                assert_eq!(output, fs::read_to_string(path.with_extension("out")).unwrap(), "{}", path.display());
+            }
+        }
+
+        let source = "\
+use io
+struct P { x: i8, tags: [string] }
+impl P {
+    func bump(self, by: i8): i8 {
+        self.x = self.x + by
+        return self.x
+    }
+}
+var ps: [P] = [new P(x: 1, tags: []), new P(x: 120, tags: [])]
+var name: string = \"\"
+input(\"{s}\", name)
+outer: for p in ps {
+    var i: int = 0
+    while true {
+        i = i + 1
+        if i == 2 { continue }
+        if i > 3 || p.x > 100 { continue outer }
+        push(p.tags, \"${name}${i}\")
+    }
+}
+println(\"${ps[1].bump(10)} ${ps[1].x} ${len(ps[0].tags)} ${ps[0].tags[1]} ${-ps[0].x % 2}\")
+";
+        let expected = "-126 -126 2 ada3 -1\n";
+        assert_eq!(lamplang::run(source, "ada").unwrap(), expected);
+        assert_eq!(lamplang::run_vm(source, "ada").unwrap(), expected);
+
+        let err = &lamplang::run_vm("var a: [int] = [1, 2]\nvar i: int = 2\nvar b: int = a[i]", "").unwrap_err()[0];
+        assert_eq!(err.kind, lamplang::ErrorKind::Runtime);
+        assert_eq!(err.message, "index 2 is out of bounds for an array of length 2");
+        assert_eq!(err.span.start, 50);
+    }
+
+    #[test]
+    fn test_bytecode_file() {
+        use lamplang::bytecode::{self, Program};
+
+        let program = lamplang::compile_bytecode("use io\nvar n: int = 2\nprintln(\"{i}\", n * 3)", 20).unwrap();
+        let bytes = program.to_bytes();
+        assert!(bytes.starts_with(bytecode::MAGIC));
+        assert_eq!(Program::from_bytes(&bytes).unwrap(), program);
+
+        let mut output = vec![];
+        lamplang::run_bytecode(&Program::from_bytes(&bytes).unwrap(), &mut "".as_bytes(), &mut output).unwrap();
+        assert_eq!(output, b"6\n");
+
+        assert_eq!(Program::from_bytes(&bytes[..bytes.len() - 1]).unwrap_err().0, "unexpected end of file");
+        assert_eq!(Program::from_bytes(b"#!lamp").unwrap_err().0, "not a Lamp bytecode file");
+        let mut corrupted = program.clone();
+        corrupted.functions[0].locals = 1_000_000_000;
+        assert_eq!(Program::from_bytes(&corrupted.to_bytes()).unwrap_err().0, "function `main` has more locals than its code uses");
+
+        assert_eq!(bytecode::disassemble(&program), "\
+constants
+     0  2: int
+     1  \"{i}\"
+     2  3: int
+     3  \"println\"
+
+fn main #0 (0 params, 1 locals)
+  0000  Constant 0          ; 2: int
+  0001  SetLocal 0
+  0002  Constant 1          ; \"{i}\"
+  0003  GetLocal 0
+  0004  Constant 2          ; 3: int
+  0005  Mul
+  0006  CallNative 3 2      ; \"println\"
+  0007  Pop
+  0008  Unit
+  0009  Return
+");
+    }
+
+    #[test]
+    fn test_repl() {
+        use lamplang::repl::{is_complete, Session};
+
+        let mut session = Session::new();
+        let mut eval = |input: &str| {
+            let mut output = vec![];
+            let value = session.eval(input, &mut "".as_bytes(), &mut output).unwrap();
+            (value, String::from_utf8(output).unwrap())
+        };
+        assert_eq!(eval("use io\nstruct P { x: int, tags: [string] }"), (None, String::new()));
+        assert_eq!(eval("var p: P = new P(x: 1, tags: [])"), (None, String::new()));
+        assert_eq!(eval("impl P {\n    func add(self, tag: string): unit {\n        push(self.tags, tag)\n    }\n}").0, None);
+        assert_eq!(eval("p.add(\"a\")\np.x = p.x + 1\nprintln(\"{i}\", p.x)"), (None, "2\n".to_string()));
+        assert_eq!(eval("p").0.unwrap(), "new P(x: 2, tags: [\"a\"]): P");
+        assert_eq!(eval("var p: number = 1.5").0, None);
+        assert_eq!(eval("p * 2.0").0.unwrap(), "3: number");
+        assert_eq!(eval("println(\"hi\")"), (None, "hi\n".to_string()));
+
+        assert_eq!(session.type_of("len([p]) > 0").unwrap(), lamplang::ValueType::Bool);
+        assert_eq!(session.c_code("p + 1.0").unwrap(), "lamp__p+1.0");
+        assert!(session.ast("var q: number = p").unwrap().starts_with("Stmt {\n    kind: VarDef(\n        \"q\","));
+        let err = &session.eval("p + true", &mut "".as_bytes(), &mut vec![]).unwrap_err()[0];
+        assert_eq!(err.kind, lamplang::ErrorKind::Type);
+        assert!(session.render_error(err).contains(" --> <repl>:1:5\n  |\n1 | p + true\n  |     ^^^^\n"));
+
+        // Errors in the definitions of earlier inputs show in those inputs.
+        let mut session = Session::new();
+        let mut eval = |input: &str| session.eval(input, &mut "".as_bytes(), &mut vec![]);
+        eval("struct S { a: int }").unwrap();
+        eval("func get(s: S): int {\n    return s.a\n}").unwrap();
+        eval("var s: S = new S(a: 1)").unwrap();
+        let errors = eval("struct S { b: int }").unwrap_err();
+        let rendered: Vec<String> = errors.iter().map(|err| session.render_error(err)).collect();
+        assert!(rendered.iter().any(|text| text.contains(" --> <input 2>:2:12\n  |\n2 |     return s.a\n")));
+        assert!(rendered.iter().any(|text| text.contains(" --> <input 3>:1:1\n  |\n1 | var s: S = new S(a: 1)\n")));
+        assert!(rendered.iter().all(|text| !text.contains("<repl>")));
+
+        assert!(!is_complete("func f(): unit {\n    if true {"));
+        assert!(is_complete("func f(): unit {\n    if true { }\n}"));
+    }
+
+    #[test]
+    fn test_llvm_ir() {
+        let source = "\
+use io
+func count(n: int): int {
+    var i: int = 0
+    while i < n {
+        if i == 3 {
+            println(\"three\")
+        }
+        i = i + 1
+    }
+    return i
+}
+println(\"{i}\", count(5))
+";
+        let expected = "\
+; ModuleID = 'count.lamp'
+source_filename = \"count.lamp\"
+
+@.str.0 = private unnamed_addr constant [6 x i8] c\"three\\00\"
+@.str.1 = private unnamed_addr constant [4 x i8] c\"{i}\\00\"
+
+declare void @println(ptr, ...)
+declare void @area_start()
+declare void @area_end()
+
+define i32 @lamp.count(i32 %arg.n) {
+entry:
+  %v.n.5 = alloca i32
+  %v.i.6 = alloca i32
+  store i32 %arg.n, ptr %v.n.5
+  store i32 0, ptr %v.i.6
+  br label %while.cond1
+while.cond1:
+  %t.2 = load i32, ptr %v.i.6
+  %t.3 = load i32, ptr %v.n.5
+  %t.4 = icmp slt i32 %t.2, %t.3
+  br i1 %t.4, label %while.body1, label %while.end1
+while.body1:
+  %t.5 = load i32, ptr %v.i.6
+  %t.6 = icmp eq i32 %t.5, 3
+  br i1 %t.6, label %if.then7, label %if.end7
+if.then7:
+  call void (ptr, ...) @println(ptr @.str.0)
+  br label %if.end7
+if.end7:
+  %t.8 = load i32, ptr %v.i.6
+  %t.9 = add i32 %t.8, 1
+  store i32 %t.9, ptr %v.i.6
+  br label %while.cond1
+while.end1:
+  %t.10 = load i32, ptr %v.i.6
+  ret i32 %t.10
+dead.11:
+  unreachable
+}
+
+define i32 @main() {
+entry:
+  call void @area_start()
+  %t.1 = call i32 @lamp.count(i32 5)
+  %t.2 = sext i32 %t.1 to i64
+  call void (ptr, ...) @println(ptr @.str.1, i64 %t.2)
+  call void @area_end()
+  ret i32 0
+}
+";
+        assert_eq!(lamplang::translate_file_to_llvm(source, "count.lamp", lamplang::DEFAULT_MAX_ERRORS).unwrap(), expected);
+    }
+
+    /// Builds every program in `tests/programs` from LLVM IR and checks that it prints the
+    /// expected `.out` file, then that integer division is checked. Skipped without `llc` and `gcc`.
+    #[test]
+    fn test_programs_with_llvm() {
+        use std::{fs, path::{Path, PathBuf}, process::Command};
+
+        if Command::new("llc").arg("--version").output().is_err() || Command::new("gcc").arg("--version").output().is_err() {
+            eprintln!("llc or gcc not found, skipping LLVM tests");
+            return;
+        }
+        let out_dir = std::env::temp_dir().join(format!("lamplang-llvm-{}", std::process::id()));
+        fs::create_dir_all(&out_dir).unwrap();
+        let build = |name: &str, source: &str, file_name: &str| -> PathBuf {
+            let ir = lamplang::translate_file_to_llvm(source, file_name, lamplang::DEFAULT_MAX_ERRORS).unwrap();
+            let ll_file = out_dir.join(format!("{}.ll", name));
+            let object = out_dir.join(format!("{}.o", name));
+            let binary = out_dir.join(name);
+            fs::write(&ll_file, ir).unwrap();
+            // LLVM before 15 reads `ptr` only with opaque pointers enabled.
+            let llc = Command::new("llc")
+                .args(["-opaque-pointers", "-relocation-model=pic", "-filetype=obj", "-o"])
+                .arg(&object)
+                .arg(&ll_file)
+                .output().unwrap();
+            assert!(llc.status.success(), "{}: {}", name, String::from_utf8_lossy(&llc.stderr));
+            let gcc = Command::new("gcc")
+                .arg(&object)
+                .args(["lib/src/io.c", "lib/src/area.c", "lib/src/array.c", "-Ilib/include", "-lm", "-o"])
+                .arg(&binary)
+                .output().unwrap();
+            assert!(gcc.status.success(), "{}: {}", name, String::from_utf8_lossy(&gcc.stderr));
+            binary
+        };
+        let mut programs: Vec<_> = fs::read_dir("tests/programs").unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "lamp"))
+            .collect();
+        programs.sort();
+        assert!(!programs.is_empty());
+        for program in programs {
+            let name = program.file_stem().unwrap().to_str().unwrap();
+            let source = fs::read_to_string(&program).unwrap();
+            let run = Command::new(build(name, &source, program.to_str().unwrap())).output().unwrap();
+            assert!(run.status.success(), "{}: {}", name, String::from_utf8_lossy(&run.stderr));
+            let expected = fs::read_to_string(Path::new("tests/programs").join(format!("{}.out", name))).unwrap();
+            assert_eq!(String::from_utf8_lossy(&run.stdout), expected, "{}", name);
+        }
+
+        let source = "\
+use io
+var m: int = -2147483647 - 1
+var minus: int = -1
+println(\"{i} {i} {i}\", m / minus, m % minus, u8(200) / u8(7))
+var zero: int = 0
+println(\"{i}\", 1 / zero)
+";
+        let run = Command::new(build("division", source, "division.lamp")).output().unwrap();
+        assert_eq!(run.status.code(), Some(1));
+        assert_eq!(String::from_utf8_lossy(&run.stdout), "-2147483648 0 28\n");
+        assert_eq!(String::from_utf8_lossy(&run.stderr), "division.lamp:6:16: division by zero\n");
+        fs::remove_dir_all(&out_dir).unwrap();
+    }
+
+    #[test]
+    fn test_wat() {
+        let source = "\
+use io
+func count(n: int): int {
+    var i: int = 0
+    while i < n {
+        i = i + 1
+    }
+    return i
+}
+if count(5) == 5 {
+    println(\"five\")
+}
+";
+        let expected = "\
+(module
+  (import \"lamp\" \"println\" (func $println (param i32)))
+  (memory (export \"memory\") 1)
+  (global $heap (mut i32) (i32.const 1032))
+  (global $area_depth (mut i32) (i32.const 0))
+  (data (i32.const 1024) \"five\\00\")
+  (func $rt.area_start
+    (if (i32.ge_u (global.get $area_depth) (i32.const 128))
+      (then (unreachable)))
+    (i32.store (i32.shl (global.get $area_depth) (i32.const 2)) (i32.const 0))
+    (global.set $area_depth (i32.add (global.get $area_depth) (i32.const 1))))
+  (func $rt.area_end
+    (local $block i32)
+    (local $rest i32)
+    (if (i32.eqz (global.get $area_depth))
+      (then (unreachable)))
+    (global.set $area_depth (i32.sub (global.get $area_depth) (i32.const 1)))
+    (local.set $block (i32.load (i32.shl (global.get $area_depth) (i32.const 2))))
+    (block $done
+      (loop $next
+        (br_if $done (i32.eqz (local.get $block)))
+        (local.set $rest (i32.load (i32.sub (local.get $block) (i32.const 4))))
+        (i32.store (i32.sub (local.get $block) (i32.const 4)) (i32.load (i32.const 512)))
+        (i32.store (i32.const 512) (local.get $block))
+        (local.set $block (local.get $rest))
+        (br $next))))
+  (func $lamp.count (export \"lamp.count\") (param $n i32) (result i32)
+    (local $v.i.6 i32)
+    (local $t.2 i32)
+    (call $rt.area_start)
+    (local.set $v.i.6 (i32.const 0))
+    (block $while.end.1
+      (loop $while.cond.1
+        (br_if $while.end.1 (i32.eqz (i32.lt_s (local.get $v.i.6) (local.get $n))))
+        (call $rt.area_start)
+        (local.set $v.i.6 (i32.add (local.get $v.i.6) (i32.const 1)))
+        (call $rt.area_end)
+        (br $while.cond.1)))
+    (local.set $t.2 (local.get $v.i.6))
+    (call $rt.area_end)
+    (return (local.get $t.2))
+    (unreachable))
+  (func $main (export \"main\")
+    (call $rt.area_start)
+    (if (i32.eq (call $lamp.count (i32.const 5)) (i32.const 5))
+      (then
+        (call $rt.area_start)
+        (call $println (i32.const 1024))
+        (call $rt.area_end)))
+    (call $rt.area_end)))
+";
+        assert_eq!(lamplang::translate_to_wat(source, lamplang::DEFAULT_MAX_ERRORS).unwrap(), expected);
+
+        let source = "\
+use io
+struct P { x: u8, w: number }
+var ps: [P] = [new P(x: 1, w: 0.5)]
+ps[0].w = 2.5
+for p in ps {
+    println(\"{i} {f}\", p.x + 255, p.w)
+}
+";
+        let wat = lamplang::translate_to_wat(source, lamplang::DEFAULT_MAX_ERRORS).unwrap();
+        // Structs are laid out like in C and copied into the loop variable.
+        assert!(wat.contains("(f64.store offset=8 (call $rt.array_at (local.get $v.ps.5) (i64.extend_i32_s (i32.const 0))) (f64.const 2.5))"));
+        assert!(wat.contains("(memory.copy (local.get $v.p.6) "));
+        assert!(wat.contains("(i32.and (i32.add (i32.load (local.get $v.p.6)) (i32.const 255)) (i32.const 255))"));
+        assert!(wat.contains("(func $rt.format_number ") && wat.contains("(func $rt.string_concat "));
+        // A string assigned to an outer variable is copied to its area before the `if` ends it, and
+        // dividing by -1 negates instead of trapping.
+        let source = "var s: string = \"\"\nvar n: int = 7\nif n > 0 {\n    s = \"${n}\"\n    n = n / (0 - n)\n}\n";
+        let wat = lamplang::translate_to_wat(source, lamplang::DEFAULT_MAX_ERRORS).unwrap();
+        assert!(wat.contains("(local.set $t.2 (i32.sub (global.get $area_depth) (i32.const 2)))"));
+        assert!(wat.contains("(local.set $t.1 (call $rt.area_strdup_in (local.get $t.2) (local.get $t.1)))"));
+        assert!(wat.contains("(if (result i32) (i32.eq (local.get $t.4) (i32.const -1)) (then (i32.sub (i32.const 0) (local.get $t.3))) (else (i32.div_s (local.get $t.3) (local.get $t.4))))"));
+        // Functions of the program cannot clash with the runtime or the entry point.
+        let wat = lamplang::translate_to_wat(&std::fs::read_to_string("tests/programs/runtime_names.lamp").unwrap(), lamplang::DEFAULT_MAX_ERRORS).unwrap();
+        assert!(wat.contains("(func $lamp.main (export \"lamp.main\") (result i32)"));
+        assert!(wat.contains("(func $lamp.area_alloc (export \"lamp.area_alloc\") (param $size i32) (result i32)"));
+        assert!(wat.contains("(func $rt.area_alloc (param $size i32) (result i32)"));
+        assert!(wat.contains("(func $main (export \"main\")"));
+        let err = &lamplang::translate_to_wat("use io\nvar s: string = \"\"\ninput(\"{s}\", s)", lamplang::DEFAULT_MAX_ERRORS).unwrap_err()[0];
+        assert_eq!(err.message, "`input` is not available on the wasm target");
+    }
+
+    /// `--target native` goes with every backend; `--target wasm` with a backend other than the
+    /// default is rejected by `main`.
+    #[test]
+    fn test_compile_args() {
+        use clap::Parser;
+        use crate::args::{Backend, Cli, Commands, Target};
+
+        let cli = Cli::try_parse_from(["lamplang", "compile", "a.lamp", "-o", "a.ll", "--target", "native", "--backend", "llvm"]).unwrap();
+        assert!(matches!(cli.command, Some(Commands::Compile { target: Target::Native, backend: Backend::Llvm, .. })));
+        let cli = Cli::try_parse_from(["lamplang", "compile", "a.lamp", "-o", "a.wat", "--target", "wasm"]).unwrap();
+        assert!(matches!(cli.command, Some(Commands::Compile { target: Target::Wasm, backend: Backend::C, .. })));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,333 @@
+//! The state of `lamplang repl`: definitions and variables that carry over from one input to the next.
+//!
+//! Every input is checked together with the definitions entered before it and a declaration of each
+//! variable, so it resolves and type checks like the rest of a program would. Only the input itself
+//! runs, in a top level that already holds the values of the variables.
+//!
+//! The spans of the session count from the start of the first input, each input starting one past the
+//! end of the one before, so an error in a definition from an earlier input points into that input.
+
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+
+use crate::translator::ast::{Expr, ExprKind, FieldInit, Stmt, StmtKind, TypedArgument};
+use crate::translator::codegen::CCodeGenerator;
+use crate::translator::interpreter::{display, Interpreter, RuntimeValue};
+use crate::translator::lexer::TokenType;
+use crate::translator::parser::Parser;
+use crate::translator::symbols::{SymbolId, SymbolTable};
+use crate::{check, lex, render_error, CompileError, Span, Value, ValueType};
+
+/// The variable that holds the value of an expression input; no input can name it.
+const VALUE: &str = "<value>";
+
+pub struct Session {
+    /// The `use`, `struct`, `impl` and `func` statements entered so far.
+    items: Vec<Stmt>,
+    /// The variables defined so far, in the order they were defined.
+    variables: Vec<Variable>,
+    /// Every input so far with the offset it starts at.
+    inputs: Vec<(usize, String)>,
+}
+
+struct Variable {
+    name: String,
+    typ: ValueType,
+    value: RuntimeValue,
+    /// Where the variable was defined, which its declaration for later inputs points at.
+    span: Span,
+}
+
+/// An input is an expression when it parses as one, and statements otherwise.
+enum Input {
+    Expr(Expr),
+    Stmts(Vec<Stmt>),
+}
+
+/// An input checked together with the session.
+struct Checked {
+    /// The definitions of the session followed by the statements of the input.
+    program: Stmt,
+    symbols: SymbolTable,
+    /// The variables of the session that the input can use, by index, with their symbols.
+    variables: Vec<(usize, SymbolId)>,
+}
+
+/// Tells whether an input has no unclosed braces, brackets or parentheses, or needs more lines.
+/// Input that does not lex is complete, so that its error is reported.
+pub fn is_complete(input: &str) -> bool {
+    let Ok(tokens) = lex(input) else {
+        return true;
+    };
+    let depth = tokens.iter().fold(0i32, |depth, token| match token.token_type {
+        TokenType::LeftBrace | TokenType::LeftBracket | TokenType::LeftParen => depth + 1,
+        TokenType::RightBrace | TokenType::RightBracket | TokenType::RightParen => depth - 1,
+        _ => depth,
+    });
+    depth <= 0
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self { items: vec![], variables: vec![], inputs: vec![] }
+    }
+
+    /// Runs an input, which reads `stdin` and prints to `stdout`. Returns the value of an expression
+    /// with its type, e.g. `3: int`, and `None` for statements and for expressions of type `unit`.
+    /// Definitions replace earlier ones with the same name.
+    pub fn eval(&mut self, input: &str, stdin: &mut dyn BufRead, stdout: &mut dyn Write) -> Result<Option<String>, Vec<CompileError>> {
+        self.add_input(input);
+        let stmts = match self.parse(input)? {
+            Input::Stmts(stmts) => stmts,
+            Input::Expr(expr) => {
+                let typ = self.type_of_expr(expr.clone())?;
+                let span = expr.span;
+                let kind = match typ {
+                    ValueType::Unit => StmtKind::Function(Box::new(expr)),
+                    typ => StmtKind::VarDef(VALUE.to_string(), Box::new(expr), typ),
+                };
+                vec![Stmt::new(kind, span)]
+            }
+        };
+        let definitions: Vec<Stmt> = stmts.iter().filter(|stmt| is_item(stmt)).cloned().collect();
+        let input_len = stmts.len();
+        let Checked { program, variables, .. } = self.check(stmts)?;
+
+        let mut globals: HashMap<SymbolId, RuntimeValue> =
+            variables.iter().map(|(index, id)| (*id, self.variables[*index].value.clone())).collect();
+        let result = Interpreter::new(stdin, stdout).run_with_globals(&program, &mut globals);
+
+        // The variables keep the values the input left them with, also after a runtime error.
+        let mut kept: Vec<Variable> = variables.into_iter().map(|(index, id)| {
+            let Variable { name, typ, value, span } = &self.variables[index];
+            Variable { name: name.clone(), typ: typ.clone(), value: globals.remove(&id).unwrap_or_else(|| value.clone()), span: *span }
+        }).collect();
+        let mut value = None;
+        for stmt in input_stmts(&program, input_len) {
+            if let (StmtKind::VarDef(name, _, typ), Some(id)) = (&stmt.kind, stmt.symbol)
+                && let Some(defined) = globals.remove(&id) {
+                if name == VALUE {
+                    value = Some(format!("{}: {}", self.describe(&defined, typ), typ));
+                } else {
+                    kept.push(Variable { name: name.clone(), typ: typ.clone(), value: defined, span: stmt.span });
+                }
+            }
+        }
+        self.items.retain(|item| !replaced_by(item, &definitions));
+        self.items.extend(definitions);
+        self.variables = kept;
+        result.map_err(|err| vec![err])?;
+        Ok(value)
+    }
+
+    /// The type of an expression.
+    pub fn type_of(&mut self, input: &str) -> Result<ValueType, Vec<CompileError>> {
+        self.add_input(input);
+        let expr = self.parser(input)?.parse_expression().map_err(|err| vec![err])?;
+        self.type_of_expr(expr)
+    }
+
+    /// The checked AST of an input: the `Stmt`s of statements, or the `Expr` of an expression.
+    pub fn ast(&mut self, input: &str) -> Result<String, Vec<CompileError>> {
+        self.add_input(input);
+        let (stmts, is_expr) = self.parse_stmts(input)?;
+        let input_len = stmts.len();
+        let Checked { program, .. } = self.check(stmts)?;
+        let dumps: Vec<String> = input_stmts(&program, input_len).iter().map(|stmt| match &stmt.kind {
+            StmtKind::Function(expr) if is_expr => format!("{:#?}", expr),
+            _ => format!("{:#?}", stmt),
+        }).collect();
+        Ok(dumps.join("\n"))
+    }
+
+    /// The C generated for an input: the C expression of an expression, or the C of statements as
+    /// they would appear in `main`.
+    pub fn c_code(&mut self, input: &str) -> Result<String, Vec<CompileError>> {
+        self.add_input(input);
+        let (stmts, is_expr) = self.parse_stmts(input)?;
+        let input_len = stmts.len();
+        let Checked { program, symbols, .. } = self.check(stmts)?;
+        let stmts = input_stmts(&program, input_len).to_vec();
+        let mut generator = CCodeGenerator::new(program, symbols);
+        let code: Result<Vec<String>, CompileError> = stmts.into_iter().map(|stmt| match stmt.kind {
+            StmtKind::Function(expr) if is_expr => generator.generate_expression(*expr),
+            _ => generator.generate_statement(stmt),
+        }).collect();
+        Ok(code.map_err(|err| vec![err])?.join("\n"))
+    }
+
+    /// Shows an error in the input it points into: the last input is `<repl>`, and an earlier one is
+    /// named by its number, e.g. `<input 2>`.
+    pub fn render_error(&self, error: &CompileError) -> String {
+        let index = self.inputs.iter().rposition(|(start, _)| *start <= error.span.start).unwrap_or(0);
+        let Some((start, source)) = self.inputs.get(index) else {
+            return render_error(error, "<repl>", "");
+        };
+        let file_name = if index + 1 == self.inputs.len() { "<repl>".to_string() } else { format!("<input {}>", index + 1) };
+        let mut error = error.clone();
+        error.span = Span::new(error.span.start.saturating_sub(*start), error.span.end.saturating_sub(*start));
+        render_error(&error, &file_name, source)
+    }
+
+    fn add_input(&mut self, input: &str) {
+        let start = self.inputs.last().map_or(0, |(start, text)| start + text.len() + 1);
+        self.inputs.push((start, input.to_string()));
+    }
+
+    /// A parser for the last input, whose spans start at the offset of the input.
+    fn parser(&self, input: &str) -> Result<Parser, Vec<CompileError>> {
+        let start = self.inputs.last().map_or(0, |(start, _)| *start);
+        let tokens = lex(input).map_err(|mut err| {
+            err.span = Span::new(err.span.start + start, err.span.end + start);
+            vec![err]
+        })?;
+        let tokens = tokens.into_iter().map(|mut token| {
+            token.start += start;
+            token.end += start;
+            token
+        }).collect();
+        let struct_names = self.items.iter().filter_map(|item| match &item.kind {
+            StmtKind::Struct(name, _) => Some(name.clone()),
+            _ => None,
+        });
+        Ok(Parser::new(tokens).with_struct_names(struct_names))
+    }
+
+    fn parse(&self, input: &str) -> Result<Input, Vec<CompileError>> {
+        if let Ok(expr) = self.parser(input)?.parse_expression() {
+            return Ok(Input::Expr(expr));
+        }
+        match self.parser(input)?.parse()?.kind {
+            StmtKind::Block(stmts) => Ok(Input::Stmts(stmts)),
+            _ => Ok(Input::Stmts(vec![])),
+        }
+    }
+
+    /// Parses an input into statements; an expression becomes a `Function` statement.
+    fn parse_stmts(&self, input: &str) -> Result<(Vec<Stmt>, bool), Vec<CompileError>> {
+        Ok(match self.parse(input)? {
+            Input::Expr(expr) => {
+                let span = expr.span;
+                (vec![Stmt::new(StmtKind::Function(Box::new(expr)), span)], true)
+            }
+            Input::Stmts(stmts) => (stmts, false),
+        })
+    }
+
+    fn type_of_expr(&self, expr: Expr) -> Result<ValueType, Vec<CompileError>> {
+        let span = expr.span;
+        let Checked { program, .. } = self.check(vec![Stmt::new(StmtKind::Function(Box::new(expr)), span)])?;
+        match &input_stmts(&program, 1)[0].kind {
+            StmtKind::Function(expr) => Ok(expr.typ.clone().unwrap_or(ValueType::Unit)),
+            _ => Ok(ValueType::Unit),
+        }
+    }
+
+    /// Checks the statements of an input after the definitions and variables of the session, leaving
+    /// out those the input defines again.
+    fn check(&self, input: Vec<Stmt>) -> Result<Checked, Vec<CompileError>> {
+        let mut stmts: Vec<Stmt> = self.items.iter().filter(|item| !replaced_by(item, &input)).cloned().collect();
+        let variables: Vec<usize> = (0..self.variables.len())
+            .filter(|index| !input.iter().any(|stmt| defined_name(stmt) == Some(&self.variables[*index].name)))
+            .collect();
+        let first_variable = stmts.len();
+        for index in &variables {
+            let Variable { name, typ, span, .. } = &self.variables[*index];
+            let declaration = StmtKind::VarDef(name.clone(), Box::new(self.placeholder(typ, *span)), typ.clone());
+            stmts.push(Stmt::new(declaration, *span));
+        }
+        stmts.extend(input);
+        let input_span = self.inputs.last().map_or(Span::default(), |(start, text)| Span::new(*start, start + text.len()));
+        let mut program = Stmt::new(StmtKind::Block(stmts), input_span);
+        let symbols = check(&mut program)?;
+
+        // The declarations only tell the checker about the variables, whose values are already known.
+        let StmtKind::Block(stmts) = &mut program.kind else {
+            unreachable!("the program was built as a block");
+        };
+        let declarations: Vec<Stmt> = stmts.drain(first_variable..first_variable + variables.len()).collect();
+        let variables = variables.into_iter()
+            .zip(declarations.iter().map(|declaration| declaration.symbol.expect("checked declarations have a symbol")))
+            .collect();
+        Ok(Checked { program, symbols, variables })
+    }
+
+    /// An expression of type `typ` for the declaration of a variable. It is checked but never run.
+    fn placeholder(&self, typ: &ValueType, span: Span) -> Expr {
+        let kind = match typ {
+            ValueType::Number => ExprKind::Value(Value::Number(0.0)),
+            ValueType::Int(_) => ExprKind::Value(Value::Integer(0)),
+            ValueType::String => ExprKind::Value(Value::String(String::new())),
+            ValueType::Bool | ValueType::Unit => ExprKind::Value(Value::Bool(false)),
+            ValueType::Struct(name) => ExprKind::New(name.clone(), self.struct_fields(name).iter().map(|field| FieldInit {
+                name: field.name.clone(),
+                value: self.placeholder(&field.typ, span),
+            }).collect()),
+            ValueType::Array(element, length) => ExprKind::Array((0..length.unwrap_or(0)).map(|_| self.placeholder(element, span)).collect()),
+        };
+        Expr::new(kind, span)
+    }
+
+    fn struct_fields(&self, name: &str) -> &[TypedArgument] {
+        self.items.iter().find_map(|item| match &item.kind {
+            StmtKind::Struct(struct_name, fields) if struct_name == name => Some(&fields[..]),
+            _ => None,
+        }).unwrap_or(&[])
+    }
+
+    /// Shows a value the way it would be written in Lamp.
+    fn describe(&self, value: &RuntimeValue, typ: &ValueType) -> String {
+        match (value, typ) {
+            (RuntimeValue::String(s), _) => format!("{:?}", s),
+            (RuntimeValue::Struct(fields), ValueType::Struct(name)) => {
+                let types = self.struct_fields(name);
+                let fields: Vec<String> = fields.iter().map(|(field, value)| {
+                    let typ = types.iter().find(|typed| typed.name == *field).map_or(ValueType::Unit, |typed| typed.typ.clone());
+                    format!("{}: {}", field, self.describe(value, &typ))
+                }).collect();
+                format!("new {}({})", name, fields.join(", "))
+            }
+            (RuntimeValue::Array(items), ValueType::Array(element, _)) => {
+                let items: Vec<String> = items.borrow().iter().map(|item| self.describe(item, element)).collect();
+                format!("[{}]", items.join(", "))
+            }
+            _ => display(value),
+        }
+    }
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The statements of the input at the end of a checked program.
+fn input_stmts(program: &Stmt, input_len: usize) -> &[Stmt] {
+    match &program.kind {
+        StmtKind::Block(stmts) => &stmts[stmts.len() - input_len..],
+        _ => &[],
+    }
+}
+
+fn is_item(stmt: &Stmt) -> bool {
+    matches!(stmt.kind, StmtKind::Use(_) | StmtKind::Struct(..) | StmtKind::Impl(..) | StmtKind::FunctionDef(..))
+}
+
+/// The name a top-level statement defines.
+fn defined_name(stmt: &Stmt) -> Option<&String> {
+    match &stmt.kind {
+        StmtKind::VarDef(name, ..) | StmtKind::FunctionDef(name, ..) | StmtKind::Struct(name, _) | StmtKind::Use(name) => Some(name),
+        _ => None,
+    }
+}
+
+/// Whether a definition of the session is defined again by an input. The methods of a struct go
+/// with it.
+fn replaced_by(item: &Stmt, input: &[Stmt]) -> bool {
+    let name = match &item.kind {
+        StmtKind::Impl(owner, _) => Some(owner),
+        _ => defined_name(item),
+    };
+    name.is_some_and(|name| input.iter().any(|stmt| is_item(stmt) && defined_name(stmt) == Some(name)))
+}
\ No newline at end of file
@@ -1,32 +1,127 @@
-﻿use super::value::{Value, ValueType};
+﻿use super::builtins::Builtin;
+use super::error::Span;
+use super::symbols::SymbolId;
+use super::value::{Value, ValueType};
 
 #[derive(Debug, Clone)]
-pub enum Expr {
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+    /// Filled in by the resolver for variable uses, calls and struct construction.
+    pub symbol: Option<SymbolId>,
+    /// Filled in by the type checker.
+    pub typ: Option<ValueType>,
+}
+
+#[derive(Debug, Clone)]
+pub enum ExprKind {
     Value(Value),
     VarUse(String),
     Binary(char, Box<Expr>, Box<Expr>),
     Condition(String, Box<Expr>, Box<Expr>),
     Unary(char, Box<Expr>),
     Functional(String, Vec<Expr>),
-    New(String, Vec<TypedArgument>),
+    /// A call of a function that is always in scope, such as `len(a)`.
+    Builtin(Builtin, Vec<Expr>),
+    New(String, Vec<FieldInit>),
+    /// Reads a field of a struct value, e.g. `p.x`.
+    Field(Box<Expr>, String),
+    /// Calls a method on a struct value, e.g. `p.len()`.
+    MethodCall(Box<Expr>, String, Vec<Expr>),
+    Convert(ValueType, Box<Expr>),
+    /// An array literal, e.g. `[1, 2, 3]`.
+    Array(Vec<Expr>),
+    /// Reads an element of an array, e.g. `a[i]`.
+    Index(Box<Expr>, Box<Expr>),
+    /// A string literal with `${...}` parts.
+    Interpolation(Vec<InterpolationPart>),
+}
+
+#[derive(Debug, Clone)]
+pub enum InterpolationPart {
+    Text(String),
+    Expr(Expr),
+}
+
+/// A `name: value` pair in a struct construction.
+#[derive(Debug, Clone)]
+pub struct FieldInit {
+    pub name: String,
+    pub value: Expr,
+}
+
+/// What a `for` loop iterates over.
+#[derive(Debug, Clone)]
+pub enum Iterable {
+    /// The integers `start..end`, excluding `end`.
+    Range(Box<Expr>, Box<Expr>),
+    /// The elements of an array.
+    Array(Box<Expr>),
 }
 
 #[derive(Debug, Clone)]
 pub struct TypedArgument {
     pub name: String,
     pub typ: ValueType,
+    /// Filled in by the resolver for function and method parameters.
+    pub symbol: Option<SymbolId>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+    /// Filled in by the resolver for variable, function and struct definitions.
+    pub symbol: Option<SymbolId>,
+    /// The `///` doc comment of a function or struct definition.
+    pub doc: Option<String>,
 }
 
 #[derive(Debug, Clone)]
-pub enum Stmt {
+pub enum StmtKind {
     VarDef(String, Box<Expr>, ValueType),
-    Assign(String, Box<Expr>),
+    /// Assigns to a variable, a field or an array element; the target is a `VarUse`, `Field` or
+    /// `Index` expression.
+    Assign(Box<Expr>, Box<Expr>),
     If(Box<Expr>, Box<Stmt>, Box<Option<Stmt>>),
     Block(Vec<Stmt>),
     While(Box<Expr>, Box<Stmt>),
+    /// `for name in ... { }`; the symbol of the statement is the loop variable.
+    For(String, Iterable, Box<Stmt>),
+    /// A `while` or `for` loop with a label, e.g. `outer: while ...`.
+    Labeled(String, Box<Stmt>),
+    /// Leaves the innermost loop, or the loop with the given label.
+    Break(Option<String>),
+    /// Starts the next iteration of the innermost loop, or of the loop with the given label.
+    Continue(Option<String>),
     Function(Box<Expr>),
     FunctionDef(String, Vec<TypedArgument>, Box<Stmt>, ValueType),
-    Return(Box<Expr>),
+    /// Leaves the function, with a value unless the function returns `unit`.
+    Return(Option<Box<Expr>>),
     Use(String),
     Struct(String, Vec<TypedArgument>),
+    /// Methods of a struct; each is a `FunctionDef` whose first parameter is `self`.
+    Impl(String, Vec<Stmt>),
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Self { kind, span, symbol: None, typ: None }
+    }
+
+    /// Whether the expression names a storage location whose address can be taken.
+    pub fn is_place(&self) -> bool {
+        match &self.kind {
+            ExprKind::VarUse(_) => true,
+            ExprKind::Field(object, _) => object.is_place(),
+            ExprKind::Index(_, _) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Stmt {
+    pub fn new(kind: StmtKind, span: Span) -> Self {
+        Self { kind, span, symbol: None, doc: None }
+    }
 }
\ No newline at end of file
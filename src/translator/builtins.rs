@@ -0,0 +1,157 @@
+﻿use std::fmt;
+
+use super::value::ValueType;
+
+pub const MODULES: [&str; 2] = ["io", "math"];
+
+#[derive(Debug, Clone)]
+pub struct Signature {
+    pub params: Vec<ValueType>,
+    pub variadic: bool,
+    pub return_type: ValueType,
+    /// Set for functions whose first argument is a format string for the remaining ones.
+    pub format: Option<FormatArgs>,
+}
+
+/// How the arguments after a format string are passed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FormatArgs {
+    /// Values substituted for the placeholders, as in `print`.
+    Values,
+    /// Variables that `input` reads into, passed by pointer.
+    Targets,
+}
+
+impl Signature {
+    pub fn new(params: Vec<ValueType>, return_type: ValueType) -> Self {
+        Self { params, variadic: false, return_type, format: None }
+    }
+
+    pub fn format(args: FormatArgs, return_type: ValueType) -> Self {
+        Self { params: vec![ValueType::String], variadic: true, return_type, format: Some(args) }
+    }
+}
+
+/// A `{s}`-style placeholder of the io runtime's format strings.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Placeholder {
+    String,
+    Number,
+    Integer,
+}
+
+impl Placeholder {
+    /// Finds the placeholders of a format string the way `format_internal` in `lib/src/io.c` does:
+    /// `{`, any one character, `}`. Unknown letters are returned as errors.
+    pub fn parse_all(fmt: &str) -> Vec<Result<Placeholder, char>> {
+        let chars: Vec<char> = fmt.chars().collect();
+        let mut placeholders = vec![];
+        let mut i = 0;
+        while i < chars.len() {
+            if chars[i] == '{' && i + 2 < chars.len() && chars[i + 2] == '}' {
+                placeholders.push(match chars[i + 1] {
+                    's' => Ok(Placeholder::String),
+                    'f' => Ok(Placeholder::Number),
+                    'i' => Ok(Placeholder::Integer),
+                    other => Err(other),
+                });
+                i += 3;
+            } else {
+                i += 1;
+            }
+        }
+        placeholders
+    }
+
+    pub fn describe(self) -> &'static str {
+        match self {
+            Placeholder::String => "a `string`",
+            Placeholder::Number => "a `number`",
+            Placeholder::Integer => "an integer",
+        }
+    }
+
+    pub fn accepts(self, typ: &ValueType) -> bool {
+        match self {
+            Placeholder::String => *typ == ValueType::String,
+            Placeholder::Number => *typ == ValueType::Number,
+            Placeholder::Integer => matches!(typ, ValueType::Int(_)),
+        }
+    }
+
+    /// The placeholder for values of a type, if the runtime can format it.
+    pub fn for_type(typ: &ValueType) -> Option<Placeholder> {
+        match typ {
+            ValueType::String => Some(Placeholder::String),
+            ValueType::Number => Some(Placeholder::Number),
+            ValueType::Int(_) => Some(Placeholder::Integer),
+            ValueType::Bool | ValueType::Unit | ValueType::Struct(_) | ValueType::Array(_, _) => None,
+        }
+    }
+}
+
+impl fmt::Display for Placeholder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let letter = match self {
+            Placeholder::String => 's',
+            Placeholder::Number => 'f',
+            Placeholder::Integer => 'i',
+        };
+        write!(f, "{{{}}}", letter)
+    }
+}
+
+/// Functions that are always in scope. They work on any array, which a `Signature` cannot express,
+/// so the type checker handles each of them itself.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Builtin {
+    /// `len(a)`, the number of elements of an array.
+    Len,
+    /// `push(a, x)`, appends an element to an array.
+    Push,
+}
+
+impl Builtin {
+    pub fn from_name(name: &str) -> Option<Builtin> {
+        match name {
+            "len" => Some(Builtin::Len),
+            "push" => Some(Builtin::Push),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Builtin::Len => "len",
+            Builtin::Push => "push",
+        }
+    }
+}
+
+/// Functions declared by the headers in `lib/include` for a module imported with `use`.
+pub fn module_functions(module: &str) -> Option<Vec<(&'static str, Signature)>> {
+    use ValueType::*;
+    match module {
+        "io" => Some(vec![
+            ("format", Signature::format(FormatArgs::Values, String)),
+            ("print", Signature::format(FormatArgs::Values, Unit)),
+            ("println", Signature::format(FormatArgs::Values, Unit)),
+            ("input", Signature::format(FormatArgs::Targets, Unit)),
+        ]),
+        "math" => {
+            let mut functions: Vec<(&'static str, Signature)> = [
+                "square", "abs_d", "sqrt", "ln", "log2", "log10", "sin", "cos", "tan", "round", "floor", "ceil",
+            ].into_iter().map(|name| (name, Signature::new(vec![Number], Number))).collect();
+            functions.push(("pow", Signature::new(vec![Number, Number], Number)));
+            Some(functions)
+        }
+        _ => None,
+    }
+}
+
+/// Finds the module that provides a function, used to suggest a missing `use`.
+pub fn module_of(function: &str) -> Option<&'static str> {
+    MODULES.into_iter().find(|module| {
+        module_functions(module).unwrap_or_default().iter().any(|(name, _)| *name == function)
+    })
+}
\ No newline at end of file
@@ -0,0 +1,415 @@
+//! The bytecode run by the VM, its `.lampc` file format and a disassembler.
+//!
+//! A program is a list of functions, the first of which is the top-level code, and a constant pool
+//! shared by all of them. Instructions work on an operand stack; the locals of a call, starting with
+//! its parameters, live at the bottom of the call's part of the stack.
+
+use std::fmt::{self, Write};
+
+use super::error::Span;
+use super::value::IntType;
+
+/// The first bytes of a `.lampc` file, followed by `FORMAT_VERSION`.
+pub const MAGIC: &[u8; 5] = b"LAMPC";
+pub const FORMAT_VERSION: u8 = 1;
+
+const INT_TYPES: [IntType; 8] = [
+    IntType::I8, IntType::I16, IntType::I32, IntType::I64, IntType::U8, IntType::U16, IntType::U32, IntType::U64,
+];
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Op {
+    /// Pushes a constant from the pool.
+    Constant(u32),
+    Unit,
+    Pop,
+    GetLocal(u32),
+    SetLocal(u32),
+    /// Pops a value and stores it in a field of a local struct; the operand after the local is a
+    /// `Names` constant with the path of fields.
+    SetLocalField(u32, u32),
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Neg,
+    Not,
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessEqual,
+    GreaterEqual,
+    ToNumber,
+    ToInt(IntType),
+    /// Jumps to an instruction of the current function.
+    Jump(u32),
+    /// Pops a `bool` and jumps if it is false.
+    JumpIfFalse(u32),
+    /// Calls a function with the given number of arguments, which are on top of the stack.
+    Call(u32, u32),
+    /// Calls a method whose receiver is the first argument. When it returns, the final value of the
+    /// receiver is pushed after the result, so the caller can store it back.
+    CallMethod(u32, u32),
+    /// Calls a function of the `io` or `math` module, named by a string constant.
+    CallNative(u32, u32),
+    /// Pops a format string and the current values of the variables it reads into, and pushes their
+    /// new values.
+    Input(u32),
+    Return,
+    /// Pops the values of the fields named by a `Names` constant and pushes the struct.
+    MakeStruct(u32),
+    /// Replaces a struct with the value of the field named by a string constant.
+    GetField(u32),
+    /// Pops the given number of elements and pushes an array of them.
+    MakeArray(u32),
+    /// Pops an index and an array and pushes the element.
+    GetIndex,
+    /// Pops an index, an array and a value, and stores the value in the element.
+    SetIndex,
+    /// Like `SetIndex`, but stores into a field of the element, along the path of a `Names` constant.
+    SetIndexField(u32),
+    /// Replaces an array with its length.
+    Len,
+    /// Pops a value and an array, appends the value and pushes `unit`.
+    Push,
+    /// Pops the given number of values and pushes the string of their text, one after the other.
+    Concat(u32),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constant {
+    Number(f64),
+    Int(IntType, i128),
+    String(String),
+    Bool(bool),
+    /// Field names, for building structs and storing into fields.
+    Names(Vec<String>),
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub arity: u32,
+    /// Number of locals, including the parameters.
+    pub locals: u32,
+    pub code: Vec<Op>,
+    /// The source of each instruction, for runtime errors.
+    pub spans: Vec<Span>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Program {
+    pub constants: Vec<Constant>,
+    /// The top-level code first, then every function and method.
+    pub functions: Vec<Function>,
+}
+
+/// Why a `.lampc` file could not be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatError(pub String);
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid bytecode file: {}", self.0)
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+impl Program {
+    /// Serializes the program in the `.lampc` format. Numbers are little endian and strings are
+    /// UTF-8 prefixed with their length.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.push(FORMAT_VERSION);
+        write_u32(&mut out, self.constants.len() as u32);
+        for constant in &self.constants {
+            write_constant(&mut out, constant);
+        }
+        write_u32(&mut out, self.functions.len() as u32);
+        for function in &self.functions {
+            write_string(&mut out, &function.name);
+            write_u32(&mut out, function.arity);
+            write_u32(&mut out, function.locals);
+            write_u32(&mut out, function.code.len() as u32);
+            for (op, span) in function.code.iter().zip(&function.spans) {
+                write_op(&mut out, *op);
+                write_u32(&mut out, span.start as u32);
+                write_u32(&mut out, span.end as u32);
+            }
+        }
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Program, FormatError> {
+        let mut reader = Reader { bytes, pos: 0 };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(FormatError("not a Lamp bytecode file".to_string()));
+        }
+        let version = reader.u8()?;
+        if version != FORMAT_VERSION {
+            return Err(FormatError(format!("unsupported version {}, expected {}", version, FORMAT_VERSION)));
+        }
+        let mut program = Program::default();
+        for _ in 0..reader.u32()? {
+            program.constants.push(reader.constant()?);
+        }
+        for _ in 0..reader.u32()? {
+            let name = reader.string()?;
+            let arity = reader.u32()?;
+            let locals = reader.u32()?;
+            let mut function = Function { name, arity, locals, code: vec![], spans: vec![] };
+            for _ in 0..reader.u32()? {
+                function.code.push(reader.op()?);
+                function.spans.push(Span::new(reader.u32()? as usize, reader.u32()? as usize));
+            }
+            // Every local besides the parameters is set by an instruction, so a larger count can only
+            // come from a corrupted file, and would make a call allocate that many slots.
+            if locals as usize > arity as usize + function.code.len() {
+                return Err(FormatError(format!("function `{}` has more locals than its code uses", function.name)));
+            }
+            program.functions.push(function);
+        }
+        if reader.pos != bytes.len() {
+            return Err(FormatError("unexpected data after the last function".to_string()));
+        }
+        Ok(program)
+    }
+}
+
+fn write_u32(out: &mut Vec<u8>, n: u32) {
+    out.extend_from_slice(&n.to_le_bytes());
+}
+
+fn write_string(out: &mut Vec<u8>, s: &str) {
+    write_u32(out, s.len() as u32);
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn int_type_code(typ: IntType) -> u8 {
+    INT_TYPES.iter().position(|t| *t == typ).expect("every integer type is listed") as u8
+}
+
+fn write_constant(out: &mut Vec<u8>, constant: &Constant) {
+    match constant {
+        Constant::Number(n) => {
+            out.push(0);
+            out.extend_from_slice(&n.to_le_bytes());
+        }
+        Constant::Int(typ, n) => {
+            out.push(1);
+            out.push(int_type_code(*typ));
+            out.extend_from_slice(&n.to_le_bytes());
+        }
+        Constant::String(s) => {
+            out.push(2);
+            write_string(out, s);
+        }
+        Constant::Bool(b) => {
+            out.push(3);
+            out.push(*b as u8);
+        }
+        Constant::Names(names) => {
+            out.push(4);
+            write_u32(out, names.len() as u32);
+            for name in names {
+                write_string(out, name);
+            }
+        }
+    }
+}
+
+/// Writes an opcode byte followed by the operands.
+fn write_op(out: &mut Vec<u8>, op: Op) {
+    let (code, operands): (u8, &[u32]) = match op {
+        Op::Constant(i) => (0, &[i]),
+        Op::Unit => (1, &[]),
+        Op::Pop => (2, &[]),
+        Op::GetLocal(slot) => (3, &[slot]),
+        Op::SetLocal(slot) => (4, &[slot]),
+        Op::SetLocalField(slot, path) => (5, &[slot, path]),
+        Op::Add => (6, &[]),
+        Op::Sub => (7, &[]),
+        Op::Mul => (8, &[]),
+        Op::Div => (9, &[]),
+        Op::Rem => (10, &[]),
+        Op::Neg => (11, &[]),
+        Op::Not => (12, &[]),
+        Op::Equal => (13, &[]),
+        Op::NotEqual => (14, &[]),
+        Op::Less => (15, &[]),
+        Op::Greater => (16, &[]),
+        Op::LessEqual => (17, &[]),
+        Op::GreaterEqual => (18, &[]),
+        Op::ToNumber => (19, &[]),
+        Op::ToInt(typ) => {
+            out.extend_from_slice(&[20, int_type_code(typ)]);
+            return;
+        }
+        Op::Jump(target) => (21, &[target]),
+        Op::JumpIfFalse(target) => (22, &[target]),
+        Op::Call(function, argc) => (23, &[function, argc]),
+        Op::CallMethod(function, argc) => (24, &[function, argc]),
+        Op::CallNative(name, argc) => (25, &[name, argc]),
+        Op::Input(count) => (26, &[count]),
+        Op::Return => (27, &[]),
+        Op::MakeStruct(names) => (28, &[names]),
+        Op::GetField(name) => (29, &[name]),
+        Op::MakeArray(count) => (30, &[count]),
+        Op::GetIndex => (31, &[]),
+        Op::SetIndex => (32, &[]),
+        Op::SetIndexField(path) => (33, &[path]),
+        Op::Len => (34, &[]),
+        Op::Push => (35, &[]),
+        Op::Concat(count) => (36, &[count]),
+    };
+    out.push(code);
+    for operand in operands {
+        write_u32(out, *operand);
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], FormatError> {
+        let bytes = self.bytes.get(self.pos..self.pos + len).ok_or_else(|| FormatError("unexpected end of file".to_string()))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, FormatError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, FormatError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().expect("took 4 bytes")))
+    }
+
+    fn string(&mut self) -> Result<String, FormatError> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| FormatError("a string is not valid UTF-8".to_string()))
+    }
+
+    fn int_type(&mut self) -> Result<IntType, FormatError> {
+        let code = self.u8()?;
+        INT_TYPES.get(code as usize).copied().ok_or_else(|| FormatError(format!("unknown integer type {}", code)))
+    }
+
+    fn constant(&mut self) -> Result<Constant, FormatError> {
+        Ok(match self.u8()? {
+            0 => Constant::Number(f64::from_le_bytes(self.take(8)?.try_into().expect("took 8 bytes"))),
+            1 => {
+                let typ = self.int_type()?;
+                Constant::Int(typ, i128::from_le_bytes(self.take(16)?.try_into().expect("took 16 bytes")))
+            }
+            2 => Constant::String(self.string()?),
+            3 => Constant::Bool(self.u8()? != 0),
+            4 => {
+                let mut names = vec![];
+                for _ in 0..self.u32()? {
+                    names.push(self.string()?);
+                }
+                Constant::Names(names)
+            }
+            tag => return Err(FormatError(format!("unknown constant kind {}", tag))),
+        })
+    }
+
+    fn op(&mut self) -> Result<Op, FormatError> {
+        Ok(match self.u8()? {
+            0 => Op::Constant(self.u32()?),
+            1 => Op::Unit,
+            2 => Op::Pop,
+            3 => Op::GetLocal(self.u32()?),
+            4 => Op::SetLocal(self.u32()?),
+            5 => Op::SetLocalField(self.u32()?, self.u32()?),
+            6 => Op::Add,
+            7 => Op::Sub,
+            8 => Op::Mul,
+            9 => Op::Div,
+            10 => Op::Rem,
+            11 => Op::Neg,
+            12 => Op::Not,
+            13 => Op::Equal,
+            14 => Op::NotEqual,
+            15 => Op::Less,
+            16 => Op::Greater,
+            17 => Op::LessEqual,
+            18 => Op::GreaterEqual,
+            19 => Op::ToNumber,
+            20 => Op::ToInt(self.int_type()?),
+            21 => Op::Jump(self.u32()?),
+            22 => Op::JumpIfFalse(self.u32()?),
+            23 => Op::Call(self.u32()?, self.u32()?),
+            24 => Op::CallMethod(self.u32()?, self.u32()?),
+            25 => Op::CallNative(self.u32()?, self.u32()?),
+            26 => Op::Input(self.u32()?),
+            27 => Op::Return,
+            28 => Op::MakeStruct(self.u32()?),
+            29 => Op::GetField(self.u32()?),
+            30 => Op::MakeArray(self.u32()?),
+            31 => Op::GetIndex,
+            32 => Op::SetIndex,
+            33 => Op::SetIndexField(self.u32()?),
+            34 => Op::Len,
+            35 => Op::Push,
+            36 => Op::Concat(self.u32()?),
+            code => return Err(FormatError(format!("unknown opcode {}", code))),
+        })
+    }
+}
+
+/// Lists the constants and the instructions of every function, one per line:
+///
+/// ```text
+/// fn main #0 (0 params, 1 locals)
+///   0000  Constant 0          ; 1
+///   0001  SetLocal 0
+/// ```
+pub fn disassemble(program: &Program) -> String {
+    let mut text = String::from("constants\n");
+    for (i, constant) in program.constants.iter().enumerate() {
+        writeln!(text, "  {:4}  {}", i, describe_constant(constant)).expect("writing to a String cannot fail");
+    }
+    for (index, function) in program.functions.iter().enumerate() {
+        writeln!(text, "\nfn {} #{} ({} params, {} locals)", function.name, index, function.arity, function.locals)
+            .expect("writing to a String cannot fail");
+        for (i, op) in function.code.iter().enumerate() {
+            let operation = describe_op(*op);
+            let comment = match op {
+                Op::Constant(c) | Op::SetLocalField(_, c) | Op::CallNative(c, _) | Op::MakeStruct(c)
+                | Op::GetField(c) | Op::SetIndexField(c) => program.constants.get(*c as usize).map(describe_constant),
+                Op::Call(f, _) | Op::CallMethod(f, _) => program.functions.get(*f as usize).map(|f| f.name.clone()),
+                _ => None,
+            };
+            match comment {
+                Some(comment) => writeln!(text, "  {:04}  {:<20}; {}", i, operation, comment),
+                None => writeln!(text, "  {:04}  {}", i, operation),
+            }.expect("writing to a String cannot fail");
+        }
+    }
+    text
+}
+
+fn describe_op(op: Op) -> String {
+    match op {
+        Op::ToInt(typ) => format!("ToInt {}", typ),
+        _ => format!("{:?}", op).replace('(', " ").replace(", ", " ").replace(')', ""),
+    }
+}
+
+fn describe_constant(constant: &Constant) -> String {
+    match constant {
+        Constant::Number(n) => format!("{:?}", n),
+        Constant::Int(typ, n) => format!("{}: {}", n, typ),
+        Constant::String(s) => format!("{:?}", s),
+        Constant::Bool(b) => b.to_string(),
+        Constant::Names(names) => format!("[{}]", names.join(", ")),
+    }
+}
\ No newline at end of file
@@ -0,0 +1,655 @@
+use std::collections::HashMap;
+
+use super::ast::{Expr, ExprKind, FieldInit, InterpolationPart, Iterable, Stmt, StmtKind, TypedArgument};
+use super::builtins::{Builtin, FormatArgs, Placeholder};
+use super::error::{CompileError, Span};
+use super::symbols::{SymbolId, SymbolKind, SymbolTable};
+use super::value::{IntType, Value, ValueType};
+
+pub struct TypeChecker<'a> {
+    symbols: &'a SymbolTable,
+    /// Types inferred for the variables of `for` loops.
+    loop_variables: HashMap<SymbolId, ValueType>,
+    return_type: Option<ValueType>,
+    errors: Vec<CompileError>,
+}
+
+impl<'a> TypeChecker<'a> {
+    pub fn new(symbols: &'a SymbolTable) -> Self {
+        Self {
+            symbols,
+            loop_variables: HashMap::new(),
+            return_type: None,
+            errors: vec![],
+        }
+    }
+
+    pub fn check(&mut self, program: &mut Stmt) -> Result<(), Vec<CompileError>> {
+        self.check_statement(program);
+
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(std::mem::take(&mut self.errors))
+        }
+    }
+
+    fn check_statement(&mut self, stmt: &mut Stmt) {
+        match &mut stmt.kind {
+            StmtKind::VarDef(name, value, typ) => {
+                let found = self.check_expr(value, Some(typ));
+                if *typ == ValueType::Unit {
+                    self.errors.push(CompileError::typ(format!("variable `{}` cannot have type `unit`", name), stmt.span));
+                } else {
+                    self.expect(typ, found, value.span);
+                }
+            }
+            StmtKind::Assign(target, value) => {
+                if let ExprKind::VarUse(name) = &target.kind
+                    && let Some(id) = target.symbol
+                    && matches!(self.symbols.get(id).kind, SymbolKind::LoopVariable) {
+                    self.errors.push(CompileError::typ(format!("cannot assign to loop variable `{}`", name), target.span)
+                        .with_help("declare a new variable with `var` to hold a changed value"));
+                }
+                let typ = self.check_expr(target, None);
+                let found = self.check_expr(value, typ.as_ref());
+                if let Some(typ) = typ {
+                    self.expect(&typ, found, value.span);
+                }
+            }
+            StmtKind::If(cond, body, else_body) => {
+                self.check_condition(cond);
+                self.check_statement(body);
+                if let Some(else_body) = else_body.as_mut() {
+                    self.check_statement(else_body);
+                }
+            }
+            StmtKind::While(cond, body) => {
+                self.check_condition(cond);
+                self.check_statement(body);
+            }
+            StmtKind::For(_, iterable, body) => {
+                let typ = self.check_iterable(iterable);
+                if let (Some(id), Some(typ)) = (stmt.symbol, typ) {
+                    self.loop_variables.insert(id, typ);
+                }
+                self.check_statement(body);
+            }
+            StmtKind::Labeled(_, body) => self.check_statement(body),
+            StmtKind::Break(_) | StmtKind::Continue(_) => {}
+            StmtKind::Block(stmts) => {
+                for stmt in stmts {
+                    self.check_statement(stmt);
+                }
+            }
+            StmtKind::Function(expr) => {
+                self.check_expr(expr, None);
+            }
+            StmtKind::FunctionDef(name, args, body, return_type) => {
+                self.check_function(name, args, body, return_type.clone(), stmt.span);
+            }
+            StmtKind::Return(Some(value)) => {
+                let return_type = self.return_type.clone();
+                let found = self.check_expr(value, return_type.as_ref());
+                match return_type {
+                    None => self.errors.push(CompileError::syntax("`return` outside of a function", stmt.span)),
+                    Some(ValueType::Unit) => self.errors.push(CompileError::typ("a function returning `unit` cannot return a value", value.span)),
+                    Some(expected) => self.expect(&expected, found, value.span),
+                }
+            }
+            StmtKind::Return(None) => match &self.return_type {
+                None => self.errors.push(CompileError::syntax("`return` outside of a function", stmt.span)),
+                Some(ValueType::Unit) => {}
+                Some(expected) => self.errors.push(CompileError::typ(format!("`return` without a value in a function returning `{}`", expected), stmt.span)),
+            },
+            StmtKind::Use(_) => {}
+            StmtKind::Impl(_, methods) => {
+                for method in methods.iter_mut() {
+                    self.check_statement(method);
+                }
+            }
+            StmtKind::Struct(name, fields) => {
+                for field in fields.iter() {
+                    if field.typ == ValueType::Unit {
+                        self.errors.push(CompileError::typ(format!("field `{}` of struct `{}` cannot have type `unit`", field.name, name), stmt.span));
+                    }
+                }
+                if self.contains_struct(fields, name, &mut vec![]) {
+                    self.errors.push(CompileError::typ(format!("recursive struct `{}` has infinite size", name), stmt.span)
+                        .with_note("a struct holds its fields by value, so it cannot contain itself"));
+                }
+            }
+        }
+    }
+
+    fn check_function(&mut self, name: &str, args: &[TypedArgument], body: &mut Stmt, return_type: ValueType, span: Span) {
+        for arg in args {
+            if arg.typ == ValueType::Unit {
+                self.errors.push(CompileError::typ(format!("parameter `{}` cannot have type `unit`", arg.name), span));
+            }
+        }
+        let outer = self.return_type.replace(return_type.clone());
+        self.check_statement(body);
+        self.return_type = outer;
+
+        if return_type != ValueType::Unit && !Self::always_returns(body) {
+            self.errors.push(CompileError::typ(format!("function `{}` does not return a value on every path", name), span)
+                .with_note(format!("the function is declared to return `{}`", return_type)));
+        }
+    }
+
+    /// Whether a struct with these fields contains the struct `target`, directly or through other
+    /// struct fields. `visited` guards against cycles that do not involve `target`.
+    fn contains_struct(&self, fields: &[TypedArgument], target: &str, visited: &mut Vec<String>) -> bool {
+        fields.iter().any(|field| match &field.typ {
+            ValueType::Struct(name) if name == target => true,
+            ValueType::Struct(name) if !visited.contains(name) => {
+                visited.push(name.clone());
+                self.symbols.struct_fields(name).is_some_and(|fields| self.contains_struct(fields, target, visited))
+            }
+            _ => false,
+        })
+    }
+
+    fn always_returns(stmt: &Stmt) -> bool {
+        match &stmt.kind {
+            StmtKind::Return(_) => true,
+            StmtKind::Block(stmts) => stmts.iter().any(Self::always_returns),
+            StmtKind::If(_, body, else_body) => match else_body.as_ref() {
+                Some(else_body) => Self::always_returns(body) && Self::always_returns(else_body),
+                None => false,
+            },
+            // `while true` only ends through a `break`, so without one the code after it never runs.
+            StmtKind::While(condition, body) => Self::is_true(condition) && !Self::breaks_out(body, None, false),
+            StmtKind::Labeled(label, body) => match &body.kind {
+                StmtKind::While(condition, body) => Self::is_true(condition) && !Self::breaks_out(body, Some(label), false),
+                _ => Self::always_returns(body),
+            },
+            _ => false,
+        }
+    }
+
+    fn is_true(condition: &Expr) -> bool {
+        matches!(condition.kind, ExprKind::Value(Value::Bool(true)))
+    }
+
+    /// Whether a loop body contains a `break` out of the loop, which has the label `label`.
+    /// `nested` is set inside inner loops, where only a labeled `break` reaches the loop.
+    fn breaks_out(stmt: &Stmt, label: Option<&str>, nested: bool) -> bool {
+        match &stmt.kind {
+            StmtKind::Break(None) => !nested,
+            StmtKind::Break(Some(target)) => label == Some(target.as_str()),
+            StmtKind::Block(stmts) => stmts.iter().any(|stmt| Self::breaks_out(stmt, label, nested)),
+            StmtKind::If(_, body, else_body) => {
+                Self::breaks_out(body, label, nested) || else_body.as_ref().as_ref().is_some_and(|else_body| Self::breaks_out(else_body, label, nested))
+            }
+            StmtKind::While(_, body) | StmtKind::For(_, _, body) => Self::breaks_out(body, label, true),
+            // An inner loop with the same label hides the outer one.
+            StmtKind::Labeled(inner, body) => Self::breaks_out(body, label.filter(|label| label != inner), nested),
+            _ => false,
+        }
+    }
+
+    /// Checks what a `for` loop iterates over and returns the type of its variable.
+    fn check_iterable(&mut self, iterable: &mut Iterable) -> Option<ValueType> {
+        match iterable {
+            Iterable::Range(start, end) => {
+                let (l, r) = self.check_operands(start, end, None);
+                let (l, r) = (l?, r?);
+                for (typ, span) in [(&l, start.span), (&r, end.span)] {
+                    if !matches!(typ, ValueType::Int(_)) {
+                        self.errors.push(CompileError::typ(format!("range bounds must be integers, found `{}`", typ), span));
+                        return None;
+                    }
+                }
+                if l != r {
+                    self.errors.push(CompileError::typ(format!("range bounds have different types `{}` and `{}`", l, r), start.span.merge(end.span))
+                        .with_help(format!("convert one side explicitly, e.g. `{}(...)`", l)));
+                    return None;
+                }
+                Some(l)
+            }
+            Iterable::Array(array) => match self.check_expr(array, None)? {
+                ValueType::Array(element, _) => Some(*element),
+                typ => {
+                    self.errors.push(CompileError::typ(format!("cannot iterate over a value of type `{}`", typ), array.span)
+                        .with_help("iterate over an array, or over a range such as `0..10`"));
+                    None
+                }
+            },
+        }
+    }
+
+    fn check_condition(&mut self, cond: &mut Expr) {
+        if let Some(found) = self.check_expr(cond, Some(&ValueType::Bool)) && found != ValueType::Bool {
+            let mut err = CompileError::typ(format!("condition must be `bool`, found `{}`", found), cond.span);
+            if found.is_numeric() {
+                err = err.with_help("compare it explicitly, e.g. `x != 0`");
+            }
+            self.errors.push(err);
+        }
+    }
+
+    /// Checks an expression and records its type on it. `expected` is the type the context wants,
+    /// which decides the type of integer literals: `var b: u8 = 1` makes `1` a `u8`, while a literal
+    /// without such a hint is an `int`.
+    fn check_expr(&mut self, expr: &mut Expr, expected: Option<&ValueType>) -> Option<ValueType> {
+        let hint = expected.filter(|typ| typ.is_numeric()).cloned();
+        let typ = match &mut expr.kind {
+            ExprKind::Value(Value::Number(_)) => Some(ValueType::Number),
+            ExprKind::Value(Value::Integer(n)) => {
+                let typ = hint.unwrap_or(ValueType::Int(IntType::I32));
+                self.check_literal(i128::from(*n), &typ, expr.span);
+                Some(typ)
+            }
+            ExprKind::Value(Value::String(_)) => Some(ValueType::String),
+            ExprKind::Value(Value::Bool(_)) => Some(ValueType::Bool),
+            ExprKind::VarUse(_) => match expr.symbol.and_then(|id| self.loop_variables.get(&id)) {
+                Some(typ) => Some(typ.clone()),
+                None => self.symbols.variable_type(expr.symbol),
+            },
+            ExprKind::Binary(op, left, right) => {
+                let op = op.to_string();
+                let (l, r) = self.check_operands(left, right, hint.as_ref());
+                self.check_arithmetic(&op, left, l, right, r)
+            }
+            ExprKind::Condition(op, left, right) if op == "==" || op == "!=" => {
+                if let (Some(l), Some(r)) = self.check_operands(left, right, None) {
+                    if l != r {
+                        self.errors.push(CompileError::typ(format!("cannot compare `{}` with `{}`", l, r), expr.span));
+                    } else if l == ValueType::String {
+                        self.errors.push(CompileError::typ(format!("cannot compare strings with `{}`", op), expr.span)
+                            .with_note("strings are compared by address in the generated C"));
+                    } else if let ValueType::Struct(name) = l {
+                        self.errors.push(CompileError::typ(format!("cannot compare structs with `{}`", op), expr.span)
+                            .with_help(format!("compare the fields of `{}` instead", name)));
+                    } else if let ValueType::Array(_, _) = l {
+                        self.errors.push(CompileError::typ(format!("cannot compare arrays with `{}`", op), expr.span)
+                            .with_note("arrays are compared by address in the generated C"));
+                    }
+                }
+                Some(ValueType::Bool)
+            }
+            ExprKind::Condition(op, left, right) if op == "&&" || op == "||" => {
+                self.check_operand(op, left, &ValueType::Bool);
+                self.check_operand(op, right, &ValueType::Bool);
+                Some(ValueType::Bool)
+            }
+            ExprKind::Condition(op, left, right) => {
+                let (l, r) = self.check_operands(left, right, None);
+                let l = self.check_numeric(op, l, left.span);
+                let r = self.check_numeric(op, r, right.span);
+                if let (Some(l), Some(r)) = (&l, &r) && l != r {
+                    self.errors.push(CompileError::typ(format!("cannot compare `{}` with `{}`", l, r), expr.span)
+                        .with_help(format!("convert one side explicitly, e.g. `{}(...)`", l)));
+                }
+                Some(ValueType::Bool)
+            }
+            ExprKind::Unary('!', operand) => {
+                self.check_operand("!", operand, &ValueType::Bool);
+                Some(ValueType::Bool)
+            }
+            ExprKind::Unary(_, operand) => {
+                if let ExprKind::Value(Value::Integer(n)) = operand.kind {
+                    // Checked as a whole, so that `-128` fits in an `i8`.
+                    let typ = hint.unwrap_or(ValueType::Int(IntType::I32));
+                    self.check_literal(-i128::from(n), &typ, expr.span);
+                    operand.typ = Some(typ.clone());
+                    Some(typ)
+                } else {
+                    let typ = self.check_expr(operand, hint.as_ref());
+                    let typ = self.check_numeric("-", typ, operand.span);
+                    if let Some(ValueType::Int(int)) = &typ && !int.is_signed() {
+                        self.errors.push(CompileError::typ(format!("cannot apply `-` to a value of type `{}`", int), operand.span));
+                    }
+                    typ
+                }
+            }
+            ExprKind::Convert(typ, operand) => {
+                let typ = typ.clone();
+                let found = self.check_expr(operand, Some(&typ));
+                if let Some(found) = found && !found.is_numeric() {
+                    self.errors.push(CompileError::typ(format!("cannot convert a value of type `{}` to `{}`", found, typ), operand.span));
+                }
+                Some(typ)
+            }
+            ExprKind::Functional(name, args) => {
+                let name = name.clone();
+                self.check_call(&name, args, expr.symbol, expr.span)
+            }
+            ExprKind::MethodCall(receiver, name, args) => {
+                let found = self.check_expr(receiver, None);
+                let method = match &found {
+                    Some(ValueType::Struct(owner)) => {
+                        let method = self.symbols.method(owner, name);
+                        if method.is_none() {
+                            let mut err = CompileError::typ(format!("no method `{}` found for struct `{}`", name, owner), expr.span);
+                            if self.symbols.struct_fields(owner).is_some_and(|fields| fields.iter().any(|f| f.name == *name)) {
+                                err = err.with_note(format!("`{}` is a field; read it without parentheses", name));
+                            }
+                            self.errors.push(err);
+                        }
+                        method
+                    }
+                    Some(typ) => {
+                        self.errors.push(CompileError::typ(format!("no method `{}` on a value of type `{}`", name, typ), expr.span));
+                        None
+                    }
+                    None => None,
+                };
+                expr.symbol = method;
+                let name = name.clone();
+                self.check_call(&name, args, method, expr.span)
+            }
+            ExprKind::Builtin(builtin, args) => {
+                let builtin = *builtin;
+                self.check_builtin(builtin, args, expr.span)
+            }
+            ExprKind::Array(elements) => {
+                let (element, length) = match expected {
+                    Some(ValueType::Array(element, length)) => (Some(element.as_ref().clone()), *length),
+                    _ => (None, None),
+                };
+                self.check_array(elements, element, length, expr.span)
+            }
+            ExprKind::Index(array, index) => {
+                let found = self.check_expr(array, None);
+                self.check_index(index);
+                match found? {
+                    ValueType::Array(element, _) => Some(*element),
+                    typ => {
+                        self.errors.push(CompileError::typ(format!("cannot index into a value of type `{}`", typ), array.span));
+                        None
+                    }
+                }
+            }
+            ExprKind::Interpolation(parts) => {
+                for part in parts.iter_mut() {
+                    if let InterpolationPart::Expr(value) = part
+                        && let Some(typ) = self.check_expr(value, None)
+                        && Placeholder::for_type(&typ).is_none()
+                        && typ != ValueType::Bool {
+                        self.errors.push(CompileError::typ(format!("cannot interpolate a value of type `{}`", typ), value.span));
+                    }
+                }
+                Some(ValueType::String)
+            }
+            ExprKind::New(name, fields) => {
+                let name = name.clone();
+                self.check_new(&name, fields, expr.span)
+            }
+            ExprKind::Field(object, field) => {
+                let found = self.check_expr(object, None)?;
+                let ValueType::Struct(name) = &found else {
+                    self.errors.push(CompileError::typ(format!("no field `{}` on a value of type `{}`", field, found), expr.span));
+                    return None;
+                };
+                let typ = self.symbols.struct_fields(name)?.iter().find(|f| f.name == *field).map(|f| f.typ.clone());
+                if typ.is_none() {
+                    self.errors.push(CompileError::typ(format!("struct `{}` has no field `{}`", name, field), expr.span));
+                }
+                typ
+            }
+        };
+        expr.typ = typ.clone();
+        typ
+    }
+
+    /// Checks both operands of a binary operator. An operand made only of literals takes its type
+    /// from the other side, so `1 + x` with `x: u8` is a `u8` addition.
+    fn check_operands(&mut self, left: &mut Expr, right: &mut Expr, hint: Option<&ValueType>) -> (Option<ValueType>, Option<ValueType>) {
+        if Self::is_literal(left) && !Self::is_literal(right) {
+            let r = self.check_expr(right, hint);
+            let l = self.check_expr(left, r.as_ref().filter(|t| t.is_numeric()).or(hint));
+            (l, r)
+        } else {
+            let l = self.check_expr(left, hint);
+            let r = self.check_expr(right, l.as_ref().filter(|t| t.is_numeric()).or(hint));
+            (l, r)
+        }
+    }
+
+    fn is_literal(expr: &Expr) -> bool {
+        match &expr.kind {
+            ExprKind::Value(Value::Integer(_)) | ExprKind::Value(Value::Number(_)) => true,
+            ExprKind::Unary('-', operand) => Self::is_literal(operand),
+            ExprKind::Binary(_, left, right) => Self::is_literal(left) && Self::is_literal(right),
+            _ => false,
+        }
+    }
+
+    fn check_arithmetic(&mut self, op: &str, left: &Expr, l: Option<ValueType>, right: &Expr, r: Option<ValueType>) -> Option<ValueType> {
+        let l = self.check_numeric(op, l, left.span);
+        let r = self.check_numeric(op, r, right.span);
+        if let (Some(l), Some(r)) = (&l, &r) && l != r {
+            self.errors.push(CompileError::typ(format!("cannot apply `{}` to `{}` and `{}`", op, l, r), right.span)
+                .with_help(format!("convert one side explicitly, e.g. `{}(...)`", l)));
+            return None;
+        }
+        let typ = l.or(r);
+        if op == "%" && typ == Some(ValueType::Number) {
+            self.errors.push(CompileError::typ("cannot apply `%` to a value of type `number`", left.span)
+                .with_note("`%` is only defined for integer types"));
+        }
+        typ
+    }
+
+    /// Reports a non-numeric operand and returns the type only if it is numeric.
+    fn check_numeric(&mut self, op: &str, typ: Option<ValueType>, span: Span) -> Option<ValueType> {
+        match typ {
+            Some(typ) if !typ.is_numeric() => {
+                self.errors.push(CompileError::typ(format!("cannot apply `{}` to a value of type `{}`", op, typ), span));
+                None
+            }
+            _ => typ,
+        }
+    }
+
+    fn check_literal(&mut self, value: i128, typ: &ValueType, span: Span) {
+        if let ValueType::Int(int) = typ && (value < int.min() || value > int.max()) {
+            self.errors.push(CompileError::typ(format!("literal out of range for `{}`", int), span)
+                .with_note(format!("the range of `{}` is {}..={}", int, int.min(), int.max())));
+        }
+    }
+
+    fn check_operand(&mut self, op: &str, operand: &mut Expr, expected: &ValueType) {
+        if let Some(typ) = self.check_expr(operand, Some(expected)) && typ != *expected {
+            self.errors.push(CompileError::typ(format!("cannot apply `{}` to a value of type `{}`", op, typ), operand.span));
+        }
+    }
+
+    fn check_call(&mut self, name: &str, args: &mut [Expr], symbol: Option<SymbolId>, span: Span) -> Option<ValueType> {
+        let Some(signature) = self.symbols.signature(symbol) else {
+            for arg in args.iter_mut() {
+                self.check_expr(arg, None);
+            }
+            return None;
+        };
+
+        let arity_ok = if signature.variadic {
+            args.len() >= signature.params.len()
+        } else {
+            args.len() == signature.params.len()
+        };
+        if !arity_ok {
+            let at_least = if signature.variadic { "at least " } else { "" };
+            self.errors.push(CompileError::typ(format!(
+                "function `{}` takes {}{} argument(s) but {} were supplied",
+                name, at_least, signature.params.len(), args.len()
+            ), span));
+        }
+
+        for (i, arg) in args.iter_mut().enumerate() {
+            let expected = signature.params.get(i);
+            let found = self.check_expr(arg, expected);
+            if let Some(expected) = expected {
+                self.expect(expected, found, arg.span);
+            }
+        }
+        if let Some(format) = signature.format {
+            self.check_format(name, format, args);
+        }
+
+        Some(signature.return_type.clone())
+    }
+
+    /// Checks the elements of an array literal against the element type the context expects, or
+    /// against the type of the first element. A literal is a fixed-size array when the context
+    /// expects one, and then it must have exactly that many elements.
+    fn check_array(&mut self, elements: &mut [Expr], element: Option<ValueType>, length: Option<usize>, span: Span) -> Option<ValueType> {
+        if let Some(length) = length && elements.len() != length {
+            self.errors.push(CompileError::typ(format!("expected an array of {} element(s), found {}", length, elements.len()), span));
+        }
+        let Some(first) = elements.first_mut() else {
+            if element.is_none() {
+                self.errors.push(CompileError::typ("cannot infer the type of an empty array", span)
+                    .with_help("give it a type, e.g. `var a: [number] = []`"));
+            }
+            return element.map(|element| ValueType::Array(Box::from(element), length));
+        };
+        let element = match element {
+            Some(element) => element,
+            None => self.check_expr(first, None)?,
+        };
+        for value in elements.iter_mut() {
+            let found = self.check_expr(value, Some(&element));
+            self.expect(&element, found, value.span);
+        }
+        Some(ValueType::Array(Box::from(element), length))
+    }
+
+    fn check_index(&mut self, index: &mut Expr) {
+        if let Some(typ) = self.check_expr(index, None) && !matches!(typ, ValueType::Int(_)) {
+            self.errors.push(CompileError::typ(format!("array index must be an integer, found `{}`", typ), index.span)
+                .with_help("convert it explicitly, e.g. `int(...)`"));
+        }
+    }
+
+    fn check_builtin(&mut self, builtin: Builtin, args: &mut [Expr], span: Span) -> Option<ValueType> {
+        let arity = match builtin {
+            Builtin::Len => 1,
+            Builtin::Push => 2,
+        };
+        if args.len() != arity {
+            self.errors.push(CompileError::typ(format!(
+                "function `{}` takes {} argument(s) but {} were supplied", builtin.name(), arity, args.len()
+            ), span));
+        }
+        let (array, rest) = args.split_first_mut()?;
+        let element = match self.check_expr(array, None)? {
+            ValueType::Array(element, Some(length)) if builtin == Builtin::Push => {
+                self.errors.push(CompileError::typ(format!("cannot `push` to a fixed-size array of type `[{}; {}]`", element, length), array.span)
+                    .with_help(format!("declare it as `[{}]` to make it growable", element)));
+                return None;
+            }
+            ValueType::Array(element, _) => *element,
+            typ => {
+                self.errors.push(CompileError::typ(format!("`{}` expects an array, found `{}`", builtin.name(), typ), array.span));
+                return None;
+            }
+        };
+        match builtin {
+            Builtin::Len => Some(ValueType::Int(IntType::I32)),
+            Builtin::Push => {
+                for value in rest.iter_mut() {
+                    let found = self.check_expr(value, Some(&element));
+                    self.expect(&element, found, value.span);
+                }
+                Some(ValueType::Unit)
+            }
+        }
+    }
+
+    fn check_new(&mut self, name: &str, inits: &mut [FieldInit], span: Span) -> Option<ValueType> {
+        let Some(fields) = self.symbols.struct_fields(name) else {
+            for init in inits.iter_mut() {
+                self.check_expr(&mut init.value, None);
+            }
+            return None;
+        };
+
+        for init in inits.iter_mut() {
+            let expected = fields.iter().find(|f| f.name == init.name).map(|f| &f.typ);
+            let found = self.check_expr(&mut init.value, expected);
+            match expected {
+                Some(expected) => self.expect(expected, found, init.value.span),
+                None => self.errors.push(CompileError::typ(format!("struct `{}` has no field `{}`", name, init.name), init.value.span)),
+            }
+        }
+        for (i, init) in inits.iter().enumerate() {
+            if inits[..i].iter().any(|other| other.name == init.name) {
+                self.errors.push(CompileError::typ(format!("field `{}` is specified twice", init.name), init.value.span));
+            }
+        }
+        let missing: Vec<String> = fields.iter()
+            .filter(|field| !inits.iter().any(|init| init.name == field.name))
+            .map(|field| format!("`{}`", field.name))
+            .collect();
+        if !missing.is_empty() {
+            self.errors.push(CompileError::typ(format!("missing field(s) {} in construction of `{}`", missing.join(", "), name), span));
+        }
+
+        Some(ValueType::Struct(name.to_string()))
+    }
+
+    /// Checks the placeholders of a literal format string against the arguments after it, since the
+    /// runtime reads them as C varargs and a mismatch is undefined behaviour.
+    fn check_format(&mut self, name: &str, format: FormatArgs, args: &[Expr]) {
+        let Some((fmt, values)) = args.split_first() else {
+            return;
+        };
+        let ExprKind::Value(Value::String(text)) = &fmt.kind else {
+            if !values.is_empty() {
+                self.errors.push(CompileError::typ(format!("the format string of `{}` must be a string literal", name), fmt.span)
+                    .with_note("its placeholders are checked against the arguments at compile time"));
+            }
+            return;
+        };
+
+        let mut placeholders = vec![];
+        for placeholder in Placeholder::parse_all(text) {
+            match placeholder {
+                Ok(Placeholder::Integer) if format == FormatArgs::Targets => {
+                    self.errors.push(CompileError::typ(format!("`{}` is not supported by `{}`", Placeholder::Integer, name), fmt.span)
+                        .with_help("read a `number` with `{f}` and convert it with `int(...)`"));
+                    return;
+                }
+                Ok(placeholder) => placeholders.push(placeholder),
+                Err(letter) => {
+                    self.errors.push(CompileError::typ(format!("unknown placeholder `{{{}}}` in format string", letter), fmt.span)
+                        .with_help("use `{s}` for strings, `{f}` for numbers and `{i}` for integers"));
+                    return;
+                }
+            }
+        }
+        if placeholders.len() != values.len() {
+            self.errors.push(CompileError::typ(format!(
+                "format string has {} placeholder(s) but {} argument(s) were supplied",
+                placeholders.len(), values.len()
+            ), fmt.span));
+        }
+
+        for (placeholder, value) in placeholders.into_iter().zip(values) {
+            if format == FormatArgs::Targets && !matches!(value.kind, ExprKind::VarUse(_)) {
+                self.errors.push(CompileError::typ(format!("`{}` can only read into a variable", name), value.span));
+                continue;
+            }
+            let Some(typ) = &value.typ else { continue };
+            if placeholder.accepts(typ) {
+                continue;
+            }
+            let mut err = CompileError::typ(format!("placeholder `{}` expects {}, found `{}`", placeholder, placeholder.describe(), typ), value.span);
+            err = match Placeholder::for_type(typ) {
+                Some(other) => err.with_help(format!("use `{}` for `{}` values", other, typ)),
+                None => err.with_help(format!("`{}` values cannot be formatted; use an interpolated string such as \"${{flag}}\"", typ)),
+            };
+            self.errors.push(err);
+        }
+    }
+
+    fn expect(&mut self, expected: &ValueType, found: Option<ValueType>, span: Span) {
+        if let Some(found) = found && found != *expected {
+            self.errors.push(CompileError::typ(format!("mismatched types: expected `{}`, found `{}`", expected, found), span));
+        }
+    }
+}
\ No newline at end of file
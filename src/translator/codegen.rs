@@ -1,160 +1,883 @@
-﻿use super::ast::{Expr, TypedArgument, Stmt};
-use super::value::{ValueType};
+﻿use std::collections::{HashMap, HashSet};
+
+use super::ast::{Expr, ExprKind, InterpolationPart, Iterable, TypedArgument, Stmt, StmtKind};
+use super::builtins::{Builtin, FormatArgs, Placeholder};
+use super::error::{CompileError, Span};
+use super::parser::precedence;
+use super::source_map::SourceMap;
+use super::symbols::{SymbolId, SymbolTable};
+use super::value::{IntType, Value, ValueType};
 
 pub struct CCodeGenerator {
     input: Stmt,
+    symbols: SymbolTable,
+    /// Name and line map of the Lamp source, for the locations reported by runtime checks.
+    file_name: String,
+    source_map: SourceMap,
+    /// The functions the program defines; any other call goes to a runtime module.
+    functions: HashSet<SymbolId>,
+    /// Set when an interpolated string needs `format` from the io runtime.
+    uses_format: bool,
+    /// Set when integer arithmetic needs the helpers of `integer.h`.
+    uses_integer: bool,
+    /// Set while generating a method body, where `self` is a pointer to the receiver.
+    in_method: bool,
+    /// Number of areas the generated code has opened around the current statement.
+    areas: usize,
+    /// The value of `areas` where each variable was declared, so values assigned to it from a
+    /// nested block can be promoted to its area.
+    var_areas: HashMap<SymbolId, usize>,
+    /// The loops around the current statement, innermost last.
+    loops: Vec<LoopScope>,
+    /// The label of the loop about to be generated.
+    label: Option<String>,
+    /// Counter that makes the names of temporaries and the C labels of labeled loops unique. C
+    /// labels are scoped to the whole function, and sibling loops may reuse a Lamp label.
+    next_id: usize,
+    /// Declarations of the temporaries that the statement being generated uses, emitted before it.
+    temps: Vec<String>,
+}
+
+/// A loop being generated, for lowering `break` and `continue`.
+struct LoopScope {
+    label: Option<String>,
+    /// The prefix of the C labels of the loop, such as `outer__0`.
+    c_label: String,
+    /// The value of `areas` outside the loop body.
+    areas: usize,
+    /// Set when a labeled jump needs the `c_label__break` or `c_label__continue` C label.
+    break_used: bool,
+    continue_used: bool,
 }
 
 impl CCodeGenerator {
-    pub fn new(input: Stmt) -> Self {
+    pub fn new(input: Stmt, symbols: SymbolTable) -> Self {
+        let functions = match &input.kind {
+            StmtKind::Block(stmts) => stmts.iter()
+                .filter(|stmt| matches!(stmt.kind, StmtKind::FunctionDef(..)))
+                .filter_map(|stmt| stmt.symbol)
+                .collect(),
+            _ => HashSet::new(),
+        };
         Self {
-            input
+            input,
+            functions,
+            symbols,
+            file_name: "<input>".to_string(),
+            source_map: SourceMap::new(""),
+            uses_format: false,
+            uses_integer: false,
+            in_method: false,
+            areas: 0,
+            var_areas: HashMap::new(),
+            loops: vec![],
+            label: None,
+            next_id: 0,
+            temps: vec![],
         }
     }
 
-    pub fn generate(&mut self) -> String {
-        let mut main = String::from("#include \"area.h\"\n");
+    pub fn with_source(mut self, file_name: &str, source: &str) -> Self {
+        self.file_name = file_name.to_string();
+        self.source_map = SourceMap::new(source);
+        self
+    }
+
+    pub fn generate(&mut self) -> Result<String, CompileError> {
+        let mut main = String::from("#include <stdbool.h>\n#include <stdint.h>\n#include \"area.h\"\n#include \"array.h\"\n");
 
-        if let Stmt::Block(stmts) = self.input.clone() {
-            self.generate_outer(&mut main, stmts.clone());
-            let code = self.generate_c_block_of_code(Stmt::Block(stmts.clone()));
+        if let StmtKind::Block(stmts) = self.input.kind.clone() {
+            self.generate_outer(&mut main, stmts.clone())?;
+            let code = self.generate_c_body(self.input.clone())?;
             main += format!("void main() {{area_start();{}area_end();}}", code).as_str();
+
+            let uses_io = stmts.iter().any(|stmt| matches!(&stmt.kind, StmtKind::Use(module) if module == "io"));
+            if self.uses_format && !uses_io {
+                main = main.replacen("#include \"area.h\"\n", "#include \"area.h\"\n#include \"io.h\"\n", 1);
+            }
+            if self.uses_integer {
+                main = main.replacen("#include \"array.h\"\n", "#include \"array.h\"\n#include \"integer.h\"\n", 1);
+            }
         }
 
-        main
+        Ok(main)
     }
 
-    fn generate_outer(&mut self, code: &mut String, stmts: Vec<Stmt>) {
+    /// Generates the C of one statement of the checked program as it would appear in `main`, or the
+    /// C function of a function definition.
+    pub fn generate_statement(&mut self, stmt: Stmt) -> Result<String, CompileError> {
+        match stmt.kind {
+            StmtKind::FunctionDef(name, args, body, return_type) => self.convert_to_c_function(name, args, *body, return_type),
+            _ => self.generate_c_statement(stmt),
+        }
+    }
+
+    /// Generates the C of an expression of the checked program.
+    pub fn generate_expression(&mut self, expr: Expr) -> Result<String, CompileError> {
+        let code = self.generate_c_expression(expr)?;
+        Ok(std::mem::take(&mut self.temps).concat() + &code)
+    }
+
+    fn generate_outer(&mut self, code: &mut String, stmts: Vec<Stmt>) -> Result<(), CompileError> {
         self.generate_uses(code, stmts.clone());
         self.generate_structs(code, stmts.clone());
-        self.generate_functions(code, stmts.clone());
+        self.generate_promotions(code, stmts.clone());
+        self.generate_function_prototypes(code, stmts.clone());
+        self.generate_method_prototypes(code, stmts.clone());
+        self.generate_functions(code, stmts.clone())?;
+        self.generate_methods(code, stmts)
     }
 
     fn generate_uses(&mut self, code: &mut String, stmts: Vec<Stmt>) {
         for stmt in stmts.clone() {
-            if let Stmt::Use(module) = stmt.clone() {
+            if let StmtKind::Use(module) = stmt.kind {
                 code.push_str(format!("#include \"{}.h\"\n", module).as_str());
             }
         }
     }
 
-    fn generate_functions(&mut self, code: &mut String, stmts: Vec<Stmt>) {
+    fn generate_functions(&mut self, code: &mut String, stmts: Vec<Stmt>) -> Result<(), CompileError> {
         for stmt in stmts.clone() {
-            if let Stmt::FunctionDef(name, args, body, return_type) = stmt.clone() {
-                code.push_str(self.convert_to_c_function(name.clone(), args, body, return_type).as_str());
+            if let StmtKind::FunctionDef(name, args, body, return_type) = stmt.kind {
+                code.push_str(self.convert_to_c_function(name.clone(), args, *body, return_type)?.as_str());
+            }
+        }
+        Ok(())
+    }
+
+    /// Emits `lamp_promote_Name` for every struct that holds strings or arrays, directly or through
+    /// other structs. The functions are declared first, because a struct can reach itself through an
+    /// array.
+    fn generate_promotions(&mut self, code: &mut String, stmts: Vec<Stmt>) {
+        let structs: Vec<(String, Vec<TypedArgument>)> = stmts.into_iter().filter_map(|stmt| match stmt.kind {
+            StmtKind::Struct(name, fields) if self.needs_promotion(&ValueType::Struct(name.clone())) => Some((name, fields)),
+            _ => None,
+        }).collect();
+        for (name, _) in &structs {
+            code.push_str(format!("void lamp_promote_{} ({}* value,int area);", name, Self::c_ident(name)).as_str());
+        }
+        for (name, fields) in &structs {
+            let body: String = fields.iter()
+                .map(|field| self.generate_c_promotion(&format!("value->{}", Self::c_ident(&field.name)), &field.typ, "area", 0))
+                .collect();
+            code.push_str(format!("void lamp_promote_{} ({}* value,int area) {{{}}}", name, Self::c_ident(name), body).as_str());
+        }
+    }
+
+    /// Whether a value of this type points into an area, so it has to be promoted to outlive it.
+    fn needs_promotion(&self, typ: &ValueType) -> bool {
+        match typ {
+            ValueType::String | ValueType::Array(_, _) => true,
+            // Structs hold each other by value without cycles, which the type checker ensures.
+            ValueType::Struct(name) => self.symbols.struct_fields(name)
+                .is_some_and(|fields| fields.iter().any(|field| self.needs_promotion(&field.typ))),
+            _ => false,
+        }
+    }
+
+    /// Statements that make the value at `place`, and everything it points to, live in the area
+    /// whose index is `area`: strings are copied there and arrays are moved.
+    fn generate_c_promotion(&self, place: &str, typ: &ValueType, area: &str, depth: usize) -> String {
+        match typ {
+            ValueType::String => format!("{place} = area_strdup_in({area}, {place});"),
+            // Arrays are shared, so an array is moved to the area rather than copied. Its elements
+            // only need promoting if it moved.
+            ValueType::Array(element, _) => {
+                let index = format!("lamp_i{}", depth);
+                let item = format!("(({}*){}->data)[{}]", self.convert_to_c_type(element), place, index);
+                let inner = self.generate_c_promotion(&item, element, area, depth + 1);
+                if inner.is_empty() {
+                    format!("array_move_in({area}, {place});")
+                } else {
+                    format!("if (array_move_in({area}, {place})) {{for (int64_t {index} = 0; {index} < {place}->len; {index}++) {{{inner}}}}}")
+                }
+            }
+            ValueType::Struct(name) if self.needs_promotion(typ) => format!("lamp_promote_{}(&{}, {});", name, place, area),
+            _ => String::new(),
+        }
+    }
+
+    /// Functions are declared up front, because the resolver lets code call a function defined
+    /// after it.
+    fn generate_function_prototypes(&mut self, code: &mut String, stmts: Vec<Stmt>) {
+        for stmt in stmts {
+            if let StmtKind::FunctionDef(name, args, _, return_type) = stmt.kind {
+                code.push_str(format!("{};", self.c_function_signature(&name, &args, &return_type)).as_str());
+            }
+        }
+    }
+
+    /// Methods are declared up front, so functions and other methods can call them in any order.
+    fn generate_method_prototypes(&mut self, code: &mut String, stmts: Vec<Stmt>) {
+        for stmt in stmts {
+            if let StmtKind::Impl(owner, methods) = stmt.kind {
+                for method in methods {
+                    if let StmtKind::FunctionDef(name, args, _, return_type) = method.kind {
+                        let signature = self.c_method_signature(&owner, &name, &args, &return_type);
+                        code.push_str(format!("{};", signature).as_str());
+                    }
+                }
+            }
+        }
+    }
+
+    fn generate_methods(&mut self, code: &mut String, stmts: Vec<Stmt>) -> Result<(), CompileError> {
+        for stmt in stmts {
+            if let StmtKind::Impl(owner, methods) = stmt.kind {
+                for method in methods {
+                    if let StmtKind::FunctionDef(name, args, body, return_type) = method.kind {
+                        let signature = self.c_method_signature(&owner, &name, &args, &return_type);
+                        self.in_method = true;
+                        let body = self.generate_c_body(*body);
+                        self.in_method = false;
+                        code.push_str(format!("{} {{area_start();{}area_end();}}", signature, body?).as_str());
+                    }
+                }
             }
         }
+        Ok(())
+    }
+
+    /// A method becomes a C function named `lamp_method_Struct__method` that takes a pointer to the
+    /// receiver first, and the area the receiver belongs to, for values the method stores into it.
+    fn c_method_signature(&self, owner: &str, name: &str, args: &[TypedArgument], return_type: &ValueType) -> String {
+        let mut c_args_vec: Vec<String> = vec![format!("{}* lamp_self", Self::c_ident(owner)), "int lamp_self_area".to_string()];
+        c_args_vec.extend(args.iter().skip(1).map(|a| format!("{} {}", self.convert_to_c_type(&a.typ), Self::c_ident(&a.name))));
+        format!("{} {} ({})", self.convert_to_c_type(return_type), Self::c_method(owner, name), c_args_vec.join(","))
     }
 
     fn generate_structs(&mut self, code: &mut String, stmts: Vec<Stmt>) {
-        for stmt in stmts.clone() {
-            if let Stmt::Struct(name, fields) = stmt.clone() {
-                let c_args_vec: Vec<String> = fields.iter().map(
-                    |a| format!("{} {}", self.convert_to_c_type(a.typ), a.name)
-                ).collect();
-                let c_args_str = c_args_vec.join("; ") + "; ";
-                code.push_str(format!("typedef struct {{{}}} {};", c_args_str, name).as_str());
+        let structs: Vec<(String, Vec<TypedArgument>)> = stmts.into_iter().filter_map(|stmt| match stmt.kind {
+            StmtKind::Struct(name, fields) => Some((name, fields)),
+            _ => None,
+        }).collect();
+        let mut emitted: Vec<String> = vec![];
+        for (name, _) in &structs {
+            self.generate_struct(code, name, &structs, &mut emitted);
+        }
+    }
+
+    /// Emits a struct after the structs its fields hold by value, since C needs complete field types.
+    fn generate_struct(&self, code: &mut String, name: &str, structs: &[(String, Vec<TypedArgument>)], emitted: &mut Vec<String>) {
+        if emitted.iter().any(|e| e == name) {
+            return;
+        }
+        emitted.push(name.to_string());
+        let Some((_, fields)) = structs.iter().find(|(n, _)| n == name) else {
+            return;
+        };
+        for field in fields {
+            if let ValueType::Struct(inner) = &field.typ {
+                self.generate_struct(code, inner, structs, emitted);
             }
         }
+        let c_args_vec: Vec<String> = fields.iter().map(
+            |a| format!("{} {}", self.convert_to_c_type(&a.typ), Self::c_ident(&a.name))
+        ).collect();
+        let c_args_str = c_args_vec.join("; ") + "; ";
+        code.push_str(format!("typedef struct {{{}}} {};", c_args_str, Self::c_ident(name)).as_str());
     }
 
-    fn generate_c_block_of_code(&mut self, statement: Stmt) -> String {
+    fn generate_c_block_of_code(&mut self, statement: Stmt) -> Result<String, CompileError> {
         let mut code = String::new();
-        if let Stmt::Block(stmts) = statement.clone() {
+        if let StmtKind::Block(stmts) = statement.kind.clone() {
             for stmt in stmts {
-                code += self.generate_c_statement(stmt).as_str();
+                code += self.generate_c_statement(stmt)?.as_str();
             }
         } else {
-            code += self.generate_c_statement(statement).as_str();
+            code += self.generate_c_statement(statement)?.as_str();
         }
 
-        code
+        Ok(code)
     }
 
-    fn generate_c_stmt_or_block(&mut self, stmt: Stmt) -> String {
-        if let Stmt::Block(_) = stmt.clone() {
+    fn generate_c_stmt_or_block(&mut self, stmt: Stmt) -> Result<String, CompileError> {
+        if let StmtKind::Block(_) = stmt.kind {
             self.generate_c_block_of_code(stmt)
         } else {
             self.generate_c_statement(stmt)
         }
     }
 
-    fn generate_c_statement(&mut self, statement: Stmt) -> String {
-        match statement {
-            Stmt::Assign(name, value) => {
-                format!("{} = {};", name, self.generate_c_expression(*value))
+    /// Generates a statement, preceded by the declarations of the temporaries it uses.
+    fn generate_c_statement(&mut self, statement: Stmt) -> Result<String, CompileError> {
+        let outer = std::mem::take(&mut self.temps);
+        let code = self.generate_c_statement_code(statement);
+        let temps = std::mem::replace(&mut self.temps, outer);
+        Ok(temps.concat() + &code?)
+    }
+
+    fn generate_c_statement_code(&mut self, statement: Stmt) -> Result<String, CompileError> {
+        let code = match statement.kind {
+            StmtKind::Assign(mut target, value) => {
+                let typ = value.typ.clone().unwrap_or(ValueType::Unit);
+                let mut inits: Vec<String> = vec![];
+                let area = if self.needs_promotion(&typ) { self.place_area(&mut target, &mut inits)? } else { None };
+                let target = self.generate_c_expression(*target)?;
+                let value = self.generate_c_expression(*value)?;
+                match area {
+                    // The value escapes to a place in an outer area, so it is promoted there.
+                    Some(area) => format!("{{{} lamp_value = {};{}int lamp_area = {};{}{} = lamp_value;}}",
+                                          self.convert_to_c_type(&typ), value, inits.iter().map(|init| format!("{};", init)).collect::<String>(),
+                                          area, self.generate_c_promotion("lamp_value", &typ, "lamp_area", 0), target),
+                    None => format!("{} = {};", target, value),
+                }
             },
-            Stmt::VarDef(name, value, v_type) => {
-                format!("{} {} = {};", self.convert_to_c_type(v_type), name, self.generate_c_expression(*value))
+            StmtKind::VarDef(name, value, v_type) => {
+                if let Some(id) = statement.symbol {
+                    self.var_areas.insert(id, self.areas);
+                }
+                format!("{} {} = {};", self.convert_to_c_type(&v_type), self.c_name(&name, statement.symbol), self.generate_c_expression(*value)?)
             }
-            Stmt::Return(value) => {
-                format!("return {};", self.generate_c_expression(*value))
+            StmtKind::Return(None) => format!("{{{}return;}}", "area_end();".repeat(self.areas)),
+            StmtKind::Return(Some(value)) => {
+                let Some(typ) = value.typ.clone() else {
+                    return Err(CompileError::codegen("return value without a type", value.span));
+                };
+                // The value is promoted to the caller's area before the areas of the function are closed.
+                let mut code = format!("{{{} lamp_ret = {};", self.convert_to_c_type(&typ), self.generate_c_expression(*value)?);
+                let promotion = self.generate_c_promotion("lamp_ret", &typ, "lamp_area", 0);
+                if !promotion.is_empty() {
+                    code += &format!("int lamp_area = area_current() - {};{}", self.areas, promotion);
+                }
+                code += &"area_end();".repeat(self.areas);
+                code + "return lamp_ret;}"
             }
-            Stmt::If(cond, body, else_body) => {
-                let mut result: String = String::from(format!("if ({}) {{area_start();{}area_end();}}",
-                                                              self.generate_c_expression(*cond),
-                                                              self.generate_c_stmt_or_block(*body)).as_str());
+            StmtKind::If(cond, body, else_body) => {
+                let mut result: String = format!("if ({}) {{area_start();{}area_end();}}",
+                                                 self.generate_c_expression(*cond)?,
+                                                 self.generate_c_area(*body)?);
 
                 if let Some(else_body) = *else_body {
-                    result += format!("else {{area_start();{}area_end();}}", self.generate_c_stmt_or_block(else_body)).as_str();
+                    result += format!("else {{area_start();{}area_end();}}", self.generate_c_area(else_body)?).as_str();
                 }
                 result
             },
-            Stmt::While(cond, body) => {
-                format!("while ({}) {{area_start();{}area_end();}}",
-                                     self.generate_c_expression(*cond),
-                                     self.generate_c_stmt_or_block(*body))
+            StmtKind::While(cond, body) => {
+                let head = format!("while ({})", self.generate_c_expression(*cond)?);
+                self.generate_c_loop(head, String::new(), *body)?
+            }
+            StmtKind::Labeled(label, body) => {
+                self.label = Some(label);
+                self.generate_c_statement(*body)?
             }
-            Stmt::Function(expr) => {
-                format!("{};", self.generate_c_expression(*expr).to_string())
+            StmtKind::Break(label) => self.generate_c_jump(label, true, statement.span)?,
+            StmtKind::Continue(label) => self.generate_c_jump(label, false, statement.span)?,
+            StmtKind::For(name, iterable, body) => {
+                let name = self.c_name(&name, statement.symbol);
+                self.generate_c_for(&name, iterable, *body, statement.span)?
             }
-            Stmt::Struct(_, _) => "".to_string(),
-            Stmt::FunctionDef(_, _, _, _) | Stmt::Use(_) => "".to_string(),
+            StmtKind::Function(expr) => self.generate_c_call_statement(*expr)?,
+            StmtKind::Struct(_, _) => "".to_string(),
+            StmtKind::FunctionDef(_, _, _, _) | StmtKind::Use(_) | StmtKind::Impl(_, _) => "".to_string(),
             _ => "\n".to_string()
+        };
+        Ok(code)
+    }
+
+    /// Generates a call whose result is unused. `push` and `input` store values into places that
+    /// may belong to an outer area, so the stored values are promoted there.
+    fn generate_c_call_statement(&mut self, expr: Expr) -> Result<String, CompileError> {
+        match expr.kind {
+            ExprKind::Builtin(Builtin::Push, args) if args.len() == 2 => {
+                let element = args[1].typ.clone().unwrap_or(ValueType::Unit);
+                if !self.needs_promotion(&element) {
+                    return Ok(format!("{};", self.generate_c_expression(Expr { kind: ExprKind::Builtin(Builtin::Push, args), ..expr })?));
+                }
+                let mut args = args.into_iter();
+                let array = self.generate_c_expression(args.next().expect("push has two arguments"))?;
+                let item = self.generate_c_expression(args.next().expect("push has two arguments"))?;
+                Ok(format!("{{LampArray* lamp_array = {};{} lamp_item = {};{}array_push(lamp_array, &lamp_item);}}",
+                           array, self.convert_to_c_type(&element), item,
+                           self.generate_c_promotion("lamp_item", &element, "lamp_array->area", 0)))
+            }
+            ExprKind::Functional(name, mut args) if self.symbols.signature(expr.symbol).and_then(|s| s.format) == Some(FormatArgs::Targets) => {
+                let mut inits: Vec<String> = vec![];
+                let mut promotions = String::new();
+                for target in args.iter_mut().skip(1) {
+                    let typ = target.typ.clone().unwrap_or(ValueType::Unit);
+                    if self.needs_promotion(&typ) && let Some(area) = self.place_area(target, &mut inits)? {
+                        let place = self.generate_c_expression(target.clone())?;
+                        promotions += &self.generate_c_promotion(&place, &typ, &area, 0);
+                    }
+                }
+                let call = self.generate_c_expression(Expr { kind: ExprKind::Functional(name, args), ..expr })?;
+                Ok(format!("{}{};{}", inits.iter().map(|init| format!("{};", init)).collect::<String>(), call, promotions))
+            }
+            _ => Ok(format!("{};", self.generate_c_expression(expr)?)),
         }
     }
 
-    fn generate_c_expression(&mut self, expression: Expr) -> String {
-        match expression {
-            Expr::Value(v) => {
-                v.to_string()
+    /// The area that a place belongs to, if it may be an outer one: the area its variable was
+    /// declared in, the area of the array holding it, or the area the caller passed for `self`.
+    /// `None` means the current area.
+    ///
+    /// The array holding an element is evaluated once, into a temporary that `place` is changed to
+    /// read. The assignments in `inits` set the temporaries and must run before the place is used.
+    fn place_area(&mut self, place: &mut Expr, inits: &mut Vec<String>) -> Result<Option<String>, CompileError> {
+        Ok(match &mut place.kind {
+            ExprKind::VarUse(name) if self.in_method && name == "self" && !self.is_shadowing(place.symbol) => {
+                Some("lamp_self_area".to_string())
             }
-            Expr::VarUse(name) => {
-                name.to_string()
+            ExprKind::VarUse(_) => {
+                // Variables without an entry are parameters, which live as long as the function body.
+                let depth = place.symbol.and_then(|id| self.var_areas.get(&id)).copied().unwrap_or(1);
+                (depth < self.areas).then(|| format!("area_current() - {}", self.areas - depth))
             }
-            Expr::Binary(op, left, right) => {
-                format!("{}{}{}", self.generate_c_expression(*left), op, self.generate_c_expression(*right))
+            ExprKind::Field(object, _) => self.place_area(object, inits)?,
+            ExprKind::Index(array, _) => {
+                self.next_id += 1;
+                let temp = format!("lamp_array{}", self.next_id - 1);
+                self.temps.push(format!("LampArray* {};", temp));
+                inits.push(format!("{} = {}", temp, self.generate_c_expression((**array).clone())?));
+                **array = Expr { kind: ExprKind::VarUse(temp.clone()), symbol: None, ..(**array).clone() };
+                Some(format!("{}->area", temp))
             }
-            Expr::Condition(op, left, right) => {
-                format!("{}{}{}", self.generate_c_expression(*left), op, self.generate_c_expression(*right))
+            _ => None,
+        })
+    }
+
+    /// Generates the body of a function, which runs in one area opened by the function itself.
+    fn generate_c_body(&mut self, body: Stmt) -> Result<String, CompileError> {
+        let outer = std::mem::replace(&mut self.areas, 1);
+        let code = self.generate_c_block_of_code(body);
+        self.areas = outer;
+        code
+    }
+
+    /// Generates a statement or block that runs in an area of its own.
+    fn generate_c_area(&mut self, body: Stmt) -> Result<String, CompileError> {
+        self.areas += 1;
+        let code = self.generate_c_stmt_or_block(body);
+        self.areas -= 1;
+        code
+    }
+
+    /// Generates a loop whose body runs in an area of its own, starting with `init`. A labeled
+    /// `continue` jumps to the end of the body, after its area is closed, and a labeled `break` to
+    /// right after the loop.
+    fn generate_c_loop(&mut self, head: String, init: String, body: Stmt) -> Result<String, CompileError> {
+        let label = self.label.take();
+        let c_label = match &label {
+            Some(label) => {
+                self.next_id += 1;
+                format!("{}__{}", label, self.next_id - 1)
             }
-            Expr::Unary(op, operand) => {
-                format!("{}({})", op, self.generate_c_expression(*operand))
+            None => String::new(),
+        };
+        self.loops.push(LoopScope { label, c_label, areas: self.areas, break_used: false, continue_used: false });
+        let body = self.generate_c_area(body);
+        let scope = self.loops.pop().expect("the loop scope was pushed above");
+        let continue_label = if scope.continue_used { format!("{}__continue:;", scope.c_label) } else { String::new() };
+        let break_label = if scope.break_used { format!("{}__break:;", scope.c_label) } else { String::new() };
+        Ok(format!("{} {{area_start();{}{}area_end();{}}}{}", head, init, body?, continue_label, break_label))
+    }
+
+    /// Lowers `break` and `continue`. Every area opened inside the target loop, including the one of
+    /// its body, is closed first, because the jump skips the `area_end` calls at the end of blocks.
+    /// C has no labeled jumps, so jumps out of an inner loop use `goto`.
+    fn generate_c_jump(&mut self, label: Option<String>, is_break: bool, span: Span) -> Result<String, CompileError> {
+        let keyword = if is_break { "break" } else { "continue" };
+        let target = match &label {
+            Some(label) => self.loops.iter().rposition(|scope| scope.label.as_ref() == Some(label)),
+            None => self.loops.len().checked_sub(1),
+        };
+        let Some(target) = target else {
+            return Err(CompileError::codegen(format!("`{}` outside of a loop", keyword), span));
+        };
+        let innermost = target == self.loops.len() - 1;
+        let scope = &mut self.loops[target];
+        let mut code = "area_end();".repeat(self.areas - scope.areas);
+        if innermost {
+            code += &format!("{};", keyword);
+        } else {
+            if is_break {
+                scope.break_used = true;
+            } else {
+                scope.continue_used = true;
             }
-            Expr::Functional(name, args) => {
-                let c_args_vec: Vec<String> = args.iter().map(|arg| self.generate_c_expression(arg.clone())).collect();
+            code += &format!("goto {}__{};", scope.c_label, keyword);
+        }
+        Ok(code)
+    }
+
+    /// Lowers a `for` loop to a C `for`. The end of a range, the array and its length are evaluated
+    /// once, into variables numbered like temporaries, so pushing to the array in the body does not
+    /// extend the loop.
+    fn generate_c_for(&mut self, name: &str, iterable: Iterable, body: Stmt, span: Span) -> Result<String, CompileError> {
+        self.next_id += 1;
+        let n = self.next_id - 1;
+        match iterable {
+            Iterable::Range(start, end) => {
+                let Some(typ) = start.typ.clone() else {
+                    return Err(CompileError::codegen("range without a type", span));
+                };
+                let head = format!("for ({} {name} = {}, lamp_end{n} = {}; {name} < lamp_end{n}; {name}++)",
+                                   self.convert_to_c_type(&typ), self.generate_c_expression(*start)?, self.generate_c_expression(*end)?);
+                self.generate_c_loop(head, String::new(), body)
+            }
+            Iterable::Array(array) => {
+                let Some(ValueType::Array(element, _)) = array.typ.clone() else {
+                    return Err(CompileError::codegen("iterating over a value that is not an array", span));
+                };
+                let element = self.convert_to_c_type(&element);
+                let items = format!("LampArray* lamp_items{n} = {};", self.generate_c_expression(*array)?);
+                let head = format!("for (int64_t lamp_index{n} = 0, lamp_len{n} = array_len(lamp_items{n}); lamp_index{n} < lamp_len{n}; lamp_index{n}++)");
+                let init = format!("{element} {name} = (({element}*)lamp_items{n}->data)[lamp_index{n}];");
+                Ok(format!("{{{}{}}}", items, self.generate_c_loop(head, init, body)?))
+            }
+        }
+    }
+
+    fn generate_c_expression(&mut self, expression: Expr) -> Result<String, CompileError> {
+        let code = match expression.kind {
+            ExprKind::Value(v) => {
+                self.generate_c_value(&v, expression.typ)
+            }
+            ExprKind::VarUse(name) if self.in_method && name == "self" && !self.is_shadowing(expression.symbol) => {
+                "(*lamp_self)".to_string()
+            }
+            ExprKind::VarUse(name) => {
+                self.c_name(&name, expression.symbol)
+            }
+            ExprKind::Binary(op, left, right) => match expression.typ {
+                Some(ValueType::Int(int)) => self.generate_c_integer_binary(op, int, *left, *right, expression.span)?,
+                _ => self.generate_c_binary(&op.to_string(), *left, *right)?,
+            },
+            ExprKind::Condition(op, left, right) => {
+                self.generate_c_binary(&op, *left, *right)?
+            }
+            // Negating the smallest signed value overflows, so it is done in unsigned arithmetic. C
+            // negates a literal in a type wide enough for it, unless it does not fit in 64 bits.
+            ExprKind::Unary('-', operand) if let Some(ValueType::Int(int)) = expression.typ
+                && !matches!(operand.kind, ExprKind::Value(Value::Integer(n)) if n <= i64::MAX as u64) => {
+                let operand = self.generate_c_expression(*operand)?;
+                format!("(({})(0-({}){}))", self.convert_to_c_type(&ValueType::Int(int)), Self::c_unsigned_type(int), Self::c_group(&operand))
+            }
+            ExprKind::Unary(op, operand) => {
+                format!("{}({})", op, self.generate_c_expression(*operand)?)
+            }
+            ExprKind::Convert(ValueType::Int(int), operand) if operand.typ == Some(ValueType::Number) => {
+                self.uses_integer = true;
+                format!("(({})integer_from_number({}))", self.convert_to_c_type(&ValueType::Int(int)), self.generate_c_expression(*operand)?)
+            }
+            ExprKind::Convert(typ, operand) => {
+                format!("(({})({}))", self.convert_to_c_type(&typ), self.generate_c_expression(*operand)?)
+            }
+            ExprKind::Functional(name, args) => {
+                let format = self.symbols.signature(expression.symbol).and_then(|s| s.format);
+                let mut c_args_vec: Vec<String> = vec![];
+                // A message that is not a literal could contain placeholders, so it is passed through `{s}`.
+                if format == Some(FormatArgs::Values) && args.len() == 1 && !matches!(args[0].kind, ExprKind::Value(Value::String(_))) {
+                    c_args_vec.push("\"{s}\"".to_string());
+                }
+                for (i, arg) in args.into_iter().enumerate() {
+                    let is_int = matches!(arg.typ, Some(ValueType::Int(_)));
+                    let code = self.generate_c_expression(arg)?;
+                    c_args_vec.push(match format {
+                        Some(FormatArgs::Targets) if i > 0 => format!("&{}", code),
+                        // The runtime reads every integer with `va_arg(args, long long)`.
+                        Some(FormatArgs::Values) if is_int => format!("(long long)({})", code),
+                        _ => code,
+                    });
+                }
                 let c_args = c_args_vec.join(", ");
+                // Functions of the program are mangled, those of the runtime modules keep their C names.
+                let name = if expression.symbol.is_some_and(|id| self.functions.contains(&id)) { Self::c_ident(&name) } else { name };
                 format!("{}({})", name, c_args)
             }
-            Expr::New(_, _) => unimplemented!()
+            ExprKind::Builtin(builtin, args) => {
+                let mut c_args_vec: Vec<String> = vec![];
+                let element = match args.first().and_then(|array| array.typ.as_ref()) {
+                    Some(ValueType::Array(element, _)) => self.convert_to_c_type(element),
+                    _ => return Err(CompileError::codegen(format!("`{}` called on a value that is not an array", builtin.name()), expression.span)),
+                };
+                for arg in args {
+                    c_args_vec.push(self.generate_c_expression(arg)?);
+                }
+                match builtin {
+                    Builtin::Len => format!("((int32_t)array_len({}))", c_args_vec.join(", ")),
+                    // The element is passed through a one-element compound literal, which has an address.
+                    // Elements that need copying into the array's area are handled by `generate_c_call_statement`.
+                    Builtin::Push => format!("array_push({}, ({}[]){{{}}})", c_args_vec[0], element, c_args_vec[1..].join(", ")),
+                }
+            }
+            ExprKind::Array(elements) => {
+                let Some(ValueType::Array(element, _)) = &expression.typ else {
+                    return Err(CompileError::codegen("array literal without a type", expression.span));
+                };
+                let element = self.convert_to_c_type(element);
+                if elements.is_empty() {
+                    format!("array_new(sizeof({}), 0, NULL)", element)
+                } else {
+                    let len = elements.len();
+                    let mut items: Vec<String> = vec![];
+                    for value in elements {
+                        items.push(self.generate_c_expression(value)?);
+                    }
+                    format!("array_new(sizeof({}), {}, ({}[]){{{}}})", element, len, element, items.join(", "))
+                }
+            }
+            ExprKind::Index(array, index) => {
+                let Some(ValueType::Array(element, _)) = array.typ.clone() else {
+                    return Err(CompileError::codegen("indexing a value that is not an array", expression.span));
+                };
+                let location = self.location(expression.span.start);
+                format!("(*({}*)array_at({}, {}, {}))", self.convert_to_c_type(&element),
+                        self.generate_c_expression(*array)?, self.generate_c_expression(*index)?, Self::c_string_literal(&location))
+            }
+            ExprKind::Interpolation(parts) => self.generate_c_interpolation(parts)?,
+            ExprKind::New(name, fields) => {
+                let mut inits: Vec<String> = vec![];
+                for field in fields {
+                    inits.push(format!(".{} = {}", Self::c_ident(&field.name), self.generate_c_expression(field.value)?));
+                }
+                format!("(({}){{{}}})", Self::c_ident(&name), inits.join(", "))
+            }
+            ExprKind::Field(object, field) => {
+                format!("{}.{}", self.generate_c_expression(*object)?, Self::c_ident(&field))
+            }
+            ExprKind::MethodCall(receiver, name, args) => {
+                let owner = match &receiver.typ {
+                    Some(ValueType::Struct(owner)) => owner.clone(),
+                    _ => return Err(CompileError::codegen(format!("method `{}` called on a value that is not a struct", name), expression.span)),
+                };
+                let mut receiver = *receiver;
+                let is_place = receiver.is_place();
+                let mut inits: Vec<String> = vec![];
+                let area = if is_place { self.place_area(&mut receiver, &mut inits)? } else { None };
+                let code = self.generate_c_expression(receiver)?;
+                // A temporary receiver has no address, so it is copied into a compound literal first.
+                let mut c_args_vec: Vec<String> = vec![if is_place {
+                    format!("&{}", code)
+                } else {
+                    format!("&(({}[]){{{}}})[0]", Self::c_ident(&owner), code)
+                }, area.unwrap_or_else(|| "area_current()".to_string())];
+                for arg in args {
+                    c_args_vec.push(self.generate_c_expression(arg)?);
+                }
+                let call = format!("{}({})", Self::c_method(&owner, &name), c_args_vec.join(", "));
+                // The comma operator sets the temporaries before the call reads them.
+                if inits.is_empty() { call } else { format!("({}, {})", inits.join(", "), call) }
+            }
+        };
+        Ok(code)
+    }
+
+    /// Lowers an interpolated string to a `format` call with a placeholder per expression, chosen
+    /// from its checked type. Text containing `{` is passed as an argument too, so `format` never
+    /// mistakes it for a placeholder.
+    fn generate_c_interpolation(&mut self, parts: Vec<InterpolationPart>) -> Result<String, CompileError> {
+        self.uses_format = true;
+        let mut fmt = String::new();
+        let mut args: Vec<String> = vec![];
+        for part in parts {
+            match part {
+                InterpolationPart::Text(text) if text.contains('{') => {
+                    fmt.push_str("{s}");
+                    args.push(Self::c_string_literal(&text));
+                }
+                InterpolationPart::Text(text) => fmt.push_str(&text),
+                InterpolationPart::Expr(value) => {
+                    let typ = value.typ.clone();
+                    let code = self.generate_c_expression(value)?;
+                    let (placeholder, arg) = match typ.as_ref().and_then(Placeholder::for_type) {
+                        Some(Placeholder::Integer) => (Placeholder::Integer, format!("(long long)({})", code)),
+                        Some(placeholder) => (placeholder, code),
+                        None => (Placeholder::String, format!("({}) ? \"true\" : \"false\"", code)),
+                    };
+                    fmt.push_str(&placeholder.to_string());
+                    args.push(arg);
+                }
+            }
         }
+        args.insert(0, Self::c_string_literal(&fmt));
+        Ok(format!("format({})", args.join(", ")))
     }
 
-    fn convert_to_c_function(&mut self, name: String, args: Vec<TypedArgument>, body: Box<Stmt>, return_type: ValueType) -> String {
-        let c_type: String = self.convert_to_c_type(return_type);
+    /// Lowers integer arithmetic so that it wraps around like in the interpreter. C's signed overflow
+    /// is undefined and narrow operands are promoted to `int`, so `+`, `-` and `*` are done in an
+    /// unsigned type at least as wide as `unsigned int` and cast back. `/` and `%` go through the
+    /// helpers of `integer.h`, which stop on a zero divisor, unless the divisor is a positive literal.
+    fn generate_c_integer_binary(&mut self, op: char, int: IntType, left: Expr, right: Expr, span: Span) -> Result<String, CompileError> {
+        let typ = self.convert_to_c_type(&ValueType::Int(int));
+        let positive_divisor = matches!(right.kind, ExprKind::Value(Value::Integer(n)) if n > 0);
+        let left = self.generate_c_expression(left)?;
+        let right = self.generate_c_expression(right)?;
+        Ok(match op {
+            '/' | '%' if positive_divisor => format!("(({})({}{}{}))", typ, Self::c_group(&left), op, Self::c_group(&right)),
+            '/' | '%' => {
+                self.uses_integer = true;
+                let helper = match (op, int.is_signed()) {
+                    ('/', true) => "integer_div",
+                    ('/', false) => "integer_udiv",
+                    (_, true) => "integer_rem",
+                    _ => "integer_urem",
+                };
+                let location = self.location(span.start);
+                format!("(({}){}({}, {}, {}))", typ, helper, left, right, Self::c_string_literal(&location))
+            }
+            _ => {
+                let unsigned = Self::c_unsigned_type(int);
+                format!("(({})(({}){}{}({}){}))", typ, unsigned, Self::c_group(&left), op, unsigned, Self::c_group(&right))
+            }
+        })
+    }
+
+    /// Parenthesizes C code for use as an operand of a cast or operator, unless it is a name, a
+    /// literal or already enclosed in parentheses.
+    fn c_group(code: &str) -> String {
+        if code.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.') {
+            return code.to_string();
+        }
+        let mut depth = 0;
+        let mut in_string = false;
+        let mut escaped = false;
+        for (i, c) in code.char_indices() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' if in_string => escaped = true,
+                '"' => in_string = !in_string,
+                '(' if !in_string => depth += 1,
+                ')' if !in_string => {
+                    depth -= 1;
+                    // The first parenthesis closes before the end, as in `(a)+(b)`.
+                    if depth == 0 && i + 1 < code.len() {
+                        return format!("({})", code);
+                    }
+                }
+                _ if depth == 0 => return format!("({})", code),
+                _ => {}
+            }
+        }
+        code.to_string()
+    }
+
+    /// The unsigned C type that integer arithmetic on `int` is done in.
+    fn c_unsigned_type(int: IntType) -> &'static str {
+        match int {
+            IntType::I64 | IntType::U64 => "uint64_t",
+            _ => "uint32_t",
+        }
+    }
+
+    fn generate_c_binary(&mut self, op: &str, left: Expr, right: Expr) -> Result<String, CompileError> {
+        let parent = precedence(op);
+        let left_code = self.generate_c_operand(left, parent, false)?;
+        let right_code = self.generate_c_operand(right, parent, true)?;
+        Ok(format!("{}{}{}", left_code, op, right_code))
+    }
+
+    /// Parenthesizes an operand whenever C's precedence and left associativity would group it
+    /// differently from the AST. A unary right operand is wrapped too, so `a - -b` does not become `a--`.
+    fn generate_c_operand(&mut self, operand: Expr, parent: u8, is_right: bool) -> Result<String, CompileError> {
+        let groups_differently = |p: u8| p < parent || (is_right && p == parent);
+        let needs_parens = match &operand.kind {
+            ExprKind::Binary(op, _, _) => groups_differently(precedence(&op.to_string())),
+            ExprKind::Condition(op, _, _) => groups_differently(precedence(op)),
+            ExprKind::Unary(_, _) => is_right,
+            _ => false,
+        };
+        let code = self.generate_c_expression(operand)?;
+        Ok(if needs_parens { format!("({})", code) } else { code })
+    }
+
+    fn generate_c_value(&mut self, value: &Value, typ: Option<ValueType>) -> String {
+        match value {
+            Value::Number(n) => format!("{:?}", n),
+            Value::Integer(n) => match typ {
+                Some(ValueType::Number) => format!("{:?}", *n as f64),
+                Some(ValueType::Int(IntType::I64)) if *n <= i64::MAX as u64 => format!("{}LL", n),
+                Some(ValueType::Int(IntType::I64)) => format!("{}ULL", n),
+                Some(ValueType::Int(IntType::U64)) => format!("{}ULL", n),
+                Some(ValueType::Int(IntType::U32)) => format!("{}U", n),
+                _ => n.to_string(),
+            },
+            Value::Bool(b) => b.to_string(),
+            Value::String(s) => format!("area_memdup({}, {})", Self::c_string_literal(s), s.len() + 1),
+        }
+    }
+
+    /// Escapes a string for a C string literal. Everything outside printable ASCII is written as
+    /// octal escapes of its UTF-8 bytes; unlike `\x`, an octal escape never swallows the next character.
+    fn c_string_literal(s: &str) -> String {
+        let mut literal = String::from("\"");
+        for byte in s.bytes() {
+            match byte {
+                b'"' => literal.push_str("\\\""),
+                b'\\' => literal.push_str("\\\\"),
+                b'\n' => literal.push_str("\\n"),
+                b'\t' => literal.push_str("\\t"),
+                b'\r' => literal.push_str("\\r"),
+                0x20..=0x7e => literal.push(byte as char),
+                _ => literal.push_str(&format!("\\{:03o}", byte)),
+            }
+        }
+        literal.push('"');
+        literal
+    }
+
+    /// A variable that shadows an outer one gets a unique C name, because in C the scope of a
+    /// declaration starts before its initializer: `double x = x + 1;` would read the new `x`. Only
+    /// the temporaries that `place_area` puts in the tree have no symbol, and they keep their name.
+    fn c_name(&self, name: &str, symbol: Option<SymbolId>) -> String {
+        match symbol {
+            Some(id) if self.symbols.get(id).shadows.is_some() => format!("lamp_v{}_{}", id.0, name),
+            Some(_) => Self::c_ident(name),
+            None => name.to_string(),
+        }
+    }
+
+    /// The C name of a name from the program. The `lamp__` prefix keeps it apart from C keywords,
+    /// the C library and the runtime. Names made up by the generator start with `lamp_` and a
+    /// letter instead, so the two never meet.
+    fn c_ident(name: &str) -> String {
+        format!("lamp__{}", name)
+    }
+
+    fn c_method(owner: &str, name: &str) -> String {
+        format!("lamp_method_{}__{}", owner, name)
+    }
+
+    /// Formats a source offset as `file:line:column`, the way compile errors point at code.
+    fn location(&self, offset: usize) -> String {
+        self.source_map.describe(&self.file_name, offset)
+    }
+
+    fn is_shadowing(&self, symbol: Option<SymbolId>) -> bool {
+        symbol.is_some_and(|id| self.symbols.get(id).shadows.is_some())
+    }
+
+    fn convert_to_c_function(&mut self, name: String, args: Vec<TypedArgument>, body: Stmt, return_type: ValueType) -> Result<String, CompileError> {
+        let signature = self.c_function_signature(&name, &args, &return_type);
+        Ok(format!("{} {{area_start();{}area_end();}}", signature, self.generate_c_body(body)?))
+    }
+
+    fn c_function_signature(&self, name: &str, args: &[TypedArgument], return_type: &ValueType) -> String {
         let c_args_vec: Vec<String> = args.iter().map(
-            |a| format!("{} {}", self.convert_to_c_type(a.typ), a.name)
+            |a| format!("{} {}", self.convert_to_c_type(&a.typ), Self::c_ident(&a.name))
         ).collect();
-        let c_args = c_args_vec.join(",");
-
-        format!("{} {} ({}) {{area_start();{}area_end();}}", c_type, name, c_args, self.generate_c_block_of_code(*body))
+        format!("{} {} ({})", self.convert_to_c_type(return_type), Self::c_ident(name), c_args_vec.join(","))
     }
 
-    fn convert_to_c_type(&mut self, typ: ValueType) -> String {
+    fn convert_to_c_type(&self, typ: &ValueType) -> String {
         match typ {
             ValueType::Number => "double".to_string(),
+            ValueType::Int(int) => match int {
+                IntType::I8 => "int8_t",
+                IntType::I16 => "int16_t",
+                IntType::I32 => "int32_t",
+                IntType::I64 => "int64_t",
+                IntType::U8 => "uint8_t",
+                IntType::U16 => "uint16_t",
+                IntType::U32 => "uint32_t",
+                IntType::U64 => "uint64_t",
+            }.to_string(),
             ValueType::String => "char*".to_string(),
+            ValueType::Bool => "bool".to_string(),
             ValueType::Unit => "void".to_string(),
+            ValueType::Struct(name) => Self::c_ident(name),
+            ValueType::Array(_, _) => "LampArray*".to_string(),
         }
     }
 }
\ No newline at end of file
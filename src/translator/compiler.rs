@@ -0,0 +1,570 @@
+use std::collections::HashMap;
+
+use super::ast::{Expr, ExprKind, InterpolationPart, Iterable, Stmt, StmtKind, TypedArgument};
+use super::builtins::Builtin;
+use super::bytecode::{Constant, Function, Op, Program};
+use super::error::{CompileError, Span};
+use super::interpreter::wrap;
+use super::symbols::SymbolId;
+use super::value::{IntType, Value, ValueType};
+
+/// Compiles a checked program to bytecode for the VM.
+pub struct BytecodeCompiler {
+    program: Program,
+    /// The index in the program of every function and method, by symbol.
+    functions: HashMap<SymbolId, u32>,
+    /// The function being compiled and the slots of its variables.
+    function: Function,
+    locals: HashMap<SymbolId, u32>,
+    /// The loops around the current statement, innermost last.
+    loops: Vec<LoopJumps>,
+    /// The label of the loop about to be compiled.
+    label: Option<String>,
+}
+
+/// The `break` and `continue` jumps of a loop, patched once its end is known.
+struct LoopJumps {
+    label: Option<String>,
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+/// A place that can be stored to. The array and index of an element are evaluated once, into
+/// hidden locals, so a place can be read and then written back, like the receiver of a method.
+struct Place {
+    root: Root,
+    fields: Vec<String>,
+}
+
+enum Root {
+    Local(u32),
+    Element { array: u32, index: u32 },
+}
+
+impl BytecodeCompiler {
+    pub fn new() -> Self {
+        Self {
+            program: Program::default(),
+            functions: HashMap::new(),
+            function: Function::default(),
+            locals: HashMap::new(),
+            loops: vec![],
+            label: None,
+        }
+    }
+
+    pub fn compile(mut self, program: &Stmt) -> Result<Program, CompileError> {
+        let StmtKind::Block(stmts) = &program.kind else {
+            return Err(CompileError::codegen("the program is not a block", program.span));
+        };
+        let mut definitions: Vec<(String, &Stmt)> = vec![];
+        for stmt in stmts {
+            match &stmt.kind {
+                StmtKind::FunctionDef(name, ..) => definitions.push((name.clone(), stmt)),
+                StmtKind::Impl(owner, methods) => {
+                    for method in methods {
+                        if let StmtKind::FunctionDef(name, ..) = &method.kind {
+                            definitions.push((format!("{}.{}", owner, name), method));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        // Functions are numbered up front, so calls can come before definitions.
+        for (i, (_, stmt)) in definitions.iter().enumerate() {
+            if let Some(id) = stmt.symbol {
+                self.functions.insert(id, i as u32 + 1);
+            }
+        }
+        let main = self.compile_function("main", &[], program)?;
+        self.program.functions.push(main);
+        for (name, stmt) in definitions {
+            if let StmtKind::FunctionDef(_, params, body, _) = &stmt.kind {
+                let function = self.compile_function(&name, params, body)?;
+                self.program.functions.push(function);
+            }
+        }
+        Ok(self.program)
+    }
+
+    fn compile_function(&mut self, name: &str, params: &[TypedArgument], body: &Stmt) -> Result<Function, CompileError> {
+        self.function = Function { name: name.to_string(), arity: params.len() as u32, ..Function::default() };
+        self.locals.clear();
+        for param in params {
+            self.declare(param.symbol, body.span)?;
+        }
+        self.statement(body)?;
+        // Functions returning `unit` end without a `return`.
+        self.emit(Op::Unit, body.span);
+        self.emit(Op::Return, body.span);
+        Ok(std::mem::take(&mut self.function))
+    }
+
+    fn statement(&mut self, stmt: &Stmt) -> Result<(), CompileError> {
+        let span = stmt.span;
+        match &stmt.kind {
+            StmtKind::VarDef(_, value, _) => {
+                self.expr(value)?;
+                let slot = self.declare(stmt.symbol, span)?;
+                self.emit(Op::SetLocal(slot), span);
+            }
+            StmtKind::Assign(target, value) => {
+                self.expr(value)?;
+                let Some(place) = self.place(target)? else {
+                    return Err(CompileError::codegen("cannot assign to a temporary value", target.span));
+                };
+                self.store(&place, span);
+            }
+            StmtKind::If(cond, body, else_body) => {
+                self.expr(cond)?;
+                let skip_body = self.emit(Op::JumpIfFalse(0), span);
+                self.statement(body)?;
+                match else_body.as_ref() {
+                    Some(else_body) => {
+                        let skip_else = self.emit(Op::Jump(0), span);
+                        self.patch(skip_body);
+                        self.statement(else_body)?;
+                        self.patch(skip_else);
+                    }
+                    None => self.patch(skip_body),
+                }
+            }
+            StmtKind::Block(stmts) => {
+                for stmt in stmts {
+                    self.statement(stmt)?;
+                }
+            }
+            StmtKind::While(cond, body) => {
+                let start = self.here();
+                self.expr(cond)?;
+                let exit = self.emit(Op::JumpIfFalse(0), span);
+                self.loop_body(body, start, span)?;
+                self.patch(exit);
+            }
+            StmtKind::For(_, iterable, body) => self.for_loop(stmt.symbol, iterable, body, span)?,
+            StmtKind::Labeled(label, body) => {
+                self.label = Some(label.clone());
+                self.statement(body)?;
+            }
+            StmtKind::Break(label) | StmtKind::Continue(label) => {
+                let target = match label {
+                    Some(label) => self.loops.iter().rposition(|jumps| jumps.label.as_ref() == Some(label)),
+                    None => self.loops.len().checked_sub(1),
+                };
+                let Some(target) = target else {
+                    return Err(CompileError::codegen("jump outside of a loop", span));
+                };
+                let jump = self.emit(Op::Jump(0), span);
+                let jumps = &mut self.loops[target];
+                if matches!(stmt.kind, StmtKind::Break(_)) {
+                    jumps.breaks.push(jump);
+                } else {
+                    jumps.continues.push(jump);
+                }
+            }
+            StmtKind::Function(expr) => {
+                self.expr(expr)?;
+                self.emit(Op::Pop, span);
+            }
+            StmtKind::Return(value) => {
+                match value {
+                    Some(value) => self.expr(value)?,
+                    None => {
+                        self.emit(Op::Unit, span);
+                    }
+                }
+                self.emit(Op::Return, span);
+            }
+            StmtKind::FunctionDef(..) | StmtKind::Use(_) | StmtKind::Struct(..) | StmtKind::Impl(..) => {}
+        }
+        Ok(())
+    }
+
+    /// Compiles a `for` loop to a counter in a hidden local. The end of a range and the length of an
+    /// array are evaluated once, so pushing to the array in the body does not extend the loop.
+    fn for_loop(&mut self, symbol: Option<SymbolId>, iterable: &Iterable, body: &Stmt, span: Span) -> Result<(), CompileError> {
+        let counter = self.temp();
+        let end = self.temp();
+        let (typ, array) = match iterable {
+            Iterable::Range(start, stop) => {
+                let Some(ValueType::Int(typ)) = start.typ else {
+                    return Err(CompileError::codegen("range without an integer type", span));
+                };
+                self.expr(start)?;
+                self.emit(Op::SetLocal(counter), span);
+                self.expr(stop)?;
+                self.emit(Op::SetLocal(end), span);
+                (typ, None)
+            }
+            Iterable::Array(items) => {
+                let array = self.temp();
+                self.expr(items)?;
+                self.emit(Op::SetLocal(array), span);
+                self.emit(Op::GetLocal(array), span);
+                self.emit(Op::Len, span);
+                self.emit(Op::SetLocal(end), span);
+                let zero = self.constant(Constant::Int(IntType::I32, 0));
+                self.emit(Op::Constant(zero), span);
+                self.emit(Op::SetLocal(counter), span);
+                (IntType::I32, Some(array))
+            }
+        };
+        let variable = self.declare(symbol, span)?;
+        let start = self.here();
+        self.emit(Op::GetLocal(counter), span);
+        self.emit(Op::GetLocal(end), span);
+        self.emit(Op::Less, span);
+        let exit = self.emit(Op::JumpIfFalse(0), span);
+        match array {
+            Some(array) => {
+                self.emit(Op::GetLocal(array), span);
+                self.emit(Op::GetLocal(counter), span);
+                self.emit(Op::GetIndex, span);
+            }
+            None => {
+                self.emit(Op::GetLocal(counter), span);
+            }
+        }
+        self.emit(Op::SetLocal(variable), span);
+
+        let label = self.label.take();
+        self.loops.push(LoopJumps { label, breaks: vec![], continues: vec![] });
+        self.statement(body)?;
+        let jumps = self.loops.pop().expect("the loop was pushed above");
+        let next = self.here();
+        let one = self.constant(Constant::Int(typ, 1));
+        self.emit(Op::GetLocal(counter), span);
+        self.emit(Op::Constant(one), span);
+        self.emit(Op::Add, span);
+        self.emit(Op::SetLocal(counter), span);
+        self.emit(Op::Jump(start as u32), span);
+        self.patch(exit);
+        self.patch_loop(jumps, next);
+        Ok(())
+    }
+
+    /// Compiles the body of a `while` loop that starts at `start`, followed by the jump back.
+    fn loop_body(&mut self, body: &Stmt, start: usize, span: Span) -> Result<(), CompileError> {
+        let label = self.label.take();
+        self.loops.push(LoopJumps { label, breaks: vec![], continues: vec![] });
+        self.statement(body)?;
+        let jumps = self.loops.pop().expect("the loop was pushed above");
+        self.emit(Op::Jump(start as u32), span);
+        self.patch_loop(jumps, start);
+        Ok(())
+    }
+
+    /// Points the `continue` jumps of a loop at `next` and its `break` jumps at the current end.
+    fn patch_loop(&mut self, jumps: LoopJumps, next: usize) {
+        for jump in jumps.continues {
+            self.patch_to(jump, next);
+        }
+        for jump in jumps.breaks {
+            self.patch(jump);
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Result<(), CompileError> {
+        let span = expr.span;
+        match &expr.kind {
+            ExprKind::Value(value) => {
+                let constant = match (value, &expr.typ) {
+                    (Value::Number(n), _) => Constant::Number(*n),
+                    (Value::Integer(n), Some(ValueType::Number)) => Constant::Number(*n as f64),
+                    (Value::Integer(n), Some(ValueType::Int(typ))) => Constant::Int(*typ, wrap(*typ, *n as i128)),
+                    (Value::Integer(_), _) => return Err(CompileError::codegen("integer literal without a type", span)),
+                    (Value::String(s), _) => Constant::String(s.clone()),
+                    (Value::Bool(b), _) => Constant::Bool(*b),
+                };
+                let index = self.constant(constant);
+                self.emit(Op::Constant(index), span);
+            }
+            ExprKind::VarUse(_) => {
+                let slot = self.local(expr.symbol, span)?;
+                self.emit(Op::GetLocal(slot), span);
+            }
+            ExprKind::Binary(op, left, right) => {
+                self.expr(left)?;
+                self.expr(right)?;
+                self.emit(match op {
+                    '+' => Op::Add,
+                    '-' => Op::Sub,
+                    '*' => Op::Mul,
+                    '/' => Op::Div,
+                    _ => Op::Rem,
+                }, span);
+            }
+            ExprKind::Condition(op, left, right) if op == "&&" || op == "||" => {
+                // The right operand only runs when the left one does not decide the result.
+                self.expr(left)?;
+                let to_right = self.emit(Op::JumpIfFalse(0), span);
+                if op == "&&" {
+                    self.expr(right)?;
+                    let to_end = self.emit(Op::Jump(0), span);
+                    self.patch(to_right);
+                    self.bool(false, span);
+                    self.patch(to_end);
+                } else {
+                    self.bool(true, span);
+                    let to_end = self.emit(Op::Jump(0), span);
+                    self.patch(to_right);
+                    self.expr(right)?;
+                    self.patch(to_end);
+                }
+            }
+            ExprKind::Condition(op, left, right) => {
+                self.expr(left)?;
+                self.expr(right)?;
+                self.emit(match op.as_str() {
+                    "==" => Op::Equal,
+                    "!=" => Op::NotEqual,
+                    "<" => Op::Less,
+                    ">" => Op::Greater,
+                    "<=" => Op::LessEqual,
+                    _ => Op::GreaterEqual,
+                }, span);
+            }
+            ExprKind::Unary(op, operand) => {
+                self.expr(operand)?;
+                self.emit(if *op == '!' { Op::Not } else { Op::Neg }, span);
+            }
+            ExprKind::Convert(typ, operand) => {
+                self.expr(operand)?;
+                self.emit(match typ {
+                    ValueType::Int(int) => Op::ToInt(*int),
+                    _ => Op::ToNumber,
+                }, span);
+            }
+            ExprKind::Functional(name, args) => match expr.symbol.and_then(|id| self.functions.get(&id)).copied() {
+                Some(function) => {
+                    for arg in args {
+                        self.expr(arg)?;
+                    }
+                    self.emit(Op::Call(function, args.len() as u32), span);
+                }
+                None if name == "input" => self.input(args, span)?,
+                None => {
+                    for arg in args {
+                        self.expr(arg)?;
+                    }
+                    let name = self.constant(Constant::String(name.clone()));
+                    self.emit(Op::CallNative(name, args.len() as u32), span);
+                }
+            },
+            ExprKind::MethodCall(receiver, name, args) => {
+                let Some(function) = expr.symbol.and_then(|id| self.functions.get(&id)).copied() else {
+                    return Err(CompileError::codegen(format!("method `{}` was not resolved", name), span));
+                };
+                let place = self.place(receiver)?;
+                match &place {
+                    Some(place) => self.load(place, span),
+                    None => self.expr(receiver)?,
+                }
+                for arg in args {
+                    self.expr(arg)?;
+                }
+                self.emit(Op::CallMethod(function, args.len() as u32 + 1), span);
+                // The receiver comes back on top of the result; a temporary one is dropped.
+                match &place {
+                    Some(place) => self.store(place, span),
+                    None => {
+                        self.emit(Op::Pop, span);
+                    }
+                }
+            }
+            ExprKind::Builtin(builtin, args) => {
+                for arg in args {
+                    self.expr(arg)?;
+                }
+                self.emit(match builtin {
+                    Builtin::Len => Op::Len,
+                    Builtin::Push => Op::Push,
+                }, span);
+            }
+            ExprKind::Array(elements) => {
+                for element in elements {
+                    self.expr(element)?;
+                }
+                self.emit(Op::MakeArray(elements.len() as u32), span);
+            }
+            ExprKind::Index(array, index) => {
+                self.expr(array)?;
+                self.expr(index)?;
+                self.emit(Op::GetIndex, span);
+            }
+            ExprKind::Interpolation(parts) => {
+                for part in parts {
+                    match part {
+                        InterpolationPart::Text(text) => {
+                            let text = self.constant(Constant::String(text.clone()));
+                            self.emit(Op::Constant(text), span);
+                        }
+                        InterpolationPart::Expr(value) => self.expr(value)?,
+                    }
+                }
+                self.emit(Op::Concat(parts.len() as u32), span);
+            }
+            ExprKind::New(_, inits) => {
+                for init in inits {
+                    self.expr(&init.value)?;
+                }
+                let names = self.constant(Constant::Names(inits.iter().map(|init| init.name.clone()).collect()));
+                self.emit(Op::MakeStruct(names), span);
+            }
+            ExprKind::Field(object, field) => {
+                self.expr(object)?;
+                let field = self.constant(Constant::String(field.clone()));
+                self.emit(Op::GetField(field), span);
+            }
+        }
+        Ok(())
+    }
+
+    /// Compiles `input`: the current values of the variables it reads into and the format string go
+    /// on the stack, and `Input` replaces them with the new values, which are stored back.
+    fn input(&mut self, args: &[Expr], span: Span) -> Result<(), CompileError> {
+        let Some((fmt, targets)) = args.split_first() else {
+            return Err(CompileError::codegen("`input` without a format string", span));
+        };
+        let mut places = vec![];
+        for target in targets {
+            let Some(place) = self.place(target)? else {
+                return Err(CompileError::codegen("`input` can only read into a variable", target.span));
+            };
+            self.load(&place, span);
+            places.push(place);
+        }
+        self.expr(fmt)?;
+        self.emit(Op::Input(targets.len() as u32), span);
+        for place in places.iter().rev() {
+            self.store(place, span);
+        }
+        self.emit(Op::Unit, span);
+        Ok(())
+    }
+
+    /// Evaluates the parts of a place that are not variables or fields, or returns `None` for an
+    /// expression that is not a place.
+    fn place(&mut self, expr: &Expr) -> Result<Option<Place>, CompileError> {
+        Ok(match &expr.kind {
+            ExprKind::VarUse(_) => Some(Place { root: Root::Local(self.local(expr.symbol, expr.span)?), fields: vec![] }),
+            ExprKind::Field(object, field) => self.place(object)?.map(|mut place| {
+                place.fields.push(field.clone());
+                place
+            }),
+            ExprKind::Index(array, index) => {
+                let (array_slot, index_slot) = (self.temp(), self.temp());
+                self.expr(array)?;
+                self.emit(Op::SetLocal(array_slot), expr.span);
+                self.expr(index)?;
+                self.emit(Op::SetLocal(index_slot), expr.span);
+                Some(Place { root: Root::Element { array: array_slot, index: index_slot }, fields: vec![] })
+            }
+            _ => None,
+        })
+    }
+
+    fn load(&mut self, place: &Place, span: Span) {
+        match place.root {
+            Root::Local(slot) => {
+                self.emit(Op::GetLocal(slot), span);
+            }
+            Root::Element { array, index } => {
+                self.emit(Op::GetLocal(array), span);
+                self.emit(Op::GetLocal(index), span);
+                self.emit(Op::GetIndex, span);
+            }
+        }
+        for field in &place.fields {
+            let field = self.constant(Constant::String(field.clone()));
+            self.emit(Op::GetField(field), span);
+        }
+    }
+
+    /// Stores the value on top of the stack into a place.
+    fn store(&mut self, place: &Place, span: Span) {
+        let path = (!place.fields.is_empty()).then(|| self.constant(Constant::Names(place.fields.clone())));
+        match (&place.root, path) {
+            (Root::Local(slot), None) => {
+                self.emit(Op::SetLocal(*slot), span);
+            }
+            (Root::Local(slot), Some(path)) => {
+                self.emit(Op::SetLocalField(*slot, path), span);
+            }
+            (Root::Element { array, index }, path) => {
+                self.emit(Op::GetLocal(*array), span);
+                self.emit(Op::GetLocal(*index), span);
+                self.emit(path.map_or(Op::SetIndex, Op::SetIndexField), span);
+            }
+        }
+    }
+
+    fn bool(&mut self, value: bool, span: Span) {
+        let index = self.constant(Constant::Bool(value));
+        self.emit(Op::Constant(index), span);
+    }
+
+    fn declare(&mut self, symbol: Option<SymbolId>, span: Span) -> Result<u32, CompileError> {
+        let id = symbol.ok_or_else(|| CompileError::codegen("variable without a symbol", span))?;
+        let slot = self.temp();
+        self.locals.insert(id, slot);
+        Ok(slot)
+    }
+
+    fn local(&self, symbol: Option<SymbolId>, span: Span) -> Result<u32, CompileError> {
+        symbol.and_then(|id| self.locals.get(&id)).copied().ok_or_else(|| CompileError::codegen("variable without a slot", span))
+    }
+
+    /// Adds a local that no variable names.
+    fn temp(&mut self) -> u32 {
+        self.function.locals += 1;
+        self.function.locals - 1
+    }
+
+    /// Adds a constant to the pool, reusing an equal one.
+    fn constant(&mut self, constant: Constant) -> u32 {
+        let existing = self.program.constants.iter().position(|c| match (c, &constant) {
+            // Compared by bits, so `0.0` and `-0.0` stay apart.
+            (Constant::Number(a), Constant::Number(b)) => a.to_bits() == b.to_bits(),
+            (a, b) => a == b,
+        });
+        match existing {
+            Some(index) => index as u32,
+            None => {
+                self.program.constants.push(constant);
+                self.program.constants.len() as u32 - 1
+            }
+        }
+    }
+
+    fn emit(&mut self, op: Op, span: Span) -> usize {
+        self.function.code.push(op);
+        self.function.spans.push(span);
+        self.function.code.len() - 1
+    }
+
+    fn here(&self) -> usize {
+        self.function.code.len()
+    }
+
+    /// Points a jump at the next instruction.
+    fn patch(&mut self, jump: usize) {
+        self.patch_to(jump, self.here());
+    }
+
+    fn patch_to(&mut self, jump: usize, target: usize) {
+        let target = target as u32;
+        self.function.code[jump] = match self.function.code[jump] {
+            Op::Jump(_) => Op::Jump(target),
+            Op::JumpIfFalse(_) => Op::JumpIfFalse(target),
+            op => op,
+        };
+    }
+}
+
+impl Default for BytecodeCompiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
\ No newline at end of file
@@ -0,0 +1,43 @@
+use std::fmt::Write;
+
+use super::error::CompileError;
+use super::source_map::SourceMap;
+
+/// Renders an error as a file location, the offending source line and a caret underline:
+///
+/// ```text
+/// syntax error: expected `=`, found number
+///  --> main.lamp:1:15
+///   |
+/// 1 | var a: number 2
+///   |               ^
+/// ```
+pub fn render(error: &CompileError, file_name: &str, source_map: &SourceMap) -> String {
+    let start = source_map.location(error.span.start);
+    let end = source_map.location(error.span.end);
+    let line = source_map.line(start.line).replace('\t', " ");
+
+    let gutter = " ".repeat(start.line.to_string().len());
+    let underline_len = if end.line == start.line && end.column > start.column {
+        end.column - start.column
+    } else {
+        line.chars().count().saturating_sub(start.column - 1).max(1)
+    };
+
+    let mut out = String::new();
+    let _ = writeln!(out, "{}: {}", error.kind, error.message);
+    let _ = writeln!(out, "{}--> {}:{}:{}", gutter, file_name, start.line, start.column);
+    let _ = writeln!(out, "{} |", gutter);
+    let _ = writeln!(out, "{} | {}", start.line, line);
+    let _ = writeln!(out, "{} | {}{}", gutter, " ".repeat(start.column - 1), "^".repeat(underline_len));
+    if !error.notes.is_empty() || error.help.is_some() {
+        let _ = writeln!(out, "{} |", gutter);
+    }
+    for note in &error.notes {
+        let _ = writeln!(out, "{} = note: {}", gutter, note);
+    }
+    if let Some(help) = &error.help {
+        let _ = writeln!(out, "{} = help: {}", gutter, help);
+    }
+    out
+}
\ No newline at end of file
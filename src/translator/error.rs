@@ -0,0 +1,105 @@
+use std::fmt;
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    pub fn merge(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    Lexical,
+    Syntax,
+    Name,
+    Type,
+    Codegen,
+    /// Raised by the interpreter while running a checked program.
+    Runtime,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ErrorKind::Lexical => "lexical error",
+            ErrorKind::Syntax => "syntax error",
+            ErrorKind::Name => "name error",
+            ErrorKind::Type => "type error",
+            ErrorKind::Codegen => "codegen error",
+            ErrorKind::Runtime => "runtime error",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CompileError {
+    pub kind: ErrorKind,
+    pub message: String,
+    pub span: Span,
+    pub notes: Vec<String>,
+    pub help: Option<String>,
+}
+
+impl CompileError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>, span: Span) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+            span,
+            notes: vec![],
+            help: None,
+        }
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    pub fn lexical(message: impl Into<String>, span: Span) -> Self {
+        Self::new(ErrorKind::Lexical, message, span)
+    }
+
+    pub fn syntax(message: impl Into<String>, span: Span) -> Self {
+        Self::new(ErrorKind::Syntax, message, span)
+    }
+
+    pub fn name(message: impl Into<String>, span: Span) -> Self {
+        Self::new(ErrorKind::Name, message, span)
+    }
+
+    pub fn typ(message: impl Into<String>, span: Span) -> Self {
+        Self::new(ErrorKind::Type, message, span)
+    }
+
+    pub fn codegen(message: impl Into<String>, span: Span) -> Self {
+        Self::new(ErrorKind::Codegen, message, span)
+    }
+
+    pub fn runtime(message: impl Into<String>, span: Span) -> Self {
+        Self::new(ErrorKind::Runtime, message, span)
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} at {}..{}", self.kind, self.message, self.span.start, self.span.end)
+    }
+}
+
+impl std::error::Error for CompileError {}
@@ -0,0 +1,696 @@
+//! A tree-walking interpreter for checked programs.
+//!
+//! It runs the AST directly, with the `io` and `math` modules implemented in Rust, and follows the
+//! semantics of the generated C, except that the `math` functions use Rust's `f64` operations;
+//! `pow` rounds its exponent to an integer like `lib/src/math.c` does.
+
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+
+use super::ast::{Expr, ExprKind, InterpolationPart, Iterable, Stmt, StmtKind, TypedArgument};
+use super::builtins::{Builtin, Placeholder};
+use super::error::{CompileError, Span};
+use super::symbols::SymbolId;
+use super::value::{IntType, Value, ValueType};
+
+/// Deeper recursion is reported as an error instead of overflowing the interpreter's own stack.
+pub const MAX_CALL_DEPTH: usize = 10_000;
+
+/// `input` reads at most this many bytes into a string, like the `%255s` of the C runtime.
+const MAX_INPUT_WORD: usize = 255;
+
+#[derive(Debug, Clone)]
+pub enum RuntimeValue {
+    Number(f64),
+    /// An integer of the given type, always within its range.
+    Int(IntType, i128),
+    String(String),
+    Bool(bool),
+    Unit,
+    /// A struct with its fields, held by value like in C: copying it copies the fields.
+    Struct(Vec<(String, RuntimeValue)>),
+    Array(Rc<RefCell<Vec<RuntimeValue>>>),
+}
+
+/// How a statement finished.
+enum Flow {
+    Normal,
+    Break(Option<String>),
+    Continue(Option<String>),
+    Return(RuntimeValue),
+}
+
+struct Function {
+    params: Vec<TypedArgument>,
+    body: Stmt,
+}
+
+/// The variables of one function call, by symbol; symbols are unique per declaration, so the
+/// variables of nested blocks need no scopes of their own.
+#[derive(Default)]
+struct Frame {
+    variables: HashMap<SymbolId, Slot>,
+}
+
+enum Slot {
+    Value(RuntimeValue),
+    /// The `self` of a method, which refers to the receiver like the pointer in the generated C.
+    Receiver(Place),
+}
+
+/// A storage location that can be assigned to: a variable or an array element, followed by fields.
+#[derive(Clone)]
+struct Place {
+    root: Root,
+    fields: Vec<String>,
+}
+
+#[derive(Clone)]
+enum Root {
+    /// A variable in the frame with the given index.
+    Variable(usize, SymbolId),
+    Element(Rc<RefCell<Vec<RuntimeValue>>>, usize),
+}
+
+pub struct Interpreter<'a> {
+    input: &'a mut dyn BufRead,
+    output: &'a mut dyn Write,
+    functions: HashMap<SymbolId, Rc<Function>>,
+    frames: Vec<Frame>,
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(input: &'a mut dyn BufRead, output: &'a mut dyn Write) -> Self {
+        Self { input, output, functions: HashMap::new(), frames: vec![] }
+    }
+
+    /// Runs a checked program. Its output is flushed even when it stops with a runtime error.
+    pub fn run(&mut self, program: &Stmt) -> Result<(), CompileError> {
+        self.run_with_globals(program, &mut HashMap::new())
+    }
+
+    /// Runs a checked program whose top level starts with the variables in `globals`, and leaves
+    /// its top-level variables there afterwards, also when it stops with a runtime error. The REPL
+    /// keeps its variables from one input to the next this way.
+    pub fn run_with_globals(&mut self, program: &Stmt, globals: &mut HashMap<SymbolId, RuntimeValue>) -> Result<(), CompileError> {
+        let StmtKind::Block(stmts) = &program.kind else {
+            return Err(unchecked(program.span));
+        };
+        self.declare_functions(stmts);
+        let variables = globals.drain().map(|(id, value)| (id, Slot::Value(value))).collect();
+        self.frames.push(Frame { variables });
+        let result = self.execute(program);
+        let frame = self.frames.pop().expect("the top-level frame was pushed above");
+        globals.extend(frame.variables.into_iter().filter_map(|(id, slot)| match slot {
+            Slot::Value(value) => Some((id, value)),
+            Slot::Receiver(_) => None,
+        }));
+        self.output.flush().map_err(|err| output_error(err, program.span))?;
+        result.map(|_| ())
+    }
+
+    fn declare_functions(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            match &stmt.kind {
+                StmtKind::FunctionDef(..) => self.declare_function(stmt),
+                StmtKind::Impl(_, methods) => methods.iter().for_each(|method| self.declare_function(method)),
+                _ => {}
+            }
+        }
+    }
+
+    fn declare_function(&mut self, stmt: &Stmt) {
+        if let (StmtKind::FunctionDef(_, params, body, _), Some(id)) = (&stmt.kind, stmt.symbol) {
+            self.functions.insert(id, Rc::new(Function { params: params.clone(), body: *body.clone() }));
+        }
+    }
+
+    fn execute(&mut self, stmt: &Stmt) -> Result<Flow, CompileError> {
+        match &stmt.kind {
+            StmtKind::VarDef(_, value, _) => {
+                let value = self.eval(value)?;
+                self.define(stmt.symbol, Slot::Value(value), stmt.span)?;
+            }
+            StmtKind::Assign(target, value) => {
+                let value = self.eval(value)?;
+                let place = self.place(target)?.ok_or_else(|| unchecked(target.span))?;
+                self.with_place(&place, target.span, |slot| *slot = value)?;
+            }
+            StmtKind::If(cond, body, else_body) => {
+                if self.eval_bool(cond)? {
+                    return self.execute(body);
+                } else if let Some(else_body) = else_body.as_ref() {
+                    return self.execute(else_body);
+                }
+            }
+            StmtKind::Block(stmts) => {
+                for stmt in stmts {
+                    let flow = self.execute(stmt)?;
+                    if !matches!(flow, Flow::Normal) {
+                        return Ok(flow);
+                    }
+                }
+            }
+            StmtKind::While(..) | StmtKind::For(..) => return self.execute_loop(stmt, None),
+            StmtKind::Labeled(label, body) => return self.execute_loop(body, Some(label)),
+            StmtKind::Break(label) => return Ok(Flow::Break(label.clone())),
+            StmtKind::Continue(label) => return Ok(Flow::Continue(label.clone())),
+            StmtKind::Function(expr) => {
+                self.eval(expr)?;
+            }
+            StmtKind::Return(Some(value)) => return Ok(Flow::Return(self.eval(value)?)),
+            StmtKind::Return(None) => return Ok(Flow::Return(RuntimeValue::Unit)),
+            StmtKind::FunctionDef(..) | StmtKind::Use(_) | StmtKind::Struct(..) | StmtKind::Impl(..) => {}
+        }
+        Ok(Flow::Normal)
+    }
+
+    /// Runs a `while` or `for` loop. The end of a range and the length of an array are evaluated
+    /// once, like in the generated C, so pushing to the array in the body does not extend the loop.
+    fn execute_loop(&mut self, stmt: &Stmt, label: Option<&str>) -> Result<Flow, CompileError> {
+        match &stmt.kind {
+            StmtKind::While(cond, body) => {
+                while self.eval_bool(cond)? {
+                    if let Some(flow) = self.iterate(body, label)? {
+                        return Ok(flow);
+                    }
+                }
+            }
+            StmtKind::For(_, Iterable::Range(start, end), body) => {
+                let (typ, start) = self.eval_int(start)?;
+                let (_, end) = self.eval_int(end)?;
+                for i in start..end {
+                    self.define(stmt.symbol, Slot::Value(RuntimeValue::Int(typ, i)), stmt.span)?;
+                    if let Some(flow) = self.iterate(body, label)? {
+                        return Ok(flow);
+                    }
+                }
+            }
+            StmtKind::For(_, Iterable::Array(array), body) => {
+                let items = self.eval_array(array)?;
+                let len = items.borrow().len();
+                for i in 0..len {
+                    let item = items.borrow()[i].clone();
+                    self.define(stmt.symbol, Slot::Value(item), stmt.span)?;
+                    if let Some(flow) = self.iterate(body, label)? {
+                        return Ok(flow);
+                    }
+                }
+            }
+            _ => return Err(unchecked(stmt.span)),
+        }
+        Ok(Flow::Normal)
+    }
+
+    /// Runs one iteration of a loop body. Returns how the loop statement finishes if it ends here,
+    /// or `None` to go on with the next iteration.
+    fn iterate(&mut self, body: &Stmt, label: Option<&str>) -> Result<Option<Flow>, CompileError> {
+        let targets_this_loop = |target: &Option<String>| target.as_deref().is_none_or(|target| Some(target) == label);
+        Ok(match self.execute(body)? {
+            Flow::Normal => None,
+            Flow::Continue(target) if targets_this_loop(&target) => None,
+            Flow::Break(target) if targets_this_loop(&target) => Some(Flow::Normal),
+            flow => Some(flow),
+        })
+    }
+
+    fn define(&mut self, symbol: Option<SymbolId>, slot: Slot, span: Span) -> Result<(), CompileError> {
+        let id = symbol.ok_or_else(|| unchecked(span))?;
+        self.frames.last_mut().expect("a program runs in a frame").variables.insert(id, slot);
+        Ok(())
+    }
+
+    /// Evaluates an expression. Each kind of expression is handled by its own method, which keeps
+    /// this frame small, as the interpreter recurses through it for every nested call.
+    fn eval(&mut self, expr: &Expr) -> Result<RuntimeValue, CompileError> {
+        match &expr.kind {
+            ExprKind::Value(value) => literal(value, expr.typ.as_ref(), expr.span),
+            ExprKind::VarUse(_) | ExprKind::Index(_, _) => self.read(expr),
+            ExprKind::Binary(..) | ExprKind::Condition(..) | ExprKind::Unary(..) | ExprKind::Convert(..) => self.eval_operator(expr),
+            ExprKind::Functional(..) | ExprKind::MethodCall(..) => self.eval_call(expr),
+            ExprKind::Builtin(builtin, args) => self.eval_builtin(*builtin, args, expr.span),
+            ExprKind::Array(..) | ExprKind::Interpolation(..) | ExprKind::New(..) | ExprKind::Field(..) => self.eval_compound(expr),
+        }
+    }
+
+    fn read(&mut self, expr: &Expr) -> Result<RuntimeValue, CompileError> {
+        let place = self.place(expr)?.ok_or_else(|| unchecked(expr.span))?;
+        self.with_place(&place, expr.span, |value| value.clone())
+    }
+
+    fn eval_operator(&mut self, expr: &Expr) -> Result<RuntimeValue, CompileError> {
+        Ok(match &expr.kind {
+            ExprKind::Binary(op, left, right) => {
+                let left = self.eval(left)?;
+                let right = self.eval(right)?;
+                arithmetic(*op, left, right, expr.span)?
+            }
+            ExprKind::Condition(op, left, right) if op == "&&" => {
+                RuntimeValue::Bool(self.eval_bool(left)? && self.eval_bool(right)?)
+            }
+            ExprKind::Condition(op, left, right) if op == "||" => {
+                RuntimeValue::Bool(self.eval_bool(left)? || self.eval_bool(right)?)
+            }
+            ExprKind::Condition(op, left, right) => {
+                let left = self.eval(left)?;
+                let right = self.eval(right)?;
+                RuntimeValue::Bool(compare(op, &left, &right, expr.span)?)
+            }
+            ExprKind::Unary('!', operand) => RuntimeValue::Bool(!self.eval_bool(operand)?),
+            ExprKind::Unary(_, operand) => match self.eval(operand)? {
+                RuntimeValue::Number(n) => RuntimeValue::Number(-n),
+                RuntimeValue::Int(typ, n) => RuntimeValue::Int(typ, wrap(typ, -n)),
+                _ => return Err(unchecked(expr.span)),
+            },
+            ExprKind::Convert(typ, operand) => {
+                let value = self.eval(operand)?;
+                convert(typ, value, expr.span)?
+            }
+            _ => return Err(unchecked(expr.span)),
+        })
+    }
+
+    fn eval_call(&mut self, expr: &Expr) -> Result<RuntimeValue, CompileError> {
+        let function = expr.symbol.and_then(|id| self.functions.get(&id)).cloned();
+        match (&expr.kind, function) {
+            (ExprKind::Functional(_, args), Some(function)) => {
+                let args = self.eval_all(args)?;
+                self.call(&function, None, args, expr.span)
+            }
+            (ExprKind::Functional(name, args), None) => self.call_native(name, args, expr.span),
+            (ExprKind::MethodCall(receiver, _, args), Some(function)) => {
+                // A temporary receiver has no place, so the method gets a copy that is dropped afterwards.
+                let receiver = match self.place(receiver)? {
+                    Some(place) => Slot::Receiver(place),
+                    None => Slot::Value(self.eval(receiver)?),
+                };
+                let args = self.eval_all(args)?;
+                self.call(&function, Some(receiver), args, expr.span)
+            }
+            _ => Err(unchecked(expr.span)),
+        }
+    }
+
+    fn eval_builtin(&mut self, builtin: Builtin, args: &[Expr], span: Span) -> Result<RuntimeValue, CompileError> {
+        let [array, rest @ ..] = args else {
+            return Err(unchecked(span));
+        };
+        let array = self.eval_array(array)?;
+        Ok(match builtin {
+            Builtin::Len => RuntimeValue::Int(IntType::I32, array.borrow().len() as i128),
+            Builtin::Push => {
+                let items = self.eval_all(rest)?;
+                array.borrow_mut().extend(items);
+                RuntimeValue::Unit
+            }
+        })
+    }
+
+    /// Evaluates the expressions that build or take apart arrays, strings and structs.
+    fn eval_compound(&mut self, expr: &Expr) -> Result<RuntimeValue, CompileError> {
+        Ok(match &expr.kind {
+            ExprKind::Array(elements) => RuntimeValue::Array(Rc::new(RefCell::new(self.eval_all(elements)?))),
+            ExprKind::Interpolation(parts) => {
+                let mut text = String::new();
+                for part in parts {
+                    match part {
+                        InterpolationPart::Text(part) => text.push_str(part),
+                        InterpolationPart::Expr(value) => text.push_str(&display(&self.eval(value)?)),
+                    }
+                }
+                RuntimeValue::String(text)
+            }
+            ExprKind::New(_, inits) => {
+                let mut fields = vec![];
+                for init in inits {
+                    fields.push((init.name.clone(), self.eval(&init.value)?));
+                }
+                RuntimeValue::Struct(fields)
+            }
+            ExprKind::Field(object, field) => match self.eval(object)? {
+                RuntimeValue::Struct(fields) => fields.into_iter()
+                    .find_map(|(name, value)| (name == *field).then_some(value))
+                    .ok_or_else(|| unchecked(expr.span))?,
+                _ => return Err(unchecked(expr.span)),
+            },
+            _ => return Err(unchecked(expr.span)),
+        })
+    }
+
+    fn eval_all(&mut self, exprs: &[Expr]) -> Result<Vec<RuntimeValue>, CompileError> {
+        exprs.iter().map(|expr| self.eval(expr)).collect()
+    }
+
+    fn eval_bool(&mut self, expr: &Expr) -> Result<bool, CompileError> {
+        match self.eval(expr)? {
+            RuntimeValue::Bool(b) => Ok(b),
+            _ => Err(unchecked(expr.span)),
+        }
+    }
+
+    fn eval_int(&mut self, expr: &Expr) -> Result<(IntType, i128), CompileError> {
+        match self.eval(expr)? {
+            RuntimeValue::Int(typ, n) => Ok((typ, n)),
+            _ => Err(unchecked(expr.span)),
+        }
+    }
+
+    fn eval_array(&mut self, expr: &Expr) -> Result<Rc<RefCell<Vec<RuntimeValue>>>, CompileError> {
+        match self.eval(expr)? {
+            RuntimeValue::Array(items) => Ok(items),
+            _ => Err(unchecked(expr.span)),
+        }
+    }
+
+    /// Finds the storage location an expression names, or `None` for a temporary value. Indices are
+    /// evaluated and bounds checked here, once.
+    fn place(&mut self, expr: &Expr) -> Result<Option<Place>, CompileError> {
+        Ok(match &expr.kind {
+            ExprKind::VarUse(_) => {
+                let id = expr.symbol.ok_or_else(|| unchecked(expr.span))?;
+                let frame = self.frames.len() - 1;
+                match self.frames[frame].variables.get(&id) {
+                    Some(Slot::Receiver(place)) => Some(place.clone()),
+                    Some(Slot::Value(_)) => Some(Place { root: Root::Variable(frame, id), fields: vec![] }),
+                    None => return Err(unchecked(expr.span)),
+                }
+            }
+            ExprKind::Field(object, field) => self.place(object)?.map(|mut place| {
+                place.fields.push(field.clone());
+                place
+            }),
+            ExprKind::Index(array, index) => {
+                let items = self.eval_array(array)?;
+                let (_, index) = self.eval_int(index)?;
+                let index = element_index(index, items.borrow().len(), expr.span)?;
+                Some(Place { root: Root::Element(items, index), fields: vec![] })
+            }
+            _ => None,
+        })
+    }
+
+    /// Calls `access` with the value stored at a place.
+    fn with_place<T>(&mut self, place: &Place, span: Span, access: impl FnOnce(&mut RuntimeValue) -> T) -> Result<T, CompileError> {
+        match &place.root {
+            Root::Variable(frame, id) => match self.frames[*frame].variables.get_mut(id) {
+                Some(Slot::Value(value)) => Ok(access(field_mut(value, &place.fields).ok_or_else(|| unchecked(span))?)),
+                _ => Err(unchecked(span)),
+            },
+            Root::Element(items, index) => {
+                let mut items = items.borrow_mut();
+                Ok(access(field_mut(&mut items[*index], &place.fields).ok_or_else(|| unchecked(span))?))
+            }
+        }
+    }
+
+    fn call(&mut self, function: &Function, receiver: Option<Slot>, args: Vec<RuntimeValue>, span: Span) -> Result<RuntimeValue, CompileError> {
+        if self.frames.len() > MAX_CALL_DEPTH {
+            return Err(CompileError::runtime("too many nested calls", span)
+                .with_note(format!("the interpreter supports at most {} nested calls", MAX_CALL_DEPTH)));
+        }
+        let mut frame = Frame::default();
+        let mut params = function.params.iter();
+        let slots = receiver.into_iter().chain(args.into_iter().map(Slot::Value));
+        for (param, slot) in params.by_ref().zip(slots) {
+            frame.variables.insert(param.symbol.ok_or_else(|| unchecked(span))?, slot);
+        }
+        if params.next().is_some() {
+            return Err(unchecked(span));
+        }
+        self.frames.push(frame);
+        let flow = self.execute(&function.body);
+        self.frames.pop();
+        Ok(match flow? {
+            Flow::Return(value) => value,
+            _ => RuntimeValue::Unit,
+        })
+    }
+
+    /// Calls a function of the `io` or `math` module.
+    fn call_native(&mut self, name: &str, args: &[Expr], span: Span) -> Result<RuntimeValue, CompileError> {
+        if name == "input" {
+            return self.input(args, span);
+        }
+        let values = self.eval_all(args)?;
+        call_native(name, &values, self.output, span)
+    }
+
+    fn input(&mut self, args: &[Expr], span: Span) -> Result<RuntimeValue, CompileError> {
+        let Some((fmt, targets)) = args.split_first() else {
+            return Err(unchecked(span));
+        };
+        let RuntimeValue::String(fmt) = self.eval(fmt)? else {
+            return Err(unchecked(span));
+        };
+        let values = self.eval_all(targets)?;
+        let values = read_input(self.input, self.output, &fmt, values, span)?;
+        for (target, value) in targets.iter().zip(values) {
+            let place = self.place(target)?.ok_or_else(|| unchecked(target.span))?;
+            self.with_place(&place, target.span, |slot| *slot = value)?;
+        }
+        Ok(RuntimeValue::Unit)
+    }
+}
+
+/// Calls a function of the `io` or `math` module other than `input`.
+pub fn call_native(name: &str, values: &[RuntimeValue], output: &mut dyn Write, span: Span) -> Result<RuntimeValue, CompileError> {
+    let number = |i: usize| match values.get(i) {
+        Some(RuntimeValue::Number(n)) => Ok(*n),
+        _ => Err(unchecked(span)),
+    };
+    let result = match name {
+        "format" => return Ok(RuntimeValue::String(format(values, span)?)),
+        "print" | "println" => {
+            let text = format(values, span)?;
+            let newline = if name == "println" { "\n" } else { "" };
+            write!(output, "{}{}", text, newline).map_err(|err| output_error(err, span))?;
+            return Ok(RuntimeValue::Unit);
+        }
+        "square" => number(0)? * number(0)?,
+        "abs_d" => number(0)?.abs(),
+        "sqrt" => number(0)?.sqrt(),
+        "ln" => number(0)?.ln(),
+        "log2" => number(0)?.log2(),
+        "log10" => number(0)?.log10(),
+        "sin" => number(0)?.sin(),
+        "cos" => number(0)?.cos(),
+        "tan" => number(0)?.tan(),
+        "round" => number(0)?.round(),
+        "floor" => number(0)?.floor(),
+        "ceil" => number(0)?.ceil(),
+        "pow" => number(0)?.powi(number(1)?.round() as i32),
+        _ => return Err(CompileError::runtime(format!("function `{}` is not available in the interpreter", name), span)),
+    };
+    Ok(RuntimeValue::Number(result))
+}
+
+/// Implements `input`: reads a whitespace separated word for each placeholder of the format string
+/// and returns the new values of the variables after it, which start as `values`. A `{f}` word that
+/// is not a number leaves its variable unchanged.
+pub fn read_input(input: &mut dyn BufRead, output: &mut dyn Write, fmt: &str, mut values: Vec<RuntimeValue>, span: Span) -> Result<Vec<RuntimeValue>, CompileError> {
+    output.flush().map_err(|err| output_error(err, span))?;
+    for (placeholder, value) in Placeholder::parse_all(fmt).into_iter().zip(values.iter_mut()) {
+        let read = match placeholder {
+            Ok(Placeholder::String) => read_word(input, MAX_INPUT_WORD, span)?.map(RuntimeValue::String),
+            Ok(Placeholder::Number) => read_word(input, usize::MAX, span)?
+                .and_then(|word| word.parse().ok())
+                .map(RuntimeValue::Number),
+            _ => None,
+        };
+        if let Some(read) = read {
+            *value = read;
+        }
+    }
+    Ok(values)
+}
+
+/// Skips whitespace and reads up to `max` bytes of the next word; `None` at the end of the input.
+fn read_word(input: &mut dyn BufRead, max: usize, span: Span) -> Result<Option<String>, CompileError> {
+    let mut word = vec![];
+    loop {
+        let buffer = input.fill_buf().map_err(|err| CompileError::runtime(format!("cannot read the input: {}", err), span))?;
+        let Some(&byte) = buffer.first() else {
+            break;
+        };
+        if byte.is_ascii_whitespace() {
+            if !word.is_empty() {
+                break;
+            }
+        } else if word.len() == max {
+            break;
+        } else {
+            word.push(byte);
+        }
+        input.consume(1);
+    }
+    Ok((!word.is_empty()).then(|| String::from_utf8_lossy(&word).into_owned()))
+}
+
+fn literal(value: &Value, typ: Option<&ValueType>, span: Span) -> Result<RuntimeValue, CompileError> {
+    Ok(match (value, typ) {
+        (Value::Number(n), _) => RuntimeValue::Number(*n),
+        (Value::Integer(n), Some(ValueType::Number)) => RuntimeValue::Number(*n as f64),
+        (Value::Integer(n), Some(ValueType::Int(typ))) => RuntimeValue::Int(*typ, wrap(*typ, *n as i128)),
+        (Value::Integer(_), _) => return Err(unchecked(span)),
+        (Value::String(s), _) => RuntimeValue::String(s.clone()),
+        (Value::Bool(b), _) => RuntimeValue::Bool(*b),
+    })
+}
+
+pub fn unchecked(span: Span) -> CompileError {
+    CompileError::runtime("the interpreter can only run programs that passed type checking", span)
+}
+
+pub fn output_error(err: std::io::Error, span: Span) -> CompileError {
+    CompileError::runtime(format!("cannot write the output: {}", err), span)
+}
+
+/// Checks an index into an array of length `len`.
+pub fn element_index(index: i128, len: usize, span: Span) -> Result<usize, CompileError> {
+    if index < 0 || index >= len as i128 {
+        return Err(CompileError::runtime(format!("index {} is out of bounds for an array of length {}", index, len), span));
+    }
+    Ok(index as usize)
+}
+
+/// Follows a path of field names into a struct value.
+pub fn field_mut<'v>(mut value: &'v mut RuntimeValue, fields: &[String]) -> Option<&'v mut RuntimeValue> {
+    for field in fields {
+        let RuntimeValue::Struct(values) = value else {
+            return None;
+        };
+        value = values.iter_mut().find_map(|(name, value)| (name == field).then_some(value))?;
+    }
+    Some(value)
+}
+
+/// Brings an integer into the range of its type, wrapping around like unsigned arithmetic in C.
+pub fn wrap(typ: IntType, n: i128) -> i128 {
+    let modulus = typ.max() - typ.min() + 1;
+    n.wrapping_sub(typ.min()).rem_euclid(modulus) + typ.min()
+}
+
+pub fn arithmetic(op: char, left: RuntimeValue, right: RuntimeValue, span: Span) -> Result<RuntimeValue, CompileError> {
+    match (left, right) {
+        (RuntimeValue::Number(l), RuntimeValue::Number(r)) => Ok(RuntimeValue::Number(match op {
+            '+' => l + r,
+            '-' => l - r,
+            '*' => l * r,
+            '/' => l / r,
+            _ => l % r,
+        })),
+        (RuntimeValue::Int(typ, l), RuntimeValue::Int(_, r)) => {
+            let n = match op {
+                '+' => l.wrapping_add(r),
+                '-' => l.wrapping_sub(r),
+                '*' => l.wrapping_mul(r),
+                _ if r == 0 => return Err(CompileError::runtime("division by zero", span)),
+                '/' => l / r,
+                _ => l % r,
+            };
+            Ok(RuntimeValue::Int(typ, wrap(typ, n)))
+        }
+        _ => Err(unchecked(span)),
+    }
+}
+
+pub fn compare(op: &str, left: &RuntimeValue, right: &RuntimeValue, span: Span) -> Result<bool, CompileError> {
+    let ordering = match (left, right) {
+        (RuntimeValue::Number(l), RuntimeValue::Number(r)) => l.partial_cmp(r),
+        (RuntimeValue::Int(_, l), RuntimeValue::Int(_, r)) => Some(l.cmp(r)),
+        (RuntimeValue::Bool(l), RuntimeValue::Bool(r)) => Some(l.cmp(r)),
+        _ => return Err(unchecked(span)),
+    };
+    Ok(match op {
+        "==" => ordering == Some(Ordering::Equal),
+        "!=" => ordering != Some(Ordering::Equal),
+        "<" => ordering == Some(Ordering::Less),
+        ">" => ordering == Some(Ordering::Greater),
+        "<=" => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+        ">=" => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+        _ => return Err(unchecked(span)),
+    })
+}
+
+/// Converts between numeric types; a `number` is truncated towards zero, like a C cast.
+pub fn convert(typ: &ValueType, value: RuntimeValue, span: Span) -> Result<RuntimeValue, CompileError> {
+    Ok(match (typ, value) {
+        (ValueType::Number, RuntimeValue::Number(n)) => RuntimeValue::Number(n),
+        (ValueType::Number, RuntimeValue::Int(_, n)) => RuntimeValue::Number(n as f64),
+        (ValueType::Int(typ), RuntimeValue::Number(n)) => RuntimeValue::Int(*typ, wrap(*typ, n as i128)),
+        (ValueType::Int(typ), RuntimeValue::Int(_, n)) => RuntimeValue::Int(*typ, wrap(*typ, n)),
+        _ => return Err(unchecked(span)),
+    })
+}
+
+/// Fills in the placeholders of a format string like `format_internal` in `lib/src/io.c`. A message
+/// without arguments is used as is, because the generated C passes it through `{s}`.
+fn format(values: &[RuntimeValue], span: Span) -> Result<String, CompileError> {
+    let Some((RuntimeValue::String(fmt), mut values)) = values.split_first() else {
+        return Err(unchecked(span));
+    };
+    if values.is_empty() {
+        return Ok(fmt.clone());
+    }
+    let chars: Vec<char> = fmt.chars().collect();
+    let mut text = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i] == '{' && i + 2 < chars.len() && chars[i + 2] == '}' {
+            if matches!(chars[i + 1], 's' | 'f' | 'i') && let Some((value, rest)) = values.split_first() {
+                text.push_str(&display(value));
+                values = rest;
+            }
+            i += 3;
+        } else {
+            text.push(chars[i]);
+            i += 1;
+        }
+    }
+    Ok(text)
+}
+
+/// The text of a value in a format string or an interpolated string.
+pub fn display(value: &RuntimeValue) -> String {
+    match value {
+        RuntimeValue::Number(n) => format_number(*n),
+        RuntimeValue::Int(_, n) => n.to_string(),
+        RuntimeValue::String(s) => s.clone(),
+        RuntimeValue::Bool(b) => b.to_string(),
+        RuntimeValue::Unit | RuntimeValue::Struct(_) | RuntimeValue::Array(_) => String::new(),
+    }
+}
+
+/// Formats a number like C's `%g`: six significant digits without trailing zeros, in scientific
+/// notation when the exponent is below -4 or above 5.
+fn format_number(n: f64) -> String {
+    if n.is_nan() {
+        return if n.is_sign_negative() { "-nan" } else { "nan" }.to_string();
+    }
+    if n.is_infinite() {
+        return if n < 0.0 { "-inf" } else { "inf" }.to_string();
+    }
+    if n == 0.0 {
+        return if n.is_sign_negative() { "-0" } else { "0" }.to_string();
+    }
+    let scientific = format!("{:.5e}", n);
+    let (mantissa, exponent) = scientific.split_once('e').expect("scientific notation has an exponent");
+    let exponent: i32 = exponent.parse().expect("the exponent is an integer");
+    if (-4..6).contains(&exponent) {
+        trim_fraction(&format!("{:.*}", (5 - exponent) as usize, n)).to_string()
+    } else {
+        let sign = if exponent < 0 { '-' } else { '+' };
+        format!("{}e{}{:02}", trim_fraction(mantissa), sign, exponent.abs())
+    }
+}
+
+fn trim_fraction(number: &str) -> &str {
+    if number.contains('.') {
+        number.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        number
+    }
+}
\ No newline at end of file
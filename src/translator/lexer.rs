@@ -1,10 +1,15 @@
 ﻿use std::collections::HashMap;
+use std::fmt;
+
+use super::error::{CompileError, Span};
 
 pub struct Lexer {
     input: String,
     pos: usize,
     pub tokens: Vec<Token>,
     keywords: HashMap<&'static str, TokenType>,
+    /// Doc comment lines waiting for the next token.
+    doc: Vec<String>,
 }
 
 #[derive(PartialEq, Debug, Copy, Clone)]
@@ -19,10 +24,13 @@ pub enum TokenType {
     Minus,
     Star,
     Slash,
+    Percent,
     LeftParen,
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Eq,
     NoEq,
     EqEq,
@@ -32,8 +40,12 @@ pub enum TokenType {
     LtEq,
     AndAnd,
     OrOr,
+    Not,
     Comma,
     Colon,
+    Semicolon,
+    Dot,
+    DotDot,
 
     // Keywords
     Var,
@@ -41,31 +53,117 @@ pub enum TokenType {
     Else,
     Func,
     While,
+    For,
+    In,
+    Break,
+    Continue,
     Return,
     Use,
     Struct,
+    Impl,
     New,
+    True,
+    False,
 
     Eof
 }
 
-#[derive(PartialEq, Debug, Clone)]
+impl fmt::Display for TokenType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use TokenType::*;
+        let text = match self {
+            String => "string",
+            Number => "number",
+            Word => "identifier",
+            Plus => "`+`",
+            Minus => "`-`",
+            Star => "`*`",
+            Slash => "`/`",
+            Percent => "`%`",
+            LeftParen => "`(`",
+            RightParen => "`)`",
+            LeftBrace => "`{`",
+            RightBrace => "`}`",
+            LeftBracket => "`[`",
+            RightBracket => "`]`",
+            Eq => "`=`",
+            NoEq => "`!=`",
+            EqEq => "`==`",
+            Gt => "`>`",
+            GtEq => "`>=`",
+            Lt => "`<`",
+            LtEq => "`<=`",
+            AndAnd => "`&&`",
+            OrOr => "`||`",
+            Not => "`!`",
+            Comma => "`,`",
+            Colon => "`:`",
+            Semicolon => "`;`",
+            Dot => "`.`",
+            DotDot => "`..`",
+            Var => "`var`",
+            If => "`if`",
+            Else => "`else`",
+            Func => "`func`",
+            While => "`while`",
+            For => "`for`",
+            In => "`in`",
+            Break => "`break`",
+            Continue => "`continue`",
+            Return => "`return`",
+            Use => "`use`",
+            Struct => "`struct`",
+            Impl => "`impl`",
+            New => "`new`",
+            True => "`true`",
+            False => "`false`",
+            Eof => "end of file",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum Lexeme {
-    NumberLexeme(f32),
-    StringLexeme(String),
-    WordLexeme(String),
+    Integer(u64),
+    Float(f64),
+    String(String),
+    /// A string literal containing `${...}`.
+    Interpolated(Vec<StringPart>),
+    Word(String),
     None
 }
 
+#[derive(Debug, Clone)]
+pub enum StringPart {
+    Text(String),
+    /// The tokens of an interpolated expression, ending with `Eof`.
+    Code(Vec<Token>),
+}
+
 #[derive(Debug, Clone)]
 pub struct Token {
     pub token_type: TokenType,
     pub lexeme: Lexeme,
     pub start: usize,
     pub end: usize,
+    /// Text of the `///` doc comments written right before this token.
+    pub doc: Option<String>,
+    /// Whether a line break separates this token from the previous one.
+    pub line_break: bool,
 }
 
-static OPERATORS: &str = "+-*/=<>(){}!&|,:";
+impl Token {
+    pub fn new(token_type: TokenType, lexeme: Lexeme, start: usize, end: usize) -> Self {
+        Self { token_type, lexeme, start, end, doc: None, line_break: false }
+    }
+
+    pub fn span(&self) -> Span {
+        Span::new(self.start, self.end)
+    }
+}
+
+static OPERATORS: &str = "+-*/%=<>(){}[]!&|,:;.";
 
 impl Lexer {
     pub fn new(input: String) -> Lexer {
@@ -74,6 +172,7 @@ impl Lexer {
             pos: 0,
             tokens: Vec::new(),
             keywords: Self::create_keywords(),
+            doc: Vec::new(),
         }
     }
 
@@ -84,206 +183,385 @@ impl Lexer {
             ("else", TokenType::Else),
             ("func", TokenType::Func),
             ("while", TokenType::While),
+            ("for", TokenType::For),
+            ("in", TokenType::In),
+            ("break", TokenType::Break),
+            ("continue", TokenType::Continue),
             ("return", TokenType::Return),
             ("use", TokenType::Use),
             ("struct", TokenType::Struct),
+            ("impl", TokenType::Impl),
             ("new", TokenType::New),
+            ("true", TokenType::True),
+            ("false", TokenType::False),
         ])
     }
 
-    pub fn lex(&mut self) {
-        loop {
-            let next = match self.peek() {
-                Some(next) => next,
-                _ => break,
-            };
-            if next.is_digit(10) {
-                self.lex_digit();
-                continue;
-            }
-            if next == '"' {
-                self.pos += 1;
-                self.lex_string();
+    pub fn lex(&mut self) -> Result<(), CompileError> {
+        while let Some(next) = self.peek() {
+            if next.is_whitespace() {
+                self.pos += next.len_utf8();
                 continue;
             }
-            if OPERATORS.contains(next) {
-                self.lex_operators();
+            if next == '/' && matches!(self.peek_nth(1), Some('/') | Some('*')) {
+                self.lex_comment()?;
                 continue;
             }
-            if next.is_ascii_alphabetic() {
+
+            let index = self.tokens.len();
+            if next.is_ascii_digit() {
+                self.lex_digit()?;
+            } else if next == '"' {
+                self.pos += 1;
+                self.lex_string()?;
+            } else if OPERATORS.contains(next) {
+                self.lex_operators()?;
+            } else if next.is_ascii_alphabetic() || next == '_' {
                 self.lex_word();
-                continue;
+            } else {
+                return Err(CompileError::lexical(
+                    format!("unexpected character `{}`", next),
+                    Span::new(self.pos, self.pos + next.len_utf8()),
+                ));
             }
-            if next.is_whitespace() {
-                self.pos += 1;
-                continue;
+            if !self.doc.is_empty() {
+                self.tokens[index].doc = Some(self.doc.join("\n"));
+                self.doc.clear();
+            }
+            self.mark_line_break(index);
+        }
+        self.tokens.push(Token::new(TokenType::Eof, Lexeme::None, self.pos, self.pos));
+        self.mark_line_break(self.tokens.len() - 1);
+        Ok(())
+    }
+
+    fn mark_line_break(&mut self, index: usize) {
+        let previous_end = index.checked_sub(1).map_or(0, |previous| self.tokens[previous].end);
+        let gap = self.input.get(previous_end..self.tokens[index].start).unwrap_or("");
+        self.tokens[index].line_break = gap.contains('\n');
+    }
+
+    /// Skips a `//` line comment or a nestable `/* */` block comment. The text of `///` doc
+    /// comments is kept and attached to the next token.
+    fn lex_comment(&mut self) -> Result<(), CompileError> {
+        let start: usize = self.pos;
+
+        if self.peek_nth(1) == Some('/') {
+            let end = self.input[start..].find('\n').map_or(self.input.len(), |i| start + i);
+            let text = &self.input[start..end];
+            // `////` is an ordinary comment, as in Rust.
+            if let Some(doc) = text.strip_prefix("///") && !doc.starts_with('/') {
+                self.doc.push(doc.strip_prefix(' ').unwrap_or(doc).trim_end().to_string());
             }
+            self.pos = end;
+            return Ok(());
         }
-        self.tokens.push(Token {
-            token_type: TokenType::Eof,
-            lexeme: Lexeme::None,
-            start: self.pos,
-            end: self.pos,
-        });
-        println!("{:#?}", self.tokens);
+
+        self.pos += 2;
+        let mut depth = 1;
+        while depth > 0 {
+            match (self.peek(), self.peek_nth(1)) {
+                (Some('/'), Some('*')) => {
+                    depth += 1;
+                    self.pos += 2;
+                }
+                (Some('*'), Some('/')) => {
+                    depth -= 1;
+                    self.pos += 2;
+                }
+                (Some(c), _) => self.pos += c.len_utf8(),
+                (None, _) => return Err(CompileError::lexical(
+                    "unterminated block comment",
+                    Span::new(start, start + 2),
+                ).with_help("add a closing `*/`; block comments nest, so every `/*` needs its own `*/`")),
+            }
+        }
+        Ok(())
     }
 
-    fn lex_digit(&mut self) {
+    /// Lexes an integer (`42`, `1_000`, `0xff`, `0b1010`, `0o17`) or a float (`3.14`, `1e-3`, `2.5E10`).
+    fn lex_digit(&mut self) -> Result<(), CompileError> {
         let start: usize = self.pos;
 
-        while self.peek().unwrap_or_default().is_digit(10) {
+        let radix = match self.input.get(start..start + 2) {
+            Some("0x") | Some("0X") => Some((16, "hex")),
+            Some("0b") | Some("0B") => Some((2, "binary")),
+            Some("0o") | Some("0O") => Some((8, "octal")),
+            _ => None,
+        };
+        let lexeme = match radix {
+            Some((radix, name)) => {
+                self.pos += 2;
+                self.skip_digits(16);
+                let digits = self.input[start + 2..self.pos].replace('_', "");
+                if digits.is_empty() {
+                    return Err(CompileError::lexical(format!("{} literal has no digits", name), Span::new(start, self.pos)));
+                }
+                if let Some(offset) = digits.find(|c: char| !c.is_digit(radix)) {
+                    let digit = &digits[offset..offset + 1];
+                    return Err(CompileError::lexical(format!("invalid digit `{}` in {} literal", digit, name), Span::new(start, self.pos)));
+                }
+                let number = u64::from_str_radix(&digits, radix).map_err(|_| CompileError::lexical(
+                    "number literal is too large",
+                    Span::new(start, self.pos),
+                ))?;
+                Lexeme::Integer(number)
+            }
+            None => self.lex_decimal(start)?,
+        };
+
+        self.tokens.push(Token::new(TokenType::Number, lexeme, start, self.pos));
+        Ok(())
+    }
+
+    fn lex_decimal(&mut self, start: usize) -> Result<Lexeme, CompileError> {
+        self.skip_digits(10);
+        let mut is_float = false;
+
+        // `1.` and `0..10` stay integers: a fraction needs a digit after the dot.
+        if self.peek() == Some('.') && self.peek_nth(1).is_some_and(|c| c.is_ascii_digit()) {
+            is_float = true;
             self.pos += 1;
+            self.skip_digits(10);
+        }
+
+        if matches!(self.peek(), Some('e') | Some('E')) {
+            let sign = matches!(self.peek_nth(1), Some('+') | Some('-'));
+            let exponent_start = if sign { 2 } else { 1 };
+            if self.peek_nth(exponent_start).is_some_and(|c| c.is_ascii_digit()) {
+                is_float = true;
+                self.pos += exponent_start;
+                self.skip_digits(10);
+            }
         }
 
-        let number = self.input[start..self.pos].parse::<i32>().unwrap();
+        let text = self.input[start..self.pos].replace('_', "");
+        let span = Span::new(start, self.pos);
+        if is_float {
+            match text.parse::<f64>() {
+                Ok(number) if number.is_finite() => Ok(Lexeme::Float(number)),
+                _ => Err(CompileError::lexical("float literal is out of range", span)),
+            }
+        } else {
+            text.parse::<u64>().map(Lexeme::Integer).map_err(|_| CompileError::lexical("number literal is too large", span))
+        }
+    }
 
-        self.tokens.push(Token {
-            token_type: TokenType::Number,
-            lexeme: Lexeme::NumberLexeme(number as f32),
-            start,
-            end: self.pos
-        });
+    /// Skips digits of the given radix and `_` separators.
+    fn skip_digits(&mut self, radix: u32) {
+        while self.peek().is_some_and(|c| c.is_digit(radix) || c == '_') {
+            self.pos += 1;
+        }
     }
 
-    fn lex_string(&mut self) {
+    fn lex_string(&mut self) -> Result<(), CompileError> {
         let start: usize = self.pos;
+        let mut string = String::new();
+        let mut parts: Vec<StringPart> = vec![];
 
         loop {
-            let peek = self.peek().unwrap_or_default();
-            if !self.check_string(peek) {
+            let peek = match self.peek() {
+                Some(peek) => peek,
+                None => return Err(CompileError::lexical(
+                    "unterminated string literal",
+                    Span::new(start - 1, self.pos),
+                ).with_help("add a closing `\"` to end the string")),
+            };
+            if peek == '"' {
                 break;
             }
-            self.pos += 1;
+            if peek == '$' && self.peek_nth(1) == Some('{') {
+                if !string.is_empty() {
+                    parts.push(StringPart::Text(std::mem::take(&mut string)));
+                }
+                parts.push(StringPart::Code(self.lex_interpolation()?));
+                continue;
+            }
+            // A trailing backslash is left for the unterminated string error above.
+            if peek == '\\' && self.peek_nth(1).is_some() {
+                string.push(self.lex_escape()?);
+                continue;
+            }
+            string.push(peek);
+            self.pos += peek.len_utf8();
         }
 
-        let string = self.input[start..self.pos].to_string();
-        self.tokens.push(Token {
-            token_type: TokenType::String,
-            lexeme: Lexeme::StringLexeme(string),
-            start: start - 1,
-            end: self.pos
-        });
+        let lexeme = if parts.is_empty() {
+            Lexeme::String(string)
+        } else {
+            if !string.is_empty() {
+                parts.push(StringPart::Text(string));
+            }
+            Lexeme::Interpolated(parts)
+        };
+        self.tokens.push(Token::new(TokenType::String, lexeme, start - 1, self.pos + 1));
 
         self.pos += 1;
+        Ok(())
     }
 
-    fn check_string(&mut self, symbol: char) -> bool {
-        symbol != '"' && (symbol != '\n' || symbol != '\0')
+    /// Lexes the expression of a `${...}` interpolation, starting at the `$`. The tokens keep their
+    /// positions in the whole source, so errors in them point into the string.
+    fn lex_interpolation(&mut self) -> Result<Vec<Token>, CompileError> {
+        let start: usize = self.pos;
+        self.pos += 2;
+        let end = self.interpolation_end().ok_or_else(|| CompileError::lexical(
+            "unterminated interpolation",
+            Span::new(start, start + 2),
+        ).with_help("close it with `}`, or write `\\$` for a literal `$`"))?;
+
+        let mut inner = Lexer::new(self.input[..end].to_string());
+        inner.pos = self.pos;
+        inner.lex()?;
+        self.pos = end + 1;
+        Ok(inner.tokens)
     }
 
-    fn lex_operators(&mut self) {
-        use TokenType::*;
-        let start: usize = self.pos;
+    /// Finds the `}` closing the interpolation that starts at `self.pos`, skipping nested braces and
+    /// string literals.
+    fn interpolation_end(&self) -> Option<usize> {
+        let mut depth = 0;
+        let mut in_string = false;
+        let mut chars = self.input[self.pos..].char_indices();
+        while let Some((offset, c)) = chars.next() {
+            match c {
+                '\\' if in_string => {
+                    chars.next();
+                }
+                '"' => in_string = !in_string,
+                '{' if !in_string => depth += 1,
+                '}' if !in_string && depth == 0 => return Some(self.pos + offset),
+                '}' if !in_string => depth -= 1,
+                '\n' => return None,
+                _ => {}
+            }
+        }
+        None
+    }
 
-        let mut str: std::string::String = std::string::String::new();
+    /// Lexes an escape sequence starting at the backslash and returns the character it stands for.
+    fn lex_escape(&mut self) -> Result<char, CompileError> {
+        let start: usize = self.pos;
+        self.pos += 1;
+        let next = self.peek().unwrap_or_default();
+        self.pos += next.len_utf8();
+
+        let escaped = match next {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            '"' => '"',
+            '\\' => '\\',
+            '$' => '$',
+            'u' => return self.lex_unicode_escape(start),
+            _ => return Err(CompileError::lexical(
+                format!("unknown escape sequence `\\{}`", next),
+                Span::new(start, self.pos),
+            ).with_help("valid escapes are `\\n`, `\\t`, `\\r`, `\\\"`, `\\\\`, `\\$` and `\\u{...}`")),
+        };
+        Ok(escaped)
+    }
 
-        let mut token_type: TokenType = Plus;
-        let mut is_founded = false;
+    /// Lexes the `{...}` part of a `\u{...}` escape: one to six hex digits naming a Unicode scalar value.
+    fn lex_unicode_escape(&mut self, start: usize) -> Result<char, CompileError> {
+        let invalid = |end: usize| CompileError::lexical("invalid unicode escape", Span::new(start, end))
+            .with_help("write it as `\\u{...}` with one to six hex digits, e.g. `\\u{1F600}`");
 
-        while OPERATORS.contains(self.peek().unwrap_or_default()) {
-            let symbol = self.peek().unwrap_or_default();
-            str += &symbol.to_string();
+        if self.peek() != Some('{') {
+            return Err(invalid(self.pos));
+        }
+        self.pos += 1;
+        let digits_start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_hexdigit()) {
             self.pos += 1;
-            is_founded = true;
-            token_type = match str.as_str() {
-                "+" => Plus,
-                "-" => Minus,
-                "*" => Star,
-                "/" => Slash,
-                "!=" => NoEq,
-                "==" => EqEq,
-                ">" => Gt,
-                ">=" => GtEq,
-                "<" => Lt,
-                "<=" => LtEq,
-                "(" => LeftParen,
-                ")" => RightParen,
-                "{" => LeftBrace,
-                "}" => RightBrace,
-                "&&" => AndAnd,
-                "||" => OrOr,
-                "," => Comma,
-                ":" => Colon,
-                _ => {
-                    is_founded = false;
-                    continue
-                },
-            };
-            break;
         }
-
-        if !is_founded {
-            token_type = match str.as_str() {
-                "+" => Plus,
-                "-" => Minus,
-                "*" => Star,
-                "/" => Slash,
-                "=" => Eq,
-                "!=" => NoEq,
-                "==" => EqEq,
-                ">" => Gt,
-                ">=" => GtEq,
-                "<" => Lt,
-                "<=" => LtEq,
-                "(" => LeftParen,
-                ")" => RightParen,
-                "{" => LeftBrace,
-                "}" => RightBrace,
-                "&&" => AndAnd,
-                "||" => OrOr,
-                "," => Comma,
-                ":" => Colon,
-                _ => panic!("Unknown operator: {}", str),
-            };
+        let digits = &self.input[digits_start..self.pos];
+        if self.peek() != Some('}') || digits.is_empty() || digits.len() > 6 {
+            return Err(invalid(self.pos));
         }
+        self.pos += 1;
 
-        self.tokens.push(Token {
-            token_type,
-            lexeme: Lexeme::None,
-            start,
-            end: self.pos
-        });
+        u32::from_str_radix(digits, 16).ok().and_then(char::from_u32).ok_or_else(|| {
+            CompileError::lexical(format!("`{}` is not a valid unicode character", digits), Span::new(start, self.pos))
+        })
     }
 
-    fn lex_word(&mut self) {
+    fn lex_operators(&mut self) -> Result<(), CompileError> {
+        use TokenType::*;
         let start: usize = self.pos;
 
-        let mut str: String = self.input[self.pos..].to_string();
+        let symbol = self.peek().unwrap_or_default();
+        self.pos += 1;
+
+        let double = match (symbol, self.peek().unwrap_or_default()) {
+            ('!', '=') => Some(NoEq),
+            ('=', '=') => Some(EqEq),
+            ('>', '=') => Some(GtEq),
+            ('<', '=') => Some(LtEq),
+            ('&', '&') => Some(AndAnd),
+            ('|', '|') => Some(OrOr),
+            ('.', '.') => Some(DotDot),
+            _ => None,
+        };
+
+        let token_type = match double {
+            Some(token_type) => {
+                self.pos += 1;
+                token_type
+            }
+            None => match symbol {
+                '+' => Plus,
+                '-' => Minus,
+                '*' => Star,
+                '/' => Slash,
+                '%' => Percent,
+                '=' => Eq,
+                '!' => Not,
+                '>' => Gt,
+                '<' => Lt,
+                '(' => LeftParen,
+                ')' => RightParen,
+                '{' => LeftBrace,
+                '}' => RightBrace,
+                '[' => LeftBracket,
+                ']' => RightBracket,
+                ',' => Comma,
+                ':' => Colon,
+                ';' => Semicolon,
+                '.' => Dot,
+                _ => return Err(CompileError::lexical(
+                    format!("unknown operator `{}`", symbol),
+                    Span::new(start, self.pos),
+                )),
+            },
+        };
+
+        self.tokens.push(Token::new(token_type, Lexeme::None, start, self.pos));
+        Ok(())
+    }
+
+    fn lex_word(&mut self) {
+        let start: usize = self.pos;
 
         while self.peek().unwrap_or_default().is_ascii_alphanumeric() || self.peek().unwrap_or_default() == '_' {
-            let symbol = self.peek().unwrap();
-            str += &symbol.to_string();
             self.pos += 1;
         }
 
         let word = &self.input[start..self.pos];
 
-        if self.keywords.contains_key(&word) {
-            self.tokens.push(Token {
-                token_type: *self.keywords.get(&word).unwrap(),
-                lexeme: Lexeme::None,
-                start,
-                end: self.pos
-            });
+        if let Some(token_type) = self.keywords.get(&word) {
+            self.tokens.push(Token::new(*token_type, Lexeme::None, start, self.pos));
             return;
         }
 
-        self.tokens.push(Token {
-            token_type: TokenType::Word,
-            lexeme: Lexeme::WordLexeme(word.to_string()),
-            start,
-            end: self.pos
-        })
+        self.tokens.push(Token::new(TokenType::Word, Lexeme::Word(word.to_string()), start, self.pos))
     }
 
-    fn peek(&mut self) -> Option<char> {
-        if self.pos < self.input.len() - 1 {
-            let symbol = match self.input.chars().nth(self.pos) {
-                Some(c) => c,
-                None => return None,
-            };
-            return Some(symbol)
-        }
-        None
+    fn peek(&self) -> Option<char> {
+        self.input.get(self.pos..)?.chars().next()
+    }
+
+    fn peek_nth(&self, n: usize) -> Option<char> {
+        self.input.get(self.pos..)?.chars().nth(n)
     }
 }
\ No newline at end of file
@@ -0,0 +1,862 @@
+//! Generates textual LLVM IR for a checked program, to be built with `llc` or `clang` and linked
+//! against the runtime in `lib`.
+//!
+//! Every local lives in an `alloca` of the entry block, which `opt -passes=mem2reg` turns into
+//! registers. Unlike the C backend, the program runs in a single area opened by `main`: nothing
+//! is freed before the program ends, so values never need copying into an outer area.
+
+use std::collections::{HashMap, HashSet};
+
+use super::ast::{Expr, ExprKind, InterpolationPart, Iterable, Stmt, StmtKind, TypedArgument};
+use super::builtins::{Builtin, FormatArgs, Placeholder, Signature};
+use super::error::{CompileError, Span};
+use super::source_map::SourceMap;
+use super::symbols::{SymbolId, SymbolTable};
+use super::value::{IntType, Value, ValueType};
+
+pub struct LlvmGenerator {
+    input: Stmt,
+    symbols: SymbolTable,
+    /// Name and line map of the Lamp source, for the locations reported by runtime checks.
+    file_name: String,
+    source_map: SourceMap,
+    /// The functions and methods the program defines; any other call goes to the runtime.
+    functions: HashSet<SymbolId>,
+    /// The string constants, named `@.str.N` after their index.
+    strings: Vec<String>,
+    /// `declare` lines of the runtime functions the program calls, by function name.
+    declarations: Vec<(String, String)>,
+    /// The function being generated.
+    allocas: String,
+    code: String,
+    /// The block that instructions are appended to, needed for `phi` operands.
+    block: String,
+    /// Counter for the temporaries and block labels of the current function.
+    next_id: usize,
+    /// The `alloca` of each local variable, or `%self` for the receiver of a method.
+    locals: HashMap<SymbolId, String>,
+    /// The loops around the current statement, innermost last.
+    loops: Vec<LoopBlocks>,
+    /// The label of the loop about to be generated.
+    label: Option<String>,
+}
+
+/// Where `break` and `continue` jump to in a loop being generated.
+struct LoopBlocks {
+    label: Option<String>,
+    break_to: String,
+    continue_to: String,
+}
+
+impl LlvmGenerator {
+    pub fn new(input: Stmt, symbols: SymbolTable) -> Self {
+        Self {
+            input,
+            symbols,
+            file_name: "<input>".to_string(),
+            source_map: SourceMap::new(""),
+            functions: HashSet::new(),
+            strings: vec![],
+            declarations: vec![],
+            allocas: String::new(),
+            code: String::new(),
+            block: String::new(),
+            next_id: 0,
+            locals: HashMap::new(),
+            loops: vec![],
+            label: None,
+        }
+    }
+
+    pub fn with_source(mut self, file_name: &str, source: &str) -> Self {
+        self.file_name = file_name.to_string();
+        self.source_map = SourceMap::new(source);
+        self
+    }
+
+    pub fn generate(&mut self) -> Result<String, CompileError> {
+        let StmtKind::Block(stmts) = self.input.kind.clone() else {
+            return Err(CompileError::codegen("the program is not a block", self.input.span));
+        };
+        for stmt in &stmts {
+            match &stmt.kind {
+                StmtKind::FunctionDef(..) => self.functions.extend(stmt.symbol),
+                StmtKind::Impl(_, methods) => self.functions.extend(methods.iter().filter_map(|method| method.symbol)),
+                _ => {}
+            }
+        }
+
+        let mut functions: Vec<String> = vec![];
+        for stmt in &stmts {
+            match &stmt.kind {
+                StmtKind::FunctionDef(name, args, body, return_type) => {
+                    functions.push(self.function(&Self::function_name(name), args, body, return_type, false)?);
+                }
+                StmtKind::Impl(owner, methods) => {
+                    for method in methods {
+                        if let StmtKind::FunctionDef(name, args, body, return_type) = &method.kind {
+                            functions.push(self.function(&Self::method_name(owner, name), args, body, return_type, true)?);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        functions.push(self.main(&stmts)?);
+
+        let mut module = format!("; ModuleID = '{}'\nsource_filename = \"{}\"\n\n", self.file_name, Self::escape(&self.file_name));
+        let mut structs = String::new();
+        for stmt in &stmts {
+            if let StmtKind::Struct(name, fields) = &stmt.kind {
+                let fields: Vec<String> = fields.iter().map(|field| self.llvm_type(&field.typ)).collect();
+                structs += &format!("%{} = type {{ {} }}\n", name, fields.join(", "));
+            }
+        }
+        if !structs.is_empty() {
+            module += &structs;
+            module.push('\n');
+        }
+        if !self.strings.is_empty() {
+            for (i, s) in self.strings.iter().enumerate() {
+                module += &format!("@.str.{} = private unnamed_addr constant [{} x i8] c\"{}\\00\"\n", i, s.len() + 1, Self::escape(s));
+            }
+            module.push('\n');
+        }
+        if !self.declarations.is_empty() {
+            for (_, declaration) in &self.declarations {
+                module += declaration;
+                module.push('\n');
+            }
+            module.push('\n');
+        }
+        module += &functions.join("\n");
+        Ok(module)
+    }
+
+    /// Generates a function or method. Methods take a pointer to their receiver, like in C, so
+    /// assignments to fields of `self` change the caller's value.
+    fn function(&mut self, name: &str, args: &[TypedArgument], body: &Stmt, return_type: &ValueType, is_method: bool) -> Result<String, CompileError> {
+        self.start_function();
+        let mut params: Vec<String> = vec![];
+        for (i, arg) in args.iter().enumerate() {
+            let Some(id) = arg.symbol else {
+                return Err(CompileError::codegen(format!("parameter `{}` without a symbol", arg.name), body.span));
+            };
+            if is_method && i == 0 {
+                params.push("ptr %self".to_string());
+                self.locals.insert(id, "%self".to_string());
+            } else {
+                let typ = self.llvm_type(&arg.typ);
+                // Parameters are named `%arg.NAME`, apart from the `%v.` variables and the numbered temporaries.
+                params.push(format!("{} %arg.{}", typ, arg.name));
+                let address = self.variable(&arg.name, id, &arg.typ);
+                self.emit(format!("store {} %arg.{}, ptr {}", typ, arg.name, address));
+            }
+        }
+        self.statement(body)?;
+        // A function that returns a value ends with a `return`, so its last block is unreachable.
+        self.emit(if *return_type == ValueType::Unit { "ret void" } else { "unreachable" });
+        Ok(self.finish_function(&format!("define {} @{}({})", self.llvm_type(return_type), name, params.join(", "))))
+    }
+
+    fn main(&mut self, stmts: &[Stmt]) -> Result<String, CompileError> {
+        self.start_function();
+        self.declare("area_start", "declare void @area_start()");
+        self.declare("area_end", "declare void @area_end()");
+        self.emit("call void @area_start()");
+        for stmt in stmts {
+            self.statement(stmt)?;
+        }
+        self.emit("call void @area_end()");
+        self.emit("ret i32 0");
+        Ok(self.finish_function("define i32 @main()"))
+    }
+
+    fn start_function(&mut self) {
+        self.allocas.clear();
+        self.code.clear();
+        self.block = "entry".to_string();
+        self.next_id = 0;
+        self.locals.clear();
+    }
+
+    fn finish_function(&mut self, header: &str) -> String {
+        format!("{} {{\nentry:\n{}{}}}\n", header, self.allocas, self.code)
+    }
+
+    fn statement(&mut self, stmt: &Stmt) -> Result<(), CompileError> {
+        match &stmt.kind {
+            StmtKind::VarDef(name, value, typ) => {
+                let value = self.expression(value)?;
+                let Some(id) = stmt.symbol else {
+                    return Err(CompileError::codegen(format!("variable `{}` without a symbol", name), stmt.span));
+                };
+                let address = self.variable(name, id, typ);
+                self.emit(format!("store {} {}, ptr {}", self.llvm_type(typ), value, address));
+            }
+            StmtKind::Assign(target, value) => {
+                let typ = self.type_of(value)?;
+                let value = self.expression(value)?;
+                let address = self.address(target)?;
+                self.emit(format!("store {} {}, ptr {}", self.llvm_type(&typ), value, address));
+            }
+            StmtKind::If(condition, body, else_body) => {
+                let condition = self.expression(condition)?;
+                let id = self.next();
+                let (then, otherwise, end) = (format!("if.then{}", id), format!("if.else{}", id), format!("if.end{}", id));
+                let false_target = if else_body.is_some() { &otherwise } else { &end };
+                self.emit(format!("br i1 {}, label %{}, label %{}", condition, then, false_target));
+                self.start_block(&then);
+                self.statement(body)?;
+                self.emit(format!("br label %{}", end));
+                if let Some(else_body) = else_body.as_ref() {
+                    self.start_block(&otherwise);
+                    self.statement(else_body)?;
+                    self.emit(format!("br label %{}", end));
+                }
+                self.start_block(&end);
+            }
+            StmtKind::Block(stmts) => {
+                for stmt in stmts {
+                    self.statement(stmt)?;
+                }
+            }
+            StmtKind::While(condition, body) => {
+                let id = self.next();
+                let (check, body_block, end) = (format!("while.cond{}", id), format!("while.body{}", id), format!("while.end{}", id));
+                self.emit(format!("br label %{}", check));
+                self.start_block(&check);
+                let condition = self.expression(condition)?;
+                self.emit(format!("br i1 {}, label %{}, label %{}", condition, body_block, end));
+                self.start_block(&body_block);
+                self.loop_body(body, &check, &end)?;
+                self.emit(format!("br label %{}", check));
+                self.start_block(&end);
+            }
+            StmtKind::For(name, iterable, body) => self.for_loop(name, stmt.symbol, iterable, body, stmt.span)?,
+            StmtKind::Labeled(label, body) => {
+                self.label = Some(label.clone());
+                self.statement(body)?;
+            }
+            StmtKind::Break(label) => self.jump(label, true, stmt.span)?,
+            StmtKind::Continue(label) => self.jump(label, false, stmt.span)?,
+            StmtKind::Function(expr) => {
+                self.expression(expr)?;
+            }
+            StmtKind::Return(None) => self.terminate("ret void".to_string()),
+            StmtKind::Return(Some(value)) => {
+                let typ = self.type_of(value)?;
+                let value = self.expression(value)?;
+                if typ == ValueType::Unit {
+                    self.terminate("ret void".to_string());
+                } else {
+                    self.terminate(format!("ret {} {}", self.llvm_type(&typ), value));
+                }
+            }
+            StmtKind::FunctionDef(..) | StmtKind::Use(_) | StmtKind::Struct(..) | StmtKind::Impl(..) => {}
+        }
+        Ok(())
+    }
+
+    fn loop_body(&mut self, body: &Stmt, continue_to: &str, break_to: &str) -> Result<(), CompileError> {
+        let label = self.label.take();
+        self.loops.push(LoopBlocks { label, break_to: break_to.to_string(), continue_to: continue_to.to_string() });
+        let result = self.statement(body);
+        self.loops.pop();
+        result
+    }
+
+    fn jump(&mut self, label: &Option<String>, is_break: bool, span: Span) -> Result<(), CompileError> {
+        let target = match label {
+            Some(label) => self.loops.iter().rev().find(|blocks| blocks.label.as_ref() == Some(label)),
+            None => self.loops.last(),
+        };
+        let Some(target) = target else {
+            let keyword = if is_break { "break" } else { "continue" };
+            return Err(CompileError::codegen(format!("`{}` outside of a loop", keyword), span));
+        };
+        let block = if is_break { target.break_to.clone() } else { target.continue_to.clone() };
+        self.terminate(format!("br label %{}", block));
+        Ok(())
+    }
+
+    /// Lowers a `for` loop. The end of a range, the array and its length are evaluated once, so
+    /// pushing to the array in the body does not extend the loop. `continue` jumps to the block
+    /// that advances the loop.
+    fn for_loop(&mut self, name: &str, symbol: Option<SymbolId>, iterable: &Iterable, body: &Stmt, span: Span) -> Result<(), CompileError> {
+        let Some(id) = symbol else {
+            return Err(CompileError::codegen(format!("loop variable `{}` without a symbol", name), span));
+        };
+        let n = self.next();
+        let (check, body_block, step, end) = (format!("for.cond{}", n), format!("for.body{}", n), format!("for.step{}", n), format!("for.end{}", n));
+        match iterable {
+            Iterable::Range(start, stop) => {
+                let Some(ValueType::Int(int)) = start.typ.clone() else {
+                    return Err(CompileError::codegen("range without an integer type", span));
+                };
+                let typ = ValueType::Int(int);
+                let llvm_type = self.llvm_type(&typ);
+                let start = self.expression(start)?;
+                let stop = self.expression(stop)?;
+                let address = self.variable(name, id, &typ);
+                self.emit(format!("store {} {}, ptr {}", llvm_type, start, address));
+                self.emit(format!("br label %{}", check));
+                self.start_block(&check);
+                let current = self.instruction(format!("load {}, ptr {}", llvm_type, address));
+                let less = if int.is_signed() { "slt" } else { "ult" };
+                let condition = self.instruction(format!("icmp {} {} {}, {}", less, llvm_type, current, stop));
+                self.emit(format!("br i1 {}, label %{}, label %{}", condition, body_block, end));
+                self.start_block(&body_block);
+                self.loop_body(body, &step, &end)?;
+                self.emit(format!("br label %{}", step));
+                self.start_block(&step);
+                let current = self.instruction(format!("load {}, ptr {}", llvm_type, address));
+                let next = self.instruction(format!("add {} {}, 1", llvm_type, current));
+                self.emit(format!("store {} {}, ptr {}", llvm_type, next, address));
+            }
+            Iterable::Array(array) => {
+                let Some(ValueType::Array(element, _)) = array.typ.clone() else {
+                    return Err(CompileError::codegen("iterating over a value that is not an array", span));
+                };
+                let element_type = self.llvm_type(&element);
+                let items = self.expression(array)?;
+                self.declare("array_len", "declare i64 @array_len(ptr)");
+                let len = self.instruction(format!("call i64 @array_len(ptr {})", items));
+                let index = self.temporary("i64");
+                self.emit(format!("store i64 0, ptr {}", index));
+                let address = self.variable(name, id, &element);
+                self.emit(format!("br label %{}", check));
+                self.start_block(&check);
+                let current = self.instruction(format!("load i64, ptr {}", index));
+                let condition = self.instruction(format!("icmp slt i64 {}, {}", current, len));
+                self.emit(format!("br i1 {}, label %{}, label %{}", condition, body_block, end));
+                self.start_block(&body_block);
+                // The elements start at the `data` pointer, the first field of a `LampArray`.
+                let data = self.instruction(format!("load ptr, ptr {}", items));
+                let pointer = self.instruction(format!("getelementptr {}, ptr {}, i64 {}", element_type, data, current));
+                let value = self.instruction(format!("load {}, ptr {}", element_type, pointer));
+                self.emit(format!("store {} {}, ptr {}", element_type, value, address));
+                self.loop_body(body, &step, &end)?;
+                self.emit(format!("br label %{}", step));
+                self.start_block(&step);
+                let current = self.instruction(format!("load i64, ptr {}", index));
+                let next = self.instruction(format!("add i64 {}, 1", current));
+                self.emit(format!("store i64 {}, ptr {}", next, index));
+            }
+        }
+        self.emit(format!("br label %{}", check));
+        self.start_block(&end);
+        Ok(())
+    }
+
+    /// Generates the instructions of an expression and returns the operand holding its value, or
+    /// an empty string for `unit`.
+    fn expression(&mut self, expr: &Expr) -> Result<String, CompileError> {
+        if expr.is_place() {
+            let typ = self.type_of(expr)?;
+            let address = self.address(expr)?;
+            return Ok(self.instruction(format!("load {}, ptr {}", self.llvm_type(&typ), address)));
+        }
+        let operand = match &expr.kind {
+            ExprKind::Value(value) => self.constant(value, expr.typ.as_ref()),
+            ExprKind::Binary(op, left, right) => {
+                let typ = self.type_of(left)?;
+                let left = self.expression(left)?;
+                let right = self.expression(right)?;
+                if let ValueType::Int(int) = typ && (*op == '/' || *op == '%') {
+                    return Ok(self.division(int, *op == '/', &left, &right, expr.span));
+                }
+                let instruction = match (&typ, op) {
+                    (ValueType::Number, '+') => "fadd",
+                    (ValueType::Number, '-') => "fsub",
+                    (ValueType::Number, '*') => "fmul",
+                    (ValueType::Number, '/') => "fdiv",
+                    (ValueType::Number, _) => "frem",
+                    (_, '+') => "add",
+                    (_, '-') => "sub",
+                    _ => "mul",
+                };
+                self.instruction(format!("{} {} {}, {}", instruction, self.llvm_type(&typ), left, right))
+            }
+            ExprKind::Condition(op, left, right) if op == "&&" || op == "||" => self.short_circuit(op == "&&", left, right)?,
+            ExprKind::Condition(op, left, right) => {
+                let typ = self.type_of(left)?;
+                let left = self.expression(left)?;
+                let right = self.expression(right)?;
+                let predicate = match (&typ, op.as_str()) {
+                    (ValueType::Number, "==") => "fcmp oeq",
+                    (ValueType::Number, "!=") => "fcmp une",
+                    (ValueType::Number, "<") => "fcmp olt",
+                    (ValueType::Number, ">") => "fcmp ogt",
+                    (ValueType::Number, "<=") => "fcmp ole",
+                    (ValueType::Number, _) => "fcmp oge",
+                    (_, "==") => "icmp eq",
+                    (_, "!=") => "icmp ne",
+                    (ValueType::Int(int), "<") => if int.is_signed() { "icmp slt" } else { "icmp ult" },
+                    (ValueType::Int(int), ">") => if int.is_signed() { "icmp sgt" } else { "icmp ugt" },
+                    (ValueType::Int(int), "<=") => if int.is_signed() { "icmp sle" } else { "icmp ule" },
+                    (ValueType::Int(int), ">=") => if int.is_signed() { "icmp sge" } else { "icmp uge" },
+                    _ => return Err(CompileError::codegen(format!("`{}` on values that cannot be ordered", op), expr.span)),
+                };
+                self.instruction(format!("{} {} {}, {}", predicate, self.llvm_type(&typ), left, right))
+            }
+            ExprKind::Unary(op, operand) => {
+                let typ = self.type_of(operand)?;
+                let value = self.expression(operand)?;
+                match (op, &typ) {
+                    ('!', _) => self.instruction(format!("xor i1 {}, true", value)),
+                    (_, ValueType::Number) => self.instruction(format!("fneg double {}", value)),
+                    _ => self.instruction(format!("sub {} 0, {}", self.llvm_type(&typ), value)),
+                }
+            }
+            ExprKind::Convert(to, operand) => {
+                let from = self.type_of(operand)?;
+                let value = self.expression(operand)?;
+                self.convert(&value, &from, to)
+            }
+            ExprKind::Functional(name, args) => {
+                if expr.symbol.is_some_and(|id| self.functions.contains(&id)) {
+                    let mut operands: Vec<String> = vec![];
+                    for arg in args {
+                        let typ = self.type_of(arg)?;
+                        operands.push(format!("{} {}", self.llvm_type(&typ), self.expression(arg)?));
+                    }
+                    let return_type = self.type_of(expr)?;
+                    self.call(&return_type, &format!("@{}({})", Self::function_name(name), operands.join(", ")))
+                } else {
+                    let Some(signature) = self.symbols.signature(expr.symbol).cloned() else {
+                        return Err(CompileError::codegen(format!("call of `{}` without a signature", name), expr.span));
+                    };
+                    self.runtime_call(name, &signature, args)?
+                }
+            }
+            ExprKind::Builtin(builtin, args) => {
+                let element = match args.first().and_then(|array| array.typ.as_ref()) {
+                    Some(ValueType::Array(element, _)) => (**element).clone(),
+                    _ => return Err(CompileError::codegen(format!("`{}` called on a value that is not an array", builtin.name()), expr.span)),
+                };
+                let array = self.expression(&args[0])?;
+                match builtin {
+                    Builtin::Len => {
+                        self.declare("array_len", "declare i64 @array_len(ptr)");
+                        let len = self.instruction(format!("call i64 @array_len(ptr {})", array));
+                        self.instruction(format!("trunc i64 {} to i32", len))
+                    }
+                    Builtin::Push => {
+                        let Some(item) = args.get(1) else {
+                            return Err(CompileError::codegen("`push` without an element", expr.span));
+                        };
+                        let element = self.llvm_type(&element);
+                        let value = self.expression(item)?;
+                        // The runtime copies the element from a pointer to it.
+                        let slot = self.temporary(&element);
+                        self.emit(format!("store {} {}, ptr {}", element, value, slot));
+                        self.declare("array_push", "declare void @array_push(ptr, ptr)");
+                        self.emit(format!("call void @array_push(ptr {}, ptr {})", array, slot));
+                        String::new()
+                    }
+                }
+            }
+            ExprKind::Array(elements) => {
+                let Some(ValueType::Array(element, _)) = &expr.typ else {
+                    return Err(CompileError::codegen("array literal without a type", expr.span));
+                };
+                let element = self.llvm_type(element);
+                let size = format!("ptrtoint (ptr getelementptr ({}, ptr null, i32 1) to i64)", element);
+                self.declare("array_new", "declare ptr @array_new(i64, i64, ptr)");
+                if elements.is_empty() {
+                    self.instruction(format!("call ptr @array_new(i64 {}, i64 0, ptr null)", size))
+                } else {
+                    let items_type = format!("[{} x {}]", elements.len(), element);
+                    let items = self.temporary(&items_type);
+                    for (i, value) in elements.iter().enumerate() {
+                        let value = self.expression(value)?;
+                        let pointer = self.instruction(format!("getelementptr {}, ptr {}, i64 0, i64 {}", items_type, items, i));
+                        self.emit(format!("store {} {}, ptr {}", element, value, pointer));
+                    }
+                    self.instruction(format!("call ptr @array_new(i64 {}, i64 {}, ptr {})", size, elements.len(), items))
+                }
+            }
+            ExprKind::Interpolation(parts) => self.interpolation(parts)?,
+            ExprKind::New(name, inits) => {
+                let Some(fields) = self.symbols.struct_fields(name).map(|fields| fields.to_vec()) else {
+                    return Err(CompileError::codegen(format!("unknown struct `{}`", name), expr.span));
+                };
+                let mut value = if fields.is_empty() { "zeroinitializer".to_string() } else { "undef".to_string() };
+                for init in inits {
+                    let Some(index) = fields.iter().position(|field| field.name == init.name) else {
+                        return Err(CompileError::codegen(format!("struct `{}` has no field `{}`", name, init.name), init.value.span));
+                    };
+                    let typ = self.llvm_type(&fields[index].typ);
+                    let field = self.expression(&init.value)?;
+                    value = self.instruction(format!("insertvalue %{} {}, {} {}, {}", name, value, typ, field, index));
+                }
+                value
+            }
+            ExprKind::Field(object, field) => {
+                let (owner, index) = self.field_index(object, field)?;
+                let value = self.expression(object)?;
+                self.instruction(format!("extractvalue %{} {}, {}", owner, value, index))
+            }
+            ExprKind::MethodCall(receiver, name, args) => {
+                let owner = match &receiver.typ {
+                    Some(ValueType::Struct(owner)) => owner.clone(),
+                    _ => return Err(CompileError::codegen(format!("method `{}` called on a value that is not a struct", name), expr.span)),
+                };
+                // A temporary receiver has no address, so it is stored into a slot first.
+                let receiver = if receiver.is_place() {
+                    self.address(receiver)?
+                } else {
+                    let value = self.expression(receiver)?;
+                    let slot = self.temporary(&format!("%{}", owner));
+                    self.emit(format!("store %{} {}, ptr {}", owner, value, slot));
+                    slot
+                };
+                let mut operands = vec![format!("ptr {}", receiver)];
+                for arg in args {
+                    let typ = self.type_of(arg)?;
+                    operands.push(format!("{} {}", self.llvm_type(&typ), self.expression(arg)?));
+                }
+                let return_type = self.type_of(expr)?;
+                self.call(&return_type, &format!("@{}({})", Self::method_name(&owner, name), operands.join(", ")))
+            }
+            ExprKind::VarUse(_) | ExprKind::Index(..) => unreachable!("places are loaded above"),
+        };
+        Ok(operand)
+    }
+
+    /// Lowers `&&` and `||` to a branch around the right operand and a `phi` of the result.
+    fn short_circuit(&mut self, is_and: bool, left: &Expr, right: &Expr) -> Result<String, CompileError> {
+        let left = self.expression(left)?;
+        let left_block = self.block.clone();
+        let id = self.next();
+        let (right_block, end) = (format!("logic.rhs{}", id), format!("logic.end{}", id));
+        if is_and {
+            self.emit(format!("br i1 {}, label %{}, label %{}", left, right_block, end));
+        } else {
+            self.emit(format!("br i1 {}, label %{}, label %{}", left, end, right_block));
+        }
+        self.start_block(&right_block);
+        let right = self.expression(right)?;
+        let right_end = self.block.clone();
+        self.emit(format!("br label %{}", end));
+        self.start_block(&end);
+        let skipped = if is_and { "false" } else { "true" };
+        Ok(self.instruction(format!("phi i1 [ {}, %{} ], [ {}, %{} ]", skipped, left_block, right, right_end)))
+    }
+
+    /// Divides integers, stopping the program with its location on a zero divisor like the index
+    /// checks do. Dividing the smallest signed value by -1 overflows, which LLVM leaves undefined,
+    /// so a divisor of -1 negates instead and wraps around like the interpreter.
+    fn division(&mut self, int: IntType, is_div: bool, left: &str, right: &str, span: Span) -> String {
+        let typ = Self::int_type(int);
+        let is_zero = self.instruction(format!("icmp eq {} {}, 0", typ, right));
+        let id = self.next();
+        let (fail, ok) = (format!("div.zero{}", id), format!("div.ok{}", id));
+        self.emit(format!("br i1 {}, label %{}, label %{}", is_zero, fail, ok));
+        self.start_block(&fail);
+        let location = self.location(span.start);
+        let location = self.string(&location);
+        let message = self.string("division by zero");
+        self.declare("runtime_error", "declare void @runtime_error(ptr, ptr)");
+        self.emit(format!("call void @runtime_error(ptr {}, ptr {})", location, message));
+        self.emit("unreachable");
+        self.start_block(&ok);
+        if !int.is_signed() {
+            return self.instruction(format!("{} {} {}, {}", if is_div { "udiv" } else { "urem" }, typ, left, right));
+        }
+        let is_minus_one = self.instruction(format!("icmp eq {} {}, -1", typ, right));
+        let divisor = self.instruction(format!("select i1 {}, {} 1, {} {}", is_minus_one, typ, typ, right));
+        let result = self.instruction(format!("{} {} {}, {}", if is_div { "sdiv" } else { "srem" }, typ, left, divisor));
+        let overflowed = if is_div { self.instruction(format!("sub {} 0, {}", typ, left)) } else { "0".to_string() };
+        self.instruction(format!("select i1 {}, {} {}, {} {}", is_minus_one, typ, overflowed, typ, result))
+    }
+
+    /// Calls an `io` or `math` function of the runtime. Like in C, integers passed to a format
+    /// string are widened to `long long` and `input` gets pointers to its targets.
+    fn runtime_call(&mut self, name: &str, signature: &Signature, args: &[Expr]) -> Result<String, CompileError> {
+        let mut operands: Vec<String> = vec![];
+        // A message that is not a literal could contain placeholders, so it is passed through `{s}`.
+        if signature.format == Some(FormatArgs::Values) && args.len() == 1 && !matches!(args[0].kind, ExprKind::Value(Value::String(_))) {
+            operands.push(format!("ptr {}", self.string("{s}")));
+        }
+        for (i, arg) in args.iter().enumerate() {
+            let typ = self.type_of(arg)?;
+            let operand = match (signature.format, &typ) {
+                (Some(FormatArgs::Targets), _) if i > 0 => format!("ptr {}", self.address(arg)?),
+                (Some(FormatArgs::Values), ValueType::Int(int)) => {
+                    let value = self.expression(arg)?;
+                    format!("i64 {}", self.widen(&value, *int, IntType::I64))
+                }
+                _ => format!("{} {}", self.llvm_type(&typ), self.expression(arg)?),
+            };
+            operands.push(operand);
+        }
+        let return_type = self.llvm_type(&signature.return_type);
+        let function_type = if signature.variadic {
+            self.declare(name, &format!("declare {} @{}(ptr, ...)", return_type, name));
+            format!("{} (ptr, ...)", return_type)
+        } else {
+            let params: Vec<String> = signature.params.iter().map(|typ| self.llvm_type(typ)).collect();
+            self.declare(name, &format!("declare {} @{}({})", return_type, name, params.join(", ")));
+            return_type
+        };
+        let call = format!("call {} @{}({})", function_type, name, operands.join(", "));
+        if signature.return_type == ValueType::Unit {
+            self.emit(call);
+            Ok(String::new())
+        } else {
+            Ok(self.instruction(call))
+        }
+    }
+
+    /// Lowers an interpolated string to a `format` call with a placeholder per expression, chosen
+    /// from its checked type, the same way as the C backend.
+    fn interpolation(&mut self, parts: &[InterpolationPart]) -> Result<String, CompileError> {
+        let mut fmt = String::new();
+        let mut args: Vec<String> = vec![];
+        for part in parts {
+            match part {
+                InterpolationPart::Text(text) if text.contains('{') => {
+                    fmt.push_str("{s}");
+                    args.push(format!("ptr {}", self.string(text)));
+                }
+                InterpolationPart::Text(text) => fmt.push_str(text),
+                InterpolationPart::Expr(value) => {
+                    let typ = self.type_of(value)?;
+                    let operand = self.expression(value)?;
+                    let (placeholder, arg) = match (Placeholder::for_type(&typ), &typ) {
+                        (Some(Placeholder::Integer), ValueType::Int(int)) => (Placeholder::Integer, format!("i64 {}", self.widen(&operand, *int, IntType::I64))),
+                        (Some(placeholder), _) => (placeholder, format!("{} {}", self.llvm_type(&typ), operand)),
+                        (None, _) => {
+                            let (yes, no) = (self.string("true"), self.string("false"));
+                            (Placeholder::String, format!("ptr {}", self.instruction(format!("select i1 {}, ptr {}, ptr {}", operand, yes, no))))
+                        }
+                    };
+                    fmt.push_str(&placeholder.to_string());
+                    args.push(arg);
+                }
+            }
+        }
+        args.insert(0, format!("ptr {}", self.string(&fmt)));
+        self.declare("format", "declare ptr @format(ptr, ...)");
+        Ok(self.instruction(format!("call ptr (ptr, ...) @format({})", args.join(", "))))
+    }
+
+    /// Returns a pointer to the storage of a variable, a field or an array element.
+    fn address(&mut self, place: &Expr) -> Result<String, CompileError> {
+        match &place.kind {
+            ExprKind::VarUse(name) => place.symbol.and_then(|id| self.locals.get(&id).cloned())
+                .ok_or_else(|| CompileError::codegen(format!("variable `{}` without storage", name), place.span)),
+            ExprKind::Field(object, field) if object.is_place() => {
+                let (owner, index) = self.field_index(object, field)?;
+                let object = self.address(object)?;
+                Ok(self.instruction(format!("getelementptr %{}, ptr {}, i32 0, i32 {}", owner, object, index)))
+            }
+            ExprKind::Index(array, index) => {
+                let Some(ValueType::Int(int)) = index.typ else {
+                    return Err(CompileError::codegen("index without an integer type", index.span));
+                };
+                let array = self.expression(array)?;
+                let index = self.expression(index)?;
+                let index = self.widen(&index, int, IntType::I64);
+                let location = self.location(place.span.start);
+                let location = self.string(&location);
+                self.declare("array_at", "declare ptr @array_at(ptr, i64, ptr)");
+                Ok(self.instruction(format!("call ptr @array_at(ptr {}, i64 {}, ptr {})", array, index, location)))
+            }
+            _ => Err(CompileError::codegen("assigning to a value that is not a place", place.span)),
+        }
+    }
+
+    fn field_index(&self, object: &Expr, field: &str) -> Result<(String, usize), CompileError> {
+        let owner = match &object.typ {
+            Some(ValueType::Struct(owner)) => owner.clone(),
+            _ => return Err(CompileError::codegen(format!("field `{}` of a value that is not a struct", field), object.span)),
+        };
+        let index = self.symbols.struct_fields(&owner).and_then(|fields| fields.iter().position(|f| f.name == field));
+        match index {
+            Some(index) => Ok((owner, index)),
+            None => Err(CompileError::codegen(format!("struct `{}` has no field `{}`", owner, field), object.span)),
+        }
+    }
+
+    fn convert(&mut self, value: &str, from: &ValueType, to: &ValueType) -> String {
+        match (from, to) {
+            (ValueType::Int(from), ValueType::Int(to)) => self.widen(value, *from, *to),
+            (ValueType::Int(from), ValueType::Number) => {
+                let instruction = if from.is_signed() { "sitofp" } else { "uitofp" };
+                self.instruction(format!("{} {} {} to double", instruction, Self::int_type(*from), value))
+            }
+            (ValueType::Number, ValueType::Int(to)) => {
+                let instruction = if to.is_signed() { "fptosi" } else { "fptoui" };
+                self.instruction(format!("{} double {} to {}", instruction, value, Self::int_type(*to)))
+            }
+            _ => value.to_string(),
+        }
+    }
+
+    /// Converts an integer to another integer type, extending it by the signedness of its type.
+    fn widen(&mut self, value: &str, from: IntType, to: IntType) -> String {
+        let (from_bits, to_bits) = (Self::int_bits(from), Self::int_bits(to));
+        let instruction = match from_bits.cmp(&to_bits) {
+            std::cmp::Ordering::Equal => return value.to_string(),
+            std::cmp::Ordering::Greater => "trunc",
+            std::cmp::Ordering::Less if from.is_signed() => "sext",
+            std::cmp::Ordering::Less => "zext",
+        };
+        self.instruction(format!("{} {} {} to {}", instruction, Self::int_type(from), value, Self::int_type(to)))
+    }
+
+    fn constant(&mut self, value: &Value, typ: Option<&ValueType>) -> String {
+        match (value, typ) {
+            (Value::Number(n), _) => Self::double(*n),
+            (Value::Integer(n), Some(ValueType::Number)) => Self::double(*n as f64),
+            (Value::Integer(n), Some(ValueType::Int(int))) => {
+                // LLVM integers have no signedness, so the bits are written as a signed number.
+                let shift = 128 - Self::int_bits(*int);
+                (((*n as i128) << shift) >> shift).to_string()
+            }
+            (Value::Integer(n), _) => n.to_string(),
+            (Value::Bool(b), _) => b.to_string(),
+            (Value::String(s), _) => self.string(s),
+        }
+    }
+
+    /// LLVM only reads decimal doubles with a `.` and no shorthand exponent, so anything else is
+    /// written as the hexadecimal bits.
+    fn double(n: f64) -> String {
+        let decimal = format!("{:?}", n);
+        if decimal.contains('.') && !decimal.contains('e') {
+            decimal
+        } else {
+            format!("0x{:016X}", n.to_bits())
+        }
+    }
+
+    /// Returns the global holding a string constant, adding it the first time.
+    fn string(&mut self, s: &str) -> String {
+        let index = self.strings.iter().position(|string| string == s).unwrap_or_else(|| {
+            self.strings.push(s.to_string());
+            self.strings.len() - 1
+        });
+        format!("@.str.{}", index)
+    }
+
+    /// Escapes a string for an LLVM `c"..."` constant, writing everything outside printable ASCII
+    /// as hex escapes of its UTF-8 bytes.
+    fn escape(s: &str) -> String {
+        let mut escaped = String::new();
+        for byte in s.bytes() {
+            match byte {
+                0x20..=0x7e if byte != b'"' && byte != b'\\' => escaped.push(byte as char),
+                _ => escaped.push_str(&format!("\\{:02X}", byte)),
+            }
+        }
+        escaped
+    }
+
+    fn declare(&mut self, name: &str, declaration: &str) {
+        if !self.declarations.iter().any(|(declared, _)| declared == name) {
+            self.declarations.push((name.to_string(), declaration.to_string()));
+        }
+    }
+
+    /// Calls a function of the program, returning the result operand unless it returns `unit`.
+    fn call(&mut self, return_type: &ValueType, callee: &str) -> String {
+        let call = format!("call {} {}", self.llvm_type(return_type), callee);
+        if *return_type == ValueType::Unit {
+            self.emit(call);
+            String::new()
+        } else {
+            self.instruction(call)
+        }
+    }
+
+    /// The symbol of a function of the program. Lamp names have no `.`, so they cannot clash with
+    /// `main` or the functions of the C runtime, which the program is linked with.
+    fn function_name(name: &str) -> String {
+        format!("lamp.{}", name)
+    }
+
+    fn method_name(owner: &str, name: &str) -> String {
+        format!("lamp.{}.{}", owner, name)
+    }
+
+    /// Declares the storage of a variable in the entry block. Variables are named `%v.NAME.ID`,
+    /// which no temporary, parameter or block label starts with.
+    fn variable(&mut self, name: &str, id: SymbolId, typ: &ValueType) -> String {
+        let address = format!("%v.{}.{}", name, id.0);
+        self.allocas += &format!("  {} = alloca {}\n", address, self.llvm_type(typ));
+        self.locals.insert(id, address.clone());
+        address
+    }
+
+    /// Declares unnamed storage in the entry block, for values that are passed by pointer.
+    fn temporary(&mut self, typ: &str) -> String {
+        let address = format!("%t.{}", self.next());
+        self.allocas += &format!("  {} = alloca {}\n", address, typ);
+        address
+    }
+
+    fn instruction(&mut self, instruction: String) -> String {
+        let result = format!("%t.{}", self.next());
+        self.emit(format!("{} = {}", result, instruction));
+        result
+    }
+
+    fn emit(&mut self, instruction: impl AsRef<str>) {
+        self.code += &format!("  {}\n", instruction.as_ref());
+    }
+
+    fn start_block(&mut self, label: &str) {
+        self.code += &format!("{}:\n", label);
+        self.block = label.to_string();
+    }
+
+    /// Ends the current block with a `ret` or `br`. Code after it in the same Lamp block cannot
+    /// run, but it still needs a block to go in.
+    fn terminate(&mut self, instruction: String) {
+        self.emit(instruction);
+        let label = format!("dead.{}", self.next());
+        self.start_block(&label);
+    }
+
+    fn next(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn type_of(&self, expr: &Expr) -> Result<ValueType, CompileError> {
+        expr.typ.clone().ok_or_else(|| CompileError::codegen("expression without a type", expr.span))
+    }
+
+    /// Formats a source offset as `file:line:column`, the way compile errors point at code.
+    fn location(&self, offset: usize) -> String {
+        self.source_map.describe(&self.file_name, offset)
+    }
+
+    fn llvm_type(&self, typ: &ValueType) -> String {
+        match typ {
+            ValueType::Number => "double".to_string(),
+            ValueType::Int(int) => Self::int_type(*int),
+            ValueType::String | ValueType::Array(_, _) => "ptr".to_string(),
+            ValueType::Bool => "i1".to_string(),
+            ValueType::Unit => "void".to_string(),
+            ValueType::Struct(name) => format!("%{}", name),
+        }
+    }
+
+    fn int_type(int: IntType) -> String {
+        format!("i{}", Self::int_bits(int))
+    }
+
+    fn int_bits(int: IntType) -> u32 {
+        match int {
+            IntType::I8 | IntType::U8 => 8,
+            IntType::I16 | IntType::U16 => 16,
+            IntType::I32 | IntType::U32 => 32,
+            IntType::I64 | IntType::U64 => 64,
+        }
+    }
+}
@@ -2,4 +2,17 @@ pub mod lexer;
 pub mod parser;
 pub mod ast;
 pub mod value;
-pub mod codegen;
\ No newline at end of file
+pub mod codegen;
+pub mod error;
+pub mod source_map;
+pub mod diagnostic;
+pub mod builtins;
+pub mod symbols;
+pub mod resolver;
+pub mod checker;
+pub mod interpreter;
+pub mod bytecode;
+pub mod compiler;
+pub mod vm;
+pub mod llvm;
+pub mod wasm;
\ No newline at end of file
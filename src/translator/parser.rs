@@ -1,43 +1,170 @@
-﻿use super::ast::{Expr, TypedArgument, Stmt};
-use super::lexer::{Lexeme, Token, TokenType};
-use super::value::{Value, ValueType};
+﻿use super::ast::{Expr, ExprKind, FieldInit, InterpolationPart, Iterable, TypedArgument, Stmt, StmtKind};
+use super::builtins::Builtin;
+use super::error::{CompileError, Span};
+use super::lexer::{Lexeme, StringPart, Token, TokenType};
+use super::value::{Value, ValueType, TYPE_NAMES};
+
+/// Binary operators with their precedence; a higher number binds tighter. The levels match C, so the
+/// generated code only needs parentheses where the AST groups against them.
+pub const BINARY_OPERATORS: [(TokenType, &str, u8); 13] = [
+    (TokenType::OrOr, "||", 1),
+    (TokenType::AndAnd, "&&", 2),
+    (TokenType::EqEq, "==", 3),
+    (TokenType::NoEq, "!=", 3),
+    (TokenType::Gt, ">", 4),
+    (TokenType::Lt, "<", 4),
+    (TokenType::GtEq, ">=", 4),
+    (TokenType::LtEq, "<=", 4),
+    (TokenType::Plus, "+", 5),
+    (TokenType::Minus, "-", 5),
+    (TokenType::Star, "*", 6),
+    (TokenType::Slash, "/", 6),
+    (TokenType::Percent, "%", 6),
+];
+
+pub fn precedence(op: &str) -> u8 {
+    BINARY_OPERATORS.iter().find(|(_, o, _)| *o == op).map(|(_, _, p)| *p).expect("unknown binary operator")
+}
 
 pub struct Parser {
     tokens: Vec<Token>,
     pos: usize,
+    /// Names of the structs defined anywhere in the program, which are valid type names.
+    struct_names: Vec<String>,
+    /// The struct whose `impl` block is being parsed.
+    impl_type: Option<String>,
+    /// Labels of the loops being parsed, innermost last.
+    labels: Vec<String>,
+    errors: Vec<CompileError>,
+    max_errors: usize,
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
-        Parser { tokens, pos: 0 }
+        let struct_names = tokens.windows(2).filter_map(|pair| match (&pair[0].token_type, &pair[1].lexeme) {
+            (TokenType::Struct, Lexeme::Word(name)) => Some(name.clone()),
+            _ => None,
+        }).collect();
+        Parser { tokens, pos: 0, struct_names, impl_type: None, labels: vec![], errors: vec![], max_errors: usize::MAX }
+    }
+
+    /// Stops parsing after `max_errors` errors; 0 means no limit.
+    pub fn with_max_errors(mut self, max_errors: usize) -> Self {
+        self.max_errors = if max_errors == 0 { usize::MAX } else { max_errors };
+        self
+    }
+
+    /// Adds struct names defined outside of the tokens, which can then be used as types.
+    pub fn with_struct_names(mut self, names: impl IntoIterator<Item = String>) -> Self {
+        self.struct_names.extend(names);
+        self
     }
 
-    pub fn parse(&mut self) -> Stmt {
+    /// Parses tokens that hold a single expression.
+    pub fn parse_expression(&mut self) -> Result<Expr, CompileError> {
+        let expr = self.expression()?;
+        self.consume(TokenType::Eof)?;
+        Ok(expr)
+    }
+
+    pub fn parse(&mut self) -> Result<Stmt, Vec<CompileError>> {
+        let start = self.get(0).start;
         let mut stmts: Vec<Stmt> = vec![];
-        while !self.check(TokenType::Eof) {
-            stmts.push(self.statement());
+        while !self.check(TokenType::Eof) && !self.too_many_errors() {
+            if let Some(stmt) = self.recovering_statement() {
+                stmts.push(stmt);
+            }
         }
-        Stmt::Block(stmts)
+        if !self.errors.is_empty() {
+            return Err(std::mem::take(&mut self.errors));
+        }
+        Ok(Stmt::new(StmtKind::Block(stmts), self.span_from(start)))
     }
 
-    fn parse_block(&mut self) -> Stmt {
+    fn parse_block(&mut self) -> Result<Stmt, CompileError> {
+        let start = self.get(0).start;
         let mut stmts: Vec<Stmt> = vec![];
-        self.consume(TokenType::LeftBrace);
+        let open = self.consume(TokenType::LeftBrace)?;
         while !self.check(TokenType::RightBrace) {
-            stmts.push(self.statement());
+            if self.get(0).token_type == TokenType::Eof {
+                return Err(CompileError::syntax("unclosed block", open.span())
+                    .with_help("add a `}` to close this block"));
+            }
+            if self.too_many_errors() {
+                break;
+            }
+            if let Some(stmt) = self.recovering_statement() {
+                stmts.push(stmt);
+            }
+        }
+
+        Ok(Stmt::new(StmtKind::Block(stmts), self.span_from(start)))
+    }
+
+    /// Parses a statement, recording its error and skipping to the next statement boundary on failure.
+    fn recovering_statement(&mut self) -> Option<Stmt> {
+        let start_pos = self.pos;
+        match self.statement() {
+            Ok(stmt) => Some(stmt),
+            Err(err) => {
+                self.errors.push(err);
+                self.synchronize(start_pos);
+                None
+            }
+        }
+    }
+
+    fn synchronize(&mut self, start_pos: usize) {
+        use TokenType::*;
+        if self.pos == start_pos && self.get(0).token_type != Eof {
+            self.pos += 1;
+        }
+        let mut depth = 0;
+        loop {
+            match self.get(0).token_type {
+                Eof => return,
+                Var | If | Func | While | For | Break | Continue | Return | Use | Struct | Impl if depth == 0 => return,
+                RightBrace if depth == 0 => return,
+                RightBrace => depth -= 1,
+                LeftBrace => depth += 1,
+                _ => {}
+            }
+            self.pos += 1;
         }
+    }
 
-        Stmt::Block(stmts)
+    fn too_many_errors(&self) -> bool {
+        self.errors.len() >= self.max_errors
     }
 
-    fn statement_or_block(&mut self) -> Stmt {
+    fn statement_or_block(&mut self) -> Result<Stmt, CompileError> {
         if self.get(0).token_type == TokenType::LeftBrace {
             return self.parse_block();
         }
         self.statement()
     }
 
-    fn statement(&mut self) -> Stmt {
+    fn statement(&mut self) -> Result<Stmt, CompileError> {
+        let first = self.get(0);
+        let kind = self.statement_kind()?;
+        let mut stmt = Stmt::new(kind, self.span_from(first.start));
+        if matches!(stmt.kind, StmtKind::FunctionDef(..) | StmtKind::Struct(..)) {
+            stmt.doc = first.doc;
+        }
+        Ok(stmt)
+    }
+
+    fn statement_kind(&mut self) -> Result<StmtKind, CompileError> {
+        if self.get(0).token_type == TokenType::Word && self.get(1).token_type == TokenType::Colon
+            && matches!(self.get(2).token_type, TokenType::While | TokenType::For) {
+            return self.labeled()
+        }
+        if self.check(TokenType::Break) {
+            return Ok(StmtKind::Break(self.loop_label()))
+        }
+        if self.check(TokenType::Continue) {
+            return Ok(StmtKind::Continue(self.loop_label()))
+        }
         if self.check(TokenType::If) {
             return self.if_else()
         }
@@ -47,14 +174,19 @@ impl Parser {
         if self.check(TokenType::While) {
             return self.while_()
         }
-        if self.get(0).token_type == TokenType::Word && self.get(1).token_type == TokenType::LeftParen {
-            return Stmt::Function(Box::from(self.function()))
+        if self.check(TokenType::For) {
+            return self.for_()
         }
         if self.check(TokenType::Func) {
             return self.function_define()
         }
         if self.check(TokenType::Return) {
-            return Stmt::Return(Box::from(self.expression()))
+            // A `return` at the end of a line or block returns from a `unit` function.
+            let next = self.get(0);
+            if next.line_break || matches!(next.token_type, TokenType::RightBrace | TokenType::Eof) {
+                return Ok(StmtKind::Return(None))
+            }
+            return Ok(StmtKind::Return(Some(Box::from(self.expression()?))))
         }
         if self.check(TokenType::Use) {
             return self.use_()
@@ -62,290 +194,426 @@ impl Parser {
         if self.check(TokenType::Struct) {
             return self.struct_()
         }
+        if self.check(TokenType::Impl) {
+            return self.impl_()
+        }
 
         self.assign()
     }
 
-    fn struct_(&mut self) -> Stmt {
-        let name = match self.consume(TokenType::Word).lexeme {
-            Lexeme::WordLexeme(v) => v,
-            _ => unreachable!(),
-        };
-        let fields: Vec<TypedArgument> = self.get_typed_arguments(TokenType::LeftBrace, TokenType::RightBrace);
-        Stmt::Struct(name, fields)
+    fn struct_(&mut self) -> Result<StmtKind, CompileError> {
+        let name = self.identifier()?;
+        let fields: Vec<TypedArgument> = self.get_typed_arguments(TokenType::LeftBrace, TokenType::RightBrace)?;
+        Ok(StmtKind::Struct(name, fields))
     }
 
-    fn use_(&mut self) -> Stmt {
-        let module = match self.consume(TokenType::Word).lexeme {
-            Lexeme::WordLexeme(v) => v,
-            _ => unreachable!(),
+    fn impl_(&mut self) -> Result<StmtKind, CompileError> {
+        let name = self.identifier()?;
+        self.consume(TokenType::LeftBrace)?;
+        let outer = self.impl_type.replace(name.clone());
+        let mut methods: Vec<Stmt> = vec![];
+        let result = loop {
+            if self.check(TokenType::RightBrace) {
+                break Ok(StmtKind::Impl(name, methods));
+            }
+            let token = self.get(0);
+            if token.token_type != TokenType::Func {
+                break Err(CompileError::syntax(format!("expected `func` or `}}`, found {}", token.token_type), token.span()));
+            }
+            match self.statement() {
+                Ok(method) => methods.push(method),
+                Err(err) => break Err(err),
+            }
         };
-        Stmt::Use(module)
+        self.impl_type = outer;
+        result
     }
 
-    fn function_define(&mut self) -> Stmt {
-        let name = match self.consume(TokenType::Word).lexeme {
-            Lexeme::WordLexeme(v) => v,
-            _ => unreachable!(),
+    fn use_(&mut self) -> Result<StmtKind, CompileError> {
+        let module = self.identifier()?;
+        Ok(StmtKind::Use(module))
+    }
+
+    fn function_define(&mut self) -> Result<StmtKind, CompileError> {
+        let name = self.identifier()?;
+        let params = match self.impl_type.clone() {
+            Some(owner) => self.method_parameters(owner)?,
+            None => self.get_typed_arguments(TokenType::LeftParen, TokenType::RightParen)?,
         };
-        let params = self.get_typed_arguments(TokenType::LeftParen, TokenType::RightParen);
-        let return_value = self.parse_value_type();
-        let body = self.statement_or_block();
-        Stmt::FunctionDef(name, params, Box::from(body), return_value)
+        let return_value = self.parse_value_type()?;
+        let body = self.statement_or_block()?;
+        Ok(StmtKind::FunctionDef(name, params, Box::from(body), return_value))
     }
 
-    fn get_typed_arguments(&mut self, left: TokenType, right: TokenType) -> Vec<TypedArgument> {
-        self.consume(left);
+    /// Parses the parameters of a method, which start with an untyped `self` of the `impl` type.
+    fn method_parameters(&mut self, owner: String) -> Result<Vec<TypedArgument>, CompileError> {
+        self.consume(TokenType::LeftParen)?;
+        let token = self.get(0);
+        if !matches!(&token.lexeme, Lexeme::Word(word) if word == "self") {
+            return Err(CompileError::syntax(format!("expected `self`, found {}", token.token_type), token.span())
+                .with_help("methods take `self` as their first parameter"));
+        }
+        self.pos += 1;
+        self.check(TokenType::Comma);
+        let mut params = vec![TypedArgument { name: "self".to_string(), typ: ValueType::Struct(owner), symbol: None }];
+        params.extend(self.typed_arguments_until(TokenType::RightParen)?);
+        Ok(params)
+    }
+
+    fn get_typed_arguments(&mut self, left: TokenType, right: TokenType) -> Result<Vec<TypedArgument>, CompileError> {
+        self.consume(left)?;
+        self.typed_arguments_until(right)
+    }
+
+    fn typed_arguments_until(&mut self, right: TokenType) -> Result<Vec<TypedArgument>, CompileError> {
         let mut params: Vec<TypedArgument> = vec![];
         while !self.check(right) {
-            let name = match self.consume(TokenType::Word).lexeme {
-                Lexeme::WordLexeme(v) => v,
-                _ => unreachable!(),
-            };
-            let value_type: ValueType = self.parse_value_type();
+            let name = self.identifier()?;
+            let value_type: ValueType = self.parse_value_type()?;
             params.push(TypedArgument {
                 name,
                 typ: value_type,
+                symbol: None,
             });
             self.check(TokenType::Comma);
         }
-        params
+        Ok(params)
     }
 
-    fn parse_value_type(&mut self) -> ValueType {
-        self.consume(TokenType::Colon);
-        let type_string = match self.consume(TokenType::Word).lexeme {
-            Lexeme::WordLexeme(v) => v,
-            _ => unreachable!()
-        };
-        let value_type: ValueType = match type_string.as_str() {
-            "number" => ValueType::Number,
-            "string" => ValueType::String,
-            "unit" => ValueType::Unit,
-            _ => panic!("incorrect value type")
-        };
-        value_type
+    /// The length of a fixed-size array type such as `[number; 3]`.
+    fn array_length(&mut self) -> Result<usize, CompileError> {
+        let token = self.get(0);
+        match token.lexeme {
+            Lexeme::Integer(length) if token.token_type == TokenType::Number && let Ok(length) = usize::try_from(length) => {
+                self.pos += 1;
+                Ok(length)
+            }
+            _ => Err(CompileError::syntax(format!("expected an array length, found {}", token.token_type), token.span())),
+        }
     }
 
-    fn while_(&mut self) -> Stmt {
-        let cond = self.expression();
-        let body = self.statement_or_block();
-        Stmt::While(Box::from(cond), Box::from(body))
+    fn parse_value_type(&mut self) -> Result<ValueType, CompileError> {
+        self.consume(TokenType::Colon)?;
+        self.value_type()
     }
 
-    fn if_else(&mut self) -> Stmt {
-        let cond = self.expression();
-        let body = self.statement_or_block();
-        let mut else_body: Option<Stmt> = None;
-        if self.check(TokenType::Else) {
-            else_body = Some(self.statement_or_block());
+    fn value_type(&mut self) -> Result<ValueType, CompileError> {
+        if self.check(TokenType::LeftBracket) {
+            let element = self.value_type()?;
+            let length = if self.check(TokenType::Semicolon) { Some(self.array_length()?) } else { None };
+            self.consume(TokenType::RightBracket)?;
+            return Ok(ValueType::Array(Box::from(element), length));
         }
-
-        Stmt::If(Box::from(cond), Box::from(body), Box::from(else_body))
-    }
-
-    fn assign(&mut self) -> Stmt {
-        let cur = self.get(0);
-        if self.check(TokenType::Word) && self.get(0).token_type == TokenType::Eq {
-            let name = match cur.lexeme {
-                Lexeme::WordLexeme(v) => v,
-                _ => unreachable!()
-            };
-            self.consume(TokenType::Eq);
-            return Stmt::Assign(name, Box::from(self.expression()));
+        let span = self.get(0).span();
+        let type_string = self.identifier()?;
+        if let Some(typ) = ValueType::from_name(&type_string) {
+            return Ok(typ);
         }
-        panic!("Invalid assignment at {:#?}", self.pos..self.pos);
-    }
-
-    fn var_def(&mut self) -> Stmt {
-        let cur = self.get(0);
-        if self.check(TokenType::Word) && self.get(0).token_type == TokenType::Colon
-            && self.get(1).token_type == TokenType::Word && self.get(2).token_type == TokenType::Eq {
-            let name = match cur.lexeme {
-                Lexeme::WordLexeme(v) => v,
-                _ => unreachable!()
-            };
-            let value_type = self.parse_value_type();
-            self.consume(TokenType::Eq);
-
-            return Stmt::VarDef(name, Box::new(self.expression()), value_type);
+        if self.struct_names.contains(&type_string) {
+            return Ok(ValueType::Struct(type_string));
         }
-        panic!("Invalid var definition at {:?}", cur.start..cur.end);
+        let names: Vec<String> = TYPE_NAMES.iter().copied().chain(self.struct_names.iter().map(String::as_str))
+            .map(|name| format!("`{}`", name)).collect();
+        Err(CompileError::syntax(format!("unknown type `{}`", type_string), span)
+            .with_help(format!("expected one of {}", names.join(", "))))
     }
 
-    fn function(&mut self) -> Expr {
-        let name = match self.consume(TokenType::Word).lexeme {
-            Lexeme::WordLexeme(v) => v,
-            _ => unreachable!()
-        };
-        self.consume(TokenType::LeftParen);
-        let mut params: Vec<Expr> = vec![];
-        while !self.check(TokenType::RightParen) {
-            params.push(self.expression());
-            self.check(TokenType::Comma);
-        }
-
-        Expr::Functional(name, params)
+    fn while_(&mut self) -> Result<StmtKind, CompileError> {
+        let cond = self.expression()?;
+        let body = self.statement_or_block()?;
+        Ok(StmtKind::While(Box::from(cond), Box::from(body)))
     }
 
-    fn expression(&mut self) -> Expr {
-        self.logical_or()
+    fn labeled(&mut self) -> Result<StmtKind, CompileError> {
+        let label = self.identifier()?;
+        self.consume(TokenType::Colon)?;
+        self.labels.push(label.clone());
+        let body = self.statement();
+        self.labels.pop();
+        Ok(StmtKind::Labeled(label, Box::from(body?)))
     }
 
-    fn logical_or(&mut self) -> Expr {
-        let mut result = self.logical_and();
-
-        loop {
-            if self.check(TokenType::OrOr) {
-                result = Expr::Condition("||".to_string(), Box::new(result), Box::new(self.logical_and()));
-                continue
+    /// Parses the label after `break` or `continue`. Statements are not separated by `;`, so a word
+    /// is only taken as the label when it names an enclosing loop.
+    fn loop_label(&mut self) -> Option<String> {
+        match &self.get(0).lexeme {
+            Lexeme::Word(name) if self.labels.contains(name) => {
+                let name = name.clone();
+                self.pos += 1;
+                Some(name)
             }
-            break
+            _ => None,
         }
-
-        result
     }
 
-    fn logical_and(&mut self) -> Expr {
-        let mut result = self.equal();
+    fn for_(&mut self) -> Result<StmtKind, CompileError> {
+        let name = self.identifier()?;
+        self.consume(TokenType::In)?;
+        let start = self.expression()?;
+        let iterable = if self.check(TokenType::DotDot) {
+            Iterable::Range(Box::from(start), Box::from(self.expression()?))
+        } else {
+            Iterable::Array(Box::from(start))
+        };
+        let body = self.statement_or_block()?;
+        Ok(StmtKind::For(name, iterable, Box::from(body)))
+    }
 
-        loop {
-            if self.check(TokenType::AndAnd) {
-                result = Expr::Condition("&&".to_string(), Box::from(result), Box::from(self.equal()));
-                continue
-            }
-            break
+    fn if_else(&mut self) -> Result<StmtKind, CompileError> {
+        let cond = self.expression()?;
+        let body = self.statement_or_block()?;
+        let mut else_body: Option<Stmt> = None;
+        if self.check(TokenType::Else) {
+            else_body = Some(self.statement_or_block()?);
         }
 
-        result
+        Ok(StmtKind::If(Box::from(cond), Box::from(body), Box::from(else_body)))
     }
 
-    fn equal(&mut self) -> Expr {
-        let mut result = self.compare();
-
-        loop {
-            if self.check(TokenType::EqEq) {
-                result = Expr::Condition("==".to_string(), Box::from(result), Box::from(self.compare()));
-                continue
-            } else if self.check(TokenType::NoEq) {
-                result = Expr::Condition("!=".to_string(), Box::from(result), Box::from(self.compare()));
-                continue
-            }
-            break
+    /// Parses a call used as a statement, or an assignment to a variable or field.
+    fn assign(&mut self) -> Result<StmtKind, CompileError> {
+        let cur = self.get(0);
+        if cur.token_type != TokenType::Word {
+            return Err(CompileError::syntax(format!("expected statement, found {}", cur.token_type), cur.span()));
         }
-
-
-        result
+        let target = self.primary()?;
+        if matches!(target.kind, ExprKind::Functional(..) | ExprKind::MethodCall(..) | ExprKind::Builtin(..)) {
+            return Ok(StmtKind::Function(Box::from(target)));
+        }
+        self.consume(TokenType::Eq)?;
+        if !matches!(target.kind, ExprKind::VarUse(_) | ExprKind::Field(..) | ExprKind::Index(..)) {
+            return Err(CompileError::syntax("invalid assignment target", target.span)
+                .with_help("only variables, fields and array elements can be assigned to"));
+        }
+        Ok(StmtKind::Assign(Box::from(target), Box::from(self.expression()?)))
     }
 
-    fn compare(&mut self) -> Expr {
-        let mut result = self.multiply();
+    fn var_def(&mut self) -> Result<StmtKind, CompileError> {
+        let name = self.identifier()?;
+        let value_type = self.parse_value_type()?;
+        self.consume(TokenType::Eq)?;
 
-        loop {
-            if self.check(TokenType::Gt) {
-                result = Expr::Condition(">".to_string(), Box::from(result), Box::from(self.multiply()));
-                continue
-            } else if self.check(TokenType::Lt) {
-                result = Expr::Condition("<".to_string(), Box::from(result), Box::from(self.multiply()));
-                continue
-            } else if self.check(TokenType::GtEq) {
-                result = Expr::Condition(">=".to_string(), Box::from(result), Box::from(self.multiply()));
-                continue
-            } else if self.check(TokenType::LtEq) {
-                result = Expr::Condition("<=".to_string(), Box::from(result), Box::from(self.multiply()));
-                continue
-            }
-            break
-        }
-
-        result
+        Ok(StmtKind::VarDef(name, Box::new(self.expression()?), value_type))
     }
 
-    fn multiply(&mut self) -> Expr {
-        let mut result = self.addition();
+    fn function(&mut self) -> Result<Expr, CompileError> {
+        let start = self.get(0).start;
+        let name = self.identifier()?;
+        self.consume(TokenType::LeftParen)?;
+        let params = self.call_arguments()?;
+        let kind = match Builtin::from_name(&name) {
+            Some(builtin) => ExprKind::Builtin(builtin, params),
+            None => ExprKind::Functional(name, params),
+        };
+        Ok(Expr::new(kind, self.span_from(start)))
+    }
 
-        loop {
-            if self.check(TokenType::Star) {
-                result = Expr::Binary('*', Box::from(result), Box::from(self.addition()));
-                continue
-            } else if self.check(TokenType::Slash) {
-                result = Expr::Binary('/', Box::from(result), Box::from(self.addition()));
-                continue
-            }
-            break
+    /// Parses call arguments after the opening `(`.
+    fn call_arguments(&mut self) -> Result<Vec<Expr>, CompileError> {
+        let mut args: Vec<Expr> = vec![];
+        while !self.check(TokenType::RightParen) {
+            args.push(self.expression()?);
+            self.check(TokenType::Comma);
         }
+        Ok(args)
+    }
 
-        result
+    fn expression(&mut self) -> Result<Expr, CompileError> {
+        self.binary_expression(1)
     }
 
-    fn addition(&mut self) -> Expr {
-        let mut result = self.unary();
+    /// Precedence climbing over `BINARY_OPERATORS`: parses operators binding at least as tightly as
+    /// `min_precedence`, and the right operand only takes operators binding tighter, so every level
+    /// is left-associative.
+    fn binary_expression(&mut self, min_precedence: u8) -> Result<Expr, CompileError> {
+        let mut result = self.unary()?;
 
         loop {
-            if self.check(TokenType::Plus) {
-                result = Expr::Binary('+', Box::from(result), Box::from(self.unary()));
-                continue
-            } else if self.check(TokenType::Minus) {
-                result = Expr::Binary('-', Box::from(result), Box::from(self.unary()));
-                continue
-            }
-            break
+            let token_type = self.get(0).token_type;
+            let (op, precedence) = match BINARY_OPERATORS.iter().find(|(t, _, _)| *t == token_type) {
+                Some((_, op, precedence)) if *precedence >= min_precedence => (*op, *precedence),
+                _ => break,
+            };
+            self.pos += 1;
+            let right = self.binary_expression(precedence + 1)?;
+            result = Self::binary(op, result, right);
         }
 
-        result
+        Ok(result)
     }
 
-
-    fn unary(&mut self) -> Expr {
+    fn unary(&mut self) -> Result<Expr, CompileError> {
+        let start = self.get(0).start;
         if self.check(TokenType::Minus) {
-            return Expr::Unary('-', Box::from(self.primary()));
+            let operand = self.unary()?;
+            return Ok(Expr::new(ExprKind::Unary('-', Box::from(operand)), self.span_from(start)));
+        }
+        if self.check(TokenType::Not) {
+            let operand = self.unary()?;
+            return Ok(Expr::new(ExprKind::Unary('!', Box::from(operand)), self.span_from(start)));
         }
 
         self.primary()
     }
 
-    fn primary(&mut self) -> Expr {
+    /// Parses an atom followed by any `.field` accesses and `.method()` calls.
+    fn primary(&mut self) -> Result<Expr, CompileError> {
+        let mut expr = self.atom()?;
+        let expr_start = expr.span.start;
+        loop {
+            if self.check(TokenType::LeftBracket) {
+                let index = self.expression()?;
+                self.consume(TokenType::RightBracket)?;
+                expr = Expr::new(ExprKind::Index(Box::from(expr), Box::from(index)), self.span_from(expr_start));
+                continue;
+            }
+            if !self.check(TokenType::Dot) {
+                break;
+            }
+            let name = self.identifier()?;
+            let kind = if self.check(TokenType::LeftParen) {
+                let args = self.call_arguments()?;
+                ExprKind::MethodCall(Box::from(expr), name, args)
+            } else {
+                ExprKind::Field(Box::from(expr), name)
+            };
+            expr = Expr::new(kind, self.span_from(expr_start));
+        }
+        Ok(expr)
+    }
+
+    fn atom(&mut self) -> Result<Expr, CompileError> {
         let token = self.get(0);
+        let span = token.span();
         if self.check(TokenType::Number) {
-            if let Lexeme::NumberLexeme(v) = token.lexeme {
-                return Expr::Value(Value::Number(v))
-            }
+            let value = match token.lexeme {
+                Lexeme::Float(v) => Value::Number(v),
+                Lexeme::Integer(v) => Value::Integer(v),
+                _ => unreachable!("number tokens carry a numeric lexeme"),
+            };
+            return Ok(Expr::new(ExprKind::Value(value), span))
         }
         if self.check(TokenType::String) {
-            if let Lexeme::StringLexeme(v) = token.lexeme {
-                return Expr::Value(Value::String(v))
+            return match &token.lexeme {
+                Lexeme::Interpolated(parts) => Self::interpolation(parts, span),
+                Lexeme::String(v) => Ok(Expr::new(ExprKind::Value(Value::String(v.clone())), span)),
+                _ => unreachable!("string tokens carry a string lexeme"),
             }
         }
+        if self.check(TokenType::True) {
+            return Ok(Expr::new(ExprKind::Value(Value::Bool(true)), span))
+        }
+        if self.check(TokenType::False) {
+            return Ok(Expr::new(ExprKind::Value(Value::Bool(false)), span))
+        }
         if self.get(0).token_type == TokenType::New && self.get(1).token_type == TokenType::Word {
-            self.consume(TokenType::New);
-            let name = match self.consume(TokenType::Word).lexeme {
-                Lexeme::WordLexeme(v) => v,
-                _ => unreachable!(),
-            };
-            let params = self.get_typed_arguments(TokenType::LeftParen, TokenType::RightParen);
-            return Expr::New(name, params)
+            self.consume(TokenType::New)?;
+            let name = self.identifier()?;
+            let fields = self.field_inits()?;
+            return Ok(Expr::new(ExprKind::New(name, fields), self.span_from(span.start)))
         }
         if self.get(0).token_type == TokenType::Word && self.get(1).token_type == TokenType::LeftParen {
+            if let Lexeme::Word(name) = &token.lexeme && let Some(typ) = ValueType::from_name(name) {
+                return self.conversion(typ);
+            }
             return self.function()
         }
-        if self.check(TokenType::Word) {
-            if let Lexeme::WordLexeme(v) = token.lexeme {
-                return Expr::VarUse(v)
-            }
+        if self.check(TokenType::Word) && let Lexeme::Word(v) = &token.lexeme {
+            return Ok(Expr::new(ExprKind::VarUse(v.clone()), span))
         }
         if self.check(TokenType::LeftParen) {
-            let expr = self.expression();
-            self.consume(TokenType::RightParen);
-            return expr;
+            let expr = self.expression()?;
+            self.consume(TokenType::RightParen)?;
+            return Ok(expr);
+        }
+        if self.check(TokenType::LeftBracket) {
+            let mut elements: Vec<Expr> = vec![];
+            while !self.check(TokenType::RightBracket) {
+                elements.push(self.expression()?);
+                if !self.check(TokenType::Comma) {
+                    self.consume(TokenType::RightBracket)?;
+                    break;
+                }
+            }
+            return Ok(Expr::new(ExprKind::Array(elements), self.span_from(span.start)));
+        }
+        Err(CompileError::syntax(format!("expected expression, found {}", token.token_type), span))
+    }
+
+    /// Parses the `(x: 1, y: 2)` part of a struct construction.
+    fn field_inits(&mut self) -> Result<Vec<FieldInit>, CompileError> {
+        self.consume(TokenType::LeftParen)?;
+        let mut fields: Vec<FieldInit> = vec![];
+        while !self.check(TokenType::RightParen) {
+            let name = self.identifier()?;
+            self.consume(TokenType::Colon)?;
+            let value = self.expression()?;
+            fields.push(FieldInit { name, value });
+            self.check(TokenType::Comma);
+        }
+        Ok(fields)
+    }
+
+    /// Parses the parts of an interpolated string; each `${...}` is parsed on its own tokens.
+    fn interpolation(parts: &[StringPart], span: Span) -> Result<Expr, CompileError> {
+        let mut result: Vec<InterpolationPart> = vec![];
+        for part in parts {
+            match part {
+                StringPart::Text(text) => result.push(InterpolationPart::Text(text.clone())),
+                StringPart::Code(tokens) => {
+                    let mut parser = Parser::new(tokens.clone());
+                    if parser.check(TokenType::Eof) {
+                        return Err(CompileError::syntax("empty interpolation", parser.get(0).span())
+                            .with_help("write an expression between `${` and `}`"));
+                    }
+                    let expr = parser.expression()?;
+                    let next = parser.get(0);
+                    if next.token_type != TokenType::Eof {
+                        return Err(CompileError::syntax(format!("expected `}}`, found {}", next.token_type), next.span()));
+                    }
+                    result.push(InterpolationPart::Expr(expr));
+                }
+            }
         }
-        panic!("Invalid primary: {:#?}", token);
+        Ok(Expr::new(ExprKind::Interpolation(result), span))
+    }
+
+    /// Parses an explicit conversion such as `int(x)` or `number(n)`.
+    fn conversion(&mut self, typ: ValueType) -> Result<Expr, CompileError> {
+        let start = self.get(0).start;
+        self.consume(TokenType::Word)?;
+        self.consume(TokenType::LeftParen)?;
+        let value = self.expression()?;
+        self.consume(TokenType::RightParen)?;
+        Ok(Expr::new(ExprKind::Convert(typ, Box::from(value)), self.span_from(start)))
     }
 
-    fn consume(&mut self, token_type: TokenType) -> Token {
-        let result = self.tokens.get(self.pos).unwrap();
-        assert_eq!(result.token_type, token_type, "expected token type {:?}, but founded {:?} at {:?}", token_type, result.token_type, result.start..result.end);
+    fn binary(op: &str, left: Expr, right: Expr) -> Expr {
+        let span = left.span.merge(right.span);
+        let kind = match op {
+            "+" | "-" | "*" | "/" | "%" => ExprKind::Binary(op.chars().next().unwrap(), Box::from(left), Box::from(right)),
+            _ => ExprKind::Condition(op.to_string(), Box::from(left), Box::from(right)),
+        };
+        Expr::new(kind, span)
+    }
+
+    fn identifier(&mut self) -> Result<String, CompileError> {
+        match self.consume(TokenType::Word)?.lexeme {
+            Lexeme::Word(v) => Ok(v),
+            _ => unreachable!(),
+        }
+    }
+
+    fn consume(&mut self, token_type: TokenType) -> Result<Token, CompileError> {
+        let result = self.get(0);
+        if result.token_type != token_type {
+            return Err(CompileError::syntax(
+                format!("expected {}, found {}", token_type, result.token_type),
+                result.span(),
+            ));
+        }
         self.pos += 1;
-        result.clone()
+        Ok(result)
     }
 
     fn check(&mut self, token_type: TokenType) -> bool {
@@ -356,8 +624,16 @@ impl Parser {
         result
     }
 
-    fn get(&mut self, relative_pos: usize) -> Token {
-        let position = self.pos + relative_pos;
-        self.tokens.get(position).unwrap().clone()
+    fn get(&self, relative_pos: usize) -> Token {
+        let position = (self.pos + relative_pos).min(self.tokens.len() - 1);
+        self.tokens[position].clone()
+    }
+
+    fn span_from(&self, start: usize) -> Span {
+        let end = match self.pos {
+            0 => start,
+            pos => self.tokens[pos.min(self.tokens.len()) - 1].end,
+        };
+        Span::new(start, end.max(start))
     }
 }
\ No newline at end of file
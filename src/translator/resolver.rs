@@ -0,0 +1,325 @@
+//! Name resolution.
+//!
+//! Scoping rules:
+//! - functions and structs share one global namespace and are visible everywhere, including before their
+//!   definition; functions imported with `use` live there too;
+//! - a variable is visible from its declaration to the end of the enclosing block;
+//! - the body of `if`, `else`, `while` and `for` is a new block, and a function's parameters share the
+//!   block of its body; the variable of a `for` loop lives in a block around the body, so the body may
+//!   shadow it;
+//! - a variable may shadow a variable of an enclosing block, and its initializer still sees the outer
+//!   one; declaring the same name twice in one block is an error;
+//! - variables and parameters may not reuse the name of a function or struct;
+//! - function bodies only see their parameters, not the variables of the top-level program.
+
+use std::collections::HashMap;
+
+use super::ast::{Expr, ExprKind, InterpolationPart, Iterable, Stmt, StmtKind, TypedArgument};
+use super::builtins::{self, Builtin, Signature};
+use super::error::{CompileError, Span};
+use super::symbols::{method_key, Symbol, SymbolId, SymbolKind, SymbolTable};
+use super::value::ValueType;
+
+#[derive(Default)]
+pub struct Resolver {
+    symbols: SymbolTable,
+    items: HashMap<String, SymbolId>,
+    scopes: Vec<HashMap<String, SymbolId>>,
+    /// Number of loops around the current statement, and the labels among them.
+    loop_depth: usize,
+    labels: Vec<String>,
+    errors: Vec<CompileError>,
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Resolves every name in the program and records the symbol ids on the AST.
+    pub fn resolve(mut self, program: &mut Stmt) -> (SymbolTable, Vec<CompileError>) {
+        if let StmtKind::Block(stmts) = &mut program.kind {
+            self.declare_items(stmts);
+            self.scopes.push(HashMap::new());
+            for stmt in stmts.iter_mut() {
+                self.resolve_statement(stmt, true);
+            }
+            self.scopes.pop();
+        }
+        (self.symbols, self.errors)
+    }
+
+    fn declare_items(&mut self, stmts: &mut [Stmt]) {
+        let mut modules: Vec<&str> = vec![];
+        for stmt in stmts.iter() {
+            if let StmtKind::Use(module) = &stmt.kind {
+                if modules.contains(&module.as_str()) {
+                    continue;
+                }
+                modules.push(module);
+                match builtins::module_functions(module) {
+                    Some(functions) => {
+                        for (name, signature) in functions {
+                            self.declare_item(name, SymbolKind::Function(signature), stmt.span);
+                        }
+                    }
+                    None => self.errors.push(CompileError::name(format!("unknown module `{}`", module), stmt.span)
+                        .with_help(format!("available modules: {}", builtins::MODULES.join(", ")))),
+                }
+            }
+        }
+        for stmt in stmts.iter_mut() {
+            match &mut stmt.kind {
+                StmtKind::FunctionDef(name, args, _, return_type) => {
+                    if Builtin::from_name(name).is_some() {
+                        self.errors.push(CompileError::name(format!("`{}` is a builtin function and cannot be redefined", name), stmt.span));
+                        continue;
+                    }
+                    let params = args.iter().map(|a| a.typ.clone()).collect();
+                    stmt.symbol = self.declare_item(name, SymbolKind::Function(Signature::new(params, return_type.clone())), stmt.span);
+                }
+                StmtKind::Struct(name, fields) => {
+                    stmt.symbol = self.declare_item(name, SymbolKind::Struct(fields.clone()), stmt.span);
+                }
+                StmtKind::Impl(owner, methods) => {
+                    for method in methods.iter_mut() {
+                        if let StmtKind::FunctionDef(name, args, _, return_type) = &method.kind {
+                            let params = args.iter().skip(1).map(|a| a.typ.clone()).collect();
+                            let signature = Signature::new(params, return_type.clone());
+                            method.symbol = self.declare_item(&method_key(owner, name), SymbolKind::Function(signature), method.span);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn declare_item(&mut self, name: &str, kind: SymbolKind, span: Span) -> Option<SymbolId> {
+        if self.items.contains_key(name) {
+            self.errors.push(CompileError::name(format!("`{}` is already defined", name), span));
+            return None;
+        }
+        let id = self.symbols.add(Symbol { name: name.to_string(), kind, span, shadows: None });
+        self.items.insert(name.to_string(), id);
+        Some(id)
+    }
+
+    fn resolve_statement(&mut self, stmt: &mut Stmt, top_level: bool) {
+        let span = stmt.span;
+        match &mut stmt.kind {
+            StmtKind::VarDef(name, value, typ) => {
+                self.resolve_expr(value);
+                stmt.symbol = self.declare_var(name, typ.clone(), span);
+            }
+            StmtKind::Assign(target, value) => {
+                self.resolve_expr(value);
+                match &mut target.kind {
+                    ExprKind::VarUse(name) => {
+                        target.symbol = self.lookup_var(name);
+                        if target.symbol.is_none() {
+                            self.errors.push(CompileError::name(format!("cannot assign to undefined variable `{}`", name), span)
+                                .with_help(format!("declare it first with `var {}: ...`", name)));
+                        }
+                    }
+                    _ => self.resolve_expr(target),
+                }
+            }
+            StmtKind::If(cond, body, else_body) => {
+                self.resolve_expr(cond);
+                self.resolve_block(body, &mut []);
+                if let Some(else_body) = else_body.as_mut() {
+                    self.resolve_block(else_body, &mut []);
+                }
+            }
+            StmtKind::While(cond, body) => {
+                self.resolve_expr(cond);
+                self.loop_depth += 1;
+                self.resolve_block(body, &mut []);
+                self.loop_depth -= 1;
+            }
+            StmtKind::Labeled(label, body) => {
+                if self.labels.contains(label) {
+                    self.errors.push(CompileError::name(format!("label `{}` is already used by an enclosing loop", label), span));
+                }
+                self.labels.push(label.clone());
+                self.resolve_statement(body, false);
+                self.labels.pop();
+            }
+            StmtKind::Break(_) | StmtKind::Continue(_) => {
+                if self.loop_depth == 0 {
+                    let keyword = if matches!(stmt.kind, StmtKind::Break(_)) { "break" } else { "continue" };
+                    self.errors.push(CompileError::syntax(format!("`{}` outside of a loop", keyword), span));
+                }
+            }
+            StmtKind::For(name, iterable, body) => {
+                match iterable {
+                    Iterable::Range(start, end) => {
+                        self.resolve_expr(start);
+                        self.resolve_expr(end);
+                    }
+                    Iterable::Array(array) => self.resolve_expr(array),
+                }
+                self.scopes.push(HashMap::new());
+                stmt.symbol = self.declare_local(name, SymbolKind::LoopVariable, span);
+                self.loop_depth += 1;
+                self.resolve_block(body, &mut []);
+                self.loop_depth -= 1;
+                self.scopes.pop();
+            }
+            StmtKind::Block(_) => self.resolve_block(stmt, &mut []),
+            StmtKind::Function(expr) => self.resolve_expr(expr),
+            StmtKind::FunctionDef(_, args, body, _) => {
+                if !top_level {
+                    self.errors.push(CompileError::syntax("functions can only be defined at the top level", span));
+                }
+                let outer = std::mem::take(&mut self.scopes);
+                let outer_loops = std::mem::take(&mut self.loop_depth);
+                let outer_labels = std::mem::take(&mut self.labels);
+                self.resolve_block(body, args);
+                self.scopes = outer;
+                self.loop_depth = outer_loops;
+                self.labels = outer_labels;
+            }
+            StmtKind::Return(Some(value)) => self.resolve_expr(value),
+            StmtKind::Return(None) => {}
+            StmtKind::Use(_) => {
+                if !top_level {
+                    self.errors.push(CompileError::syntax("`use` can only appear at the top level", span));
+                }
+            }
+            StmtKind::Impl(owner, methods) => {
+                if !top_level {
+                    self.errors.push(CompileError::syntax("`impl` blocks can only appear at the top level", span));
+                }
+                if !matches!(self.items.get(owner.as_str()).map(|id| &self.symbols.get(*id).kind), Some(SymbolKind::Struct(_))) {
+                    self.errors.push(CompileError::name(format!("cannot find struct `{}`", owner), span));
+                }
+                for method in methods.iter_mut() {
+                    self.resolve_statement(method, true);
+                }
+            }
+            StmtKind::Struct(name, fields) => {
+                if !top_level {
+                    self.errors.push(CompileError::syntax("structs can only be defined at the top level", span));
+                }
+                for (i, field) in fields.iter().enumerate() {
+                    if fields[..i].iter().any(|f| f.name == field.name) {
+                        self.errors.push(CompileError::name(format!("field `{}` is declared twice in struct `{}`", field.name, name), span));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Resolves a statement in a new block that starts with the given parameters.
+    fn resolve_block(&mut self, body: &mut Stmt, params: &mut [TypedArgument]) {
+        self.scopes.push(HashMap::new());
+        for param in params.iter_mut() {
+            param.symbol = self.declare_var(&param.name, param.typ.clone(), body.span);
+        }
+        match &mut body.kind {
+            StmtKind::Block(stmts) => {
+                for stmt in stmts.iter_mut() {
+                    self.resolve_statement(stmt, false);
+                }
+            }
+            _ => self.resolve_statement(body, false),
+        }
+        self.scopes.pop();
+    }
+
+    fn resolve_expr(&mut self, expr: &mut Expr) {
+        let span = expr.span;
+        match &mut expr.kind {
+            ExprKind::Value(_) => {}
+            ExprKind::VarUse(name) => {
+                expr.symbol = self.lookup_var(name);
+                if expr.symbol.is_none() {
+                    let mut err = CompileError::name(format!("cannot find variable `{}` in this scope", name), span);
+                    if self.items.contains_key(name.as_str()) {
+                        err = err.with_note(format!("`{}` is a function or struct, not a variable", name));
+                    }
+                    self.errors.push(err);
+                }
+            }
+            ExprKind::Binary(_, left, right) | ExprKind::Condition(_, left, right) => {
+                self.resolve_expr(left);
+                self.resolve_expr(right);
+            }
+            ExprKind::Unary(_, operand) | ExprKind::Convert(_, operand) | ExprKind::Field(operand, _) => self.resolve_expr(operand),
+            ExprKind::Index(array, index) => {
+                self.resolve_expr(array);
+                self.resolve_expr(index);
+            }
+            ExprKind::Array(args) | ExprKind::Builtin(_, args) => {
+                for arg in args.iter_mut() {
+                    self.resolve_expr(arg);
+                }
+            }
+            ExprKind::MethodCall(receiver, _, args) => {
+                // The method is found by the type checker, once the receiver's type is known.
+                self.resolve_expr(receiver);
+                for arg in args.iter_mut() {
+                    self.resolve_expr(arg);
+                }
+            }
+            ExprKind::Functional(name, args) => {
+                for arg in args.iter_mut() {
+                    self.resolve_expr(arg);
+                }
+                match self.items.get(name.as_str()).copied() {
+                    Some(id) if matches!(self.symbols.get(id).kind, SymbolKind::Function(_)) => expr.symbol = Some(id),
+                    Some(_) => self.errors.push(CompileError::name(format!("`{}` is a struct, not a function", name), span)
+                        .with_help(format!("use `new {}(...)` to construct it", name))),
+                    None => {
+                        let mut err = CompileError::name(format!("cannot find function `{}`", name), span);
+                        if let Some(module) = builtins::module_of(name) {
+                            err = err.with_help(format!("add `use {}` to import it", module));
+                        }
+                        self.errors.push(err);
+                    }
+                }
+            }
+            ExprKind::Interpolation(parts) => {
+                for part in parts.iter_mut() {
+                    if let InterpolationPart::Expr(value) = part {
+                        self.resolve_expr(value);
+                    }
+                }
+            }
+            ExprKind::New(name, fields) => {
+                for field in fields.iter_mut() {
+                    self.resolve_expr(&mut field.value);
+                }
+                match self.items.get(name.as_str()).copied() {
+                    Some(id) if matches!(self.symbols.get(id).kind, SymbolKind::Struct(_)) => expr.symbol = Some(id),
+                    _ => self.errors.push(CompileError::name(format!("cannot find struct `{}`", name), span)),
+                }
+            }
+        }
+    }
+
+    fn declare_var(&mut self, name: &str, typ: ValueType, span: Span) -> Option<SymbolId> {
+        self.declare_local(name, SymbolKind::Variable(typ), span)
+    }
+
+    fn declare_local(&mut self, name: &str, kind: SymbolKind, span: Span) -> Option<SymbolId> {
+        if self.items.contains_key(name) {
+            self.errors.push(CompileError::name(format!("`{}` is already defined as a function or struct", name), span));
+            return None;
+        }
+        if self.scopes.last().is_some_and(|scope| scope.contains_key(name)) {
+            self.errors.push(CompileError::name(format!("variable `{}` is already defined in this scope", name), span));
+            return None;
+        }
+        let shadows = self.lookup_var(name);
+        let id = self.symbols.add(Symbol { name: name.to_string(), kind, span, shadows });
+        self.scopes.last_mut().expect("resolver always has a scope").insert(name.to_string(), id);
+        Some(id)
+    }
+
+    fn lookup_var(&self, name: &str) -> Option<SymbolId> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name).copied())
+    }
+}
\ No newline at end of file
@@ -0,0 +1,54 @@
+pub struct SourceMap {
+    source: String,
+    line_starts: Vec<usize>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl SourceMap {
+    pub fn new(source: &str) -> Self {
+        let mut line_starts = vec![0];
+        for (i, c) in source.char_indices() {
+            if c == '\n' {
+                line_starts.push(i + 1);
+            }
+        }
+        Self { source: source.to_string(), line_starts }
+    }
+
+    /// Converts a byte offset into a 1-based line and a 1-based column counted in characters.
+    pub fn location(&self, offset: usize) -> Location {
+        let offset = self.clamp(offset);
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next) => next - 1,
+        };
+        let column = self.source[self.line_starts[line]..offset].chars().count() + 1;
+        Location { line: line + 1, column }
+    }
+
+    /// Formats a byte offset as `file:line:column`, the way compile errors point at code.
+    pub fn describe(&self, file_name: &str, offset: usize) -> String {
+        let location = self.location(offset);
+        format!("{}:{}:{}", file_name, location.line, location.column)
+    }
+
+    /// Returns the text of a 1-based line without its line terminator.
+    pub fn line(&self, line: usize) -> &str {
+        let start = self.line_starts[line - 1];
+        let end = self.line_starts.get(line).copied().unwrap_or(self.source.len());
+        self.source[start..end].trim_end_matches(['\n', '\r'])
+    }
+
+    fn clamp(&self, offset: usize) -> usize {
+        let mut offset = offset.min(self.source.len());
+        while !self.source.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        offset
+    }
+}
\ No newline at end of file
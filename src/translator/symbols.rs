@@ -0,0 +1,78 @@
+use super::ast::TypedArgument;
+use super::builtins::Signature;
+use super::error::Span;
+use super::value::ValueType;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct SymbolId(pub usize);
+
+#[derive(Debug, Clone)]
+pub enum SymbolKind {
+    Variable(ValueType),
+    /// The variable of a `for` loop. Its type is inferred by the type checker from what the loop
+    /// iterates over.
+    LoopVariable,
+    Function(Signature),
+    Struct(Vec<TypedArgument>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub span: Span,
+    /// The variable of an enclosing block that this variable hides, if any.
+    pub shadows: Option<SymbolId>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    symbols: Vec<Symbol>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, symbol: Symbol) -> SymbolId {
+        self.symbols.push(symbol);
+        SymbolId(self.symbols.len() - 1)
+    }
+
+    pub fn get(&self, id: SymbolId) -> &Symbol {
+        &self.symbols[id.0]
+    }
+
+    pub fn variable_type(&self, id: Option<SymbolId>) -> Option<ValueType> {
+        match &self.get(id?).kind {
+            SymbolKind::Variable(typ) => Some(typ.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn signature(&self, id: Option<SymbolId>) -> Option<&Signature> {
+        match &self.get(id?).kind {
+            SymbolKind::Function(signature) => Some(signature),
+            _ => None,
+        }
+    }
+
+    /// Fields of the struct with the given name. Structs are top-level items, so names are unique.
+    pub fn struct_fields(&self, name: &str) -> Option<&[TypedArgument]> {
+        self.symbols.iter().find_map(|symbol| match &symbol.kind {
+            SymbolKind::Struct(fields) if symbol.name == name => Some(fields.as_slice()),
+            _ => None,
+        })
+    }
+
+    pub fn method(&self, owner: &str, method: &str) -> Option<SymbolId> {
+        let key = method_key(owner, method);
+        self.symbols.iter().position(|symbol| symbol.name == key && matches!(symbol.kind, SymbolKind::Function(_))).map(SymbolId)
+    }
+}
+
+/// Methods are items named `Struct.method`, which cannot clash with any identifier.
+pub fn method_key(owner: &str, method: &str) -> String {
+    format!("{}.{}", owner, method)
+}
\ No newline at end of file
@@ -1,28 +1,127 @@
-﻿#[derive(Debug, Copy, Clone)]
+﻿use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ValueType {
     Number,
+    Int(IntType),
     String,
+    Bool,
     Unit,
+    /// A struct defined in the program, by name.
+    Struct(String),
+    /// A growable list of elements, written `[number]`, or with a length a fixed-size array,
+    /// written `[number; 3]`. Both are stored the same way, the checker keeps the length fixed.
+    Array(Box<ValueType>, Option<usize>),
 }
 
-#[derive(Debug, Clone)]
-pub enum Value {
-    Number(f32),
-    String(String),
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IntType {
+    I8,
+    I16,
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+}
+
+pub const TYPE_NAMES: [&str; 13] = [
+    "number", "int", "i8", "i16", "i32", "i64", "u8", "u16", "u32", "u64", "string", "bool", "unit",
+];
+
+impl ValueType {
+    pub fn from_name(name: &str) -> Option<ValueType> {
+        let typ = match name {
+            "number" => ValueType::Number,
+            "int" | "i32" => ValueType::Int(IntType::I32),
+            "i8" => ValueType::Int(IntType::I8),
+            "i16" => ValueType::Int(IntType::I16),
+            "i64" => ValueType::Int(IntType::I64),
+            "u8" => ValueType::Int(IntType::U8),
+            "u16" => ValueType::Int(IntType::U16),
+            "u32" => ValueType::Int(IntType::U32),
+            "u64" => ValueType::Int(IntType::U64),
+            "string" => ValueType::String,
+            "bool" => ValueType::Bool,
+            "unit" => ValueType::Unit,
+            _ => return None,
+        };
+        Some(typ)
+    }
+
+    pub fn is_numeric(&self) -> bool {
+        matches!(self, ValueType::Number | ValueType::Int(_))
+    }
 }
 
-impl Value {
-    pub fn to_string(&self) -> String {
+impl IntType {
+    pub fn is_signed(self) -> bool {
+        matches!(self, IntType::I8 | IntType::I16 | IntType::I32 | IntType::I64)
+    }
+
+    pub fn min(self) -> i128 {
         match self {
-            Value::Number(n) => n.to_string(),
-            Value::String(s) => format!("area_memdup(\"{}\", {})", s, s.len() + 1),
+            IntType::I8 => i8::MIN as i128,
+            IntType::I16 => i16::MIN as i128,
+            IntType::I32 => i32::MIN as i128,
+            IntType::I64 => i64::MIN as i128,
+            IntType::U8 | IntType::U16 | IntType::U32 | IntType::U64 => 0,
         }
     }
 
-    pub fn to_number(&self) -> f32 {
+    pub fn max(self) -> i128 {
         match self {
-            Value::Number(n) => *n,
-            Value::String(_) => panic!("Cannot convert string to number"),
+            IntType::I8 => i8::MAX as i128,
+            IntType::I16 => i16::MAX as i128,
+            IntType::I32 => i32::MAX as i128,
+            IntType::I64 => i64::MAX as i128,
+            IntType::U8 => u8::MAX as i128,
+            IntType::U16 => u16::MAX as i128,
+            IntType::U32 => u32::MAX as i128,
+            IntType::U64 => u64::MAX as i128,
         }
     }
+}
+
+impl fmt::Display for ValueType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ValueType::Number => "number",
+            ValueType::Int(int) => return write!(f, "{}", int),
+            ValueType::String => "string",
+            ValueType::Bool => "bool",
+            ValueType::Unit => "unit",
+            ValueType::Struct(name) => name,
+            ValueType::Array(element, None) => return write!(f, "[{}]", element),
+            ValueType::Array(element, Some(length)) => return write!(f, "[{}; {}]", element, length),
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for IntType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            IntType::I8 => "i8",
+            IntType::I16 => "i16",
+            IntType::I32 => "int",
+            IntType::I64 => "i64",
+            IntType::U8 => "u8",
+            IntType::U16 => "u16",
+            IntType::U32 => "u32",
+            IntType::U64 => "u64",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    Number(f64),
+    /// An integer literal, which is never negative: `-1` is a negation. Its type, and whether it
+    /// fits in it, comes from the checker.
+    Integer(u64),
+    String(String),
+    Bool(bool),
 }
\ No newline at end of file
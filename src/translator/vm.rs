@@ -0,0 +1,310 @@
+//! A stack VM for compiled bytecode.
+//!
+//! It shares its values and the `io` and `math` modules with the interpreter, so both print the
+//! same output for a program. Calls do not recurse in Rust, so a deep recursion only grows the
+//! VM's own stacks, up to the same `MAX_CALL_DEPTH` as the interpreter.
+
+use std::cell::RefCell;
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+
+use super::bytecode::{Constant, Op, Program};
+use super::error::{CompileError, Span};
+use super::interpreter::{
+    arithmetic, call_native, compare, convert, display, element_index, field_mut, output_error, read_input, unchecked,
+    wrap, RuntimeValue, MAX_CALL_DEPTH,
+};
+use super::value::{IntType, ValueType};
+
+/// The elements of an array value.
+type Items = Rc<RefCell<Vec<RuntimeValue>>>;
+
+/// A call in progress: the function, the next instruction and where its locals start on the stack.
+struct CallFrame {
+    function: usize,
+    ip: usize,
+    base: usize,
+    /// Whether the caller expects the final value of the receiver after the result.
+    method: bool,
+}
+
+pub struct Vm<'a> {
+    program: &'a Program,
+    input: &'a mut dyn BufRead,
+    output: &'a mut dyn Write,
+    stack: Vec<RuntimeValue>,
+    frames: Vec<CallFrame>,
+}
+
+impl<'a> Vm<'a> {
+    pub fn new(program: &'a Program, input: &'a mut dyn BufRead, output: &'a mut dyn Write) -> Self {
+        Self { program, input, output, stack: vec![], frames: vec![] }
+    }
+
+    /// Runs the top-level code of the program. Its output is flushed even when it stops with a
+    /// runtime error.
+    pub fn run(&mut self) -> Result<(), CompileError> {
+        let result = self.call(0, 0, false, Span::default()).and_then(|_| self.execute());
+        self.stack.clear();
+        self.frames.clear();
+        self.output.flush().map_err(|err| output_error(err, Span::default()))?;
+        result
+    }
+
+    fn execute(&mut self) -> Result<(), CompileError> {
+        let program = self.program;
+        loop {
+            let frame = self.frames.last_mut().expect("the top-level call returns before the frames run out");
+            let function = &program.functions[frame.function];
+            let (Some(&op), Some(&span)) = (function.code.get(frame.ip), function.spans.get(frame.ip)) else {
+                return Err(malformed(Span::default()));
+            };
+            frame.ip += 1;
+            let base = frame.base;
+            match op {
+                Op::Constant(index) => {
+                    let value = match self.constant(index, span)? {
+                        Constant::Number(n) => RuntimeValue::Number(*n),
+                        Constant::Int(typ, n) => RuntimeValue::Int(*typ, *n),
+                        Constant::String(s) => RuntimeValue::String(s.clone()),
+                        Constant::Bool(b) => RuntimeValue::Bool(*b),
+                        Constant::Names(_) => return Err(malformed(span)),
+                    };
+                    self.stack.push(value);
+                }
+                Op::Unit => self.stack.push(RuntimeValue::Unit),
+                Op::Pop => {
+                    self.pop(span)?;
+                }
+                Op::GetLocal(slot) => {
+                    let value = self.local(base, slot, span)?.clone();
+                    self.stack.push(value);
+                }
+                Op::SetLocal(slot) => {
+                    let value = self.pop(span)?;
+                    *self.local(base, slot, span)? = value;
+                }
+                Op::SetLocalField(slot, path) => {
+                    let value = self.pop(span)?;
+                    let path = self.names(path, span)?;
+                    let local = self.local(base, slot, span)?;
+                    *field_mut(local, path).ok_or_else(|| unchecked(span))? = value;
+                }
+                Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Rem => {
+                    let right = self.pop(span)?;
+                    let left = self.pop(span)?;
+                    let op = match op {
+                        Op::Add => '+',
+                        Op::Sub => '-',
+                        Op::Mul => '*',
+                        Op::Div => '/',
+                        _ => '%',
+                    };
+                    self.stack.push(arithmetic(op, left, right, span)?);
+                }
+                Op::Neg => {
+                    let value = match self.pop(span)? {
+                        RuntimeValue::Number(n) => RuntimeValue::Number(-n),
+                        RuntimeValue::Int(typ, n) => RuntimeValue::Int(typ, wrap(typ, -n)),
+                        _ => return Err(unchecked(span)),
+                    };
+                    self.stack.push(value);
+                }
+                Op::Not => {
+                    let value = self.pop_bool(span)?;
+                    self.stack.push(RuntimeValue::Bool(!value));
+                }
+                Op::Equal | Op::NotEqual | Op::Less | Op::Greater | Op::LessEqual | Op::GreaterEqual => {
+                    let right = self.pop(span)?;
+                    let left = self.pop(span)?;
+                    let op = match op {
+                        Op::Equal => "==",
+                        Op::NotEqual => "!=",
+                        Op::Less => "<",
+                        Op::Greater => ">",
+                        Op::LessEqual => "<=",
+                        _ => ">=",
+                    };
+                    self.stack.push(RuntimeValue::Bool(compare(op, &left, &right, span)?));
+                }
+                Op::ToNumber => {
+                    let value = self.pop(span)?;
+                    self.stack.push(convert(&ValueType::Number, value, span)?);
+                }
+                Op::ToInt(typ) => {
+                    let value = self.pop(span)?;
+                    self.stack.push(convert(&ValueType::Int(typ), value, span)?);
+                }
+                Op::Jump(target) => self.jump(target),
+                Op::JumpIfFalse(target) => {
+                    if !self.pop_bool(span)? {
+                        self.jump(target);
+                    }
+                }
+                Op::Call(function, argc) => self.call(function, argc, false, span)?,
+                Op::CallMethod(function, argc) => self.call(function, argc, true, span)?,
+                Op::CallNative(name, argc) => {
+                    let Constant::String(name) = self.constant(name, span)? else {
+                        return Err(malformed(span));
+                    };
+                    let args = self.pop_many(argc, span)?;
+                    let result = call_native(name, &args, self.output, span)?;
+                    self.stack.push(result);
+                }
+                Op::Input(count) => {
+                    let RuntimeValue::String(fmt) = self.pop(span)? else {
+                        return Err(unchecked(span));
+                    };
+                    let values = self.pop_many(count, span)?;
+                    let values = read_input(self.input, self.output, &fmt, values, span)?;
+                    self.stack.extend(values);
+                }
+                Op::Return => {
+                    let result = self.pop(span)?;
+                    let frame = self.frames.pop().expect("the current call has a frame");
+                    let receiver = frame.method.then(|| self.stack[frame.base].clone());
+                    self.stack.truncate(frame.base);
+                    if self.frames.is_empty() {
+                        return Ok(());
+                    }
+                    self.stack.push(result);
+                    self.stack.extend(receiver);
+                }
+                Op::MakeStruct(names) => {
+                    let Constant::Names(names) = self.constant(names, span)? else {
+                        return Err(malformed(span));
+                    };
+                    let values = self.pop_many(names.len() as u32, span)?;
+                    self.stack.push(RuntimeValue::Struct(names.iter().cloned().zip(values).collect()));
+                }
+                Op::GetField(name) => {
+                    let Constant::String(name) = self.constant(name, span)? else {
+                        return Err(malformed(span));
+                    };
+                    let RuntimeValue::Struct(fields) = self.pop(span)? else {
+                        return Err(unchecked(span));
+                    };
+                    let value = fields.into_iter().find_map(|(field, value)| (field == *name).then_some(value));
+                    self.stack.push(value.ok_or_else(|| unchecked(span))?);
+                }
+                Op::MakeArray(count) => {
+                    let items = self.pop_many(count, span)?;
+                    self.stack.push(RuntimeValue::Array(Rc::new(RefCell::new(items))));
+                }
+                Op::GetIndex => {
+                    let (items, index) = self.pop_element(span)?;
+                    let value = items.borrow()[index].clone();
+                    self.stack.push(value);
+                }
+                Op::SetIndex | Op::SetIndexField(_) => {
+                    let (items, index) = self.pop_element(span)?;
+                    let value = self.pop(span)?;
+                    let path = match op {
+                        Op::SetIndexField(path) => self.names(path, span)?,
+                        _ => &[],
+                    };
+                    let mut items = items.borrow_mut();
+                    *field_mut(&mut items[index], path).ok_or_else(|| unchecked(span))? = value;
+                }
+                Op::Len => {
+                    let items = self.pop_array(span)?;
+                    let len = items.borrow().len();
+                    self.stack.push(RuntimeValue::Int(IntType::I32, len as i128));
+                }
+                Op::Push => {
+                    let value = self.pop(span)?;
+                    self.pop_array(span)?.borrow_mut().push(value);
+                    self.stack.push(RuntimeValue::Unit);
+                }
+                Op::Concat(count) => {
+                    let text: String = self.pop_many(count, span)?.iter().map(display).collect();
+                    self.stack.push(RuntimeValue::String(text));
+                }
+            }
+        }
+    }
+
+    /// Starts a call whose arguments are on top of the stack; they become its first locals.
+    fn call(&mut self, function: u32, argc: u32, method: bool, span: Span) -> Result<(), CompileError> {
+        if self.frames.len() > MAX_CALL_DEPTH {
+            return Err(CompileError::runtime("too many nested calls", span)
+                .with_note(format!("the VM supports at most {} nested calls", MAX_CALL_DEPTH)));
+        }
+        let callee = self.program.functions.get(function as usize).ok_or_else(|| malformed(span))?;
+        let base = self.stack.len().checked_sub(argc as usize).ok_or_else(|| malformed(span))?;
+        if argc != callee.arity || callee.locals < argc || (method && argc == 0) {
+            return Err(malformed(span));
+        }
+        self.stack.resize(self.stack.len() + (callee.locals - argc) as usize, RuntimeValue::Unit);
+        self.frames.push(CallFrame { function: function as usize, ip: 0, base, method });
+        Ok(())
+    }
+
+    fn jump(&mut self, target: u32) {
+        // A target past the end is reported as malformed when the next instruction is fetched.
+        self.frames.last_mut().expect("jumps happen inside a call").ip = target as usize;
+    }
+
+    fn constant(&self, index: u32, span: Span) -> Result<&'a Constant, CompileError> {
+        self.program.constants.get(index as usize).ok_or_else(|| malformed(span))
+    }
+
+    fn names(&self, index: u32, span: Span) -> Result<&'a [String], CompileError> {
+        match self.constant(index, span)? {
+            Constant::Names(names) => Ok(names),
+            _ => Err(malformed(span)),
+        }
+    }
+
+    fn local(&mut self, base: usize, slot: u32, span: Span) -> Result<&mut RuntimeValue, CompileError> {
+        self.stack.get_mut(base + slot as usize).ok_or_else(|| malformed(span))
+    }
+
+    fn pop(&mut self, span: Span) -> Result<RuntimeValue, CompileError> {
+        if self.stack.len() <= self.operands_start(span)? {
+            return Err(malformed(span));
+        }
+        Ok(self.stack.pop().expect("the stack has an operand"))
+    }
+
+    /// Pops `count` values, returning them in the order they were pushed.
+    fn pop_many(&mut self, count: u32, span: Span) -> Result<Vec<RuntimeValue>, CompileError> {
+        let start = self.operands_start(span)?;
+        let len = self.stack.len().checked_sub(count as usize).filter(|len| *len >= start).ok_or_else(|| malformed(span))?;
+        Ok(self.stack.split_off(len))
+    }
+
+    /// Where the operands of the current call start; its locals are below them and are never popped.
+    fn operands_start(&self, span: Span) -> Result<usize, CompileError> {
+        let frame = self.frames.last().ok_or_else(|| malformed(span))?;
+        Ok(frame.base + self.program.functions[frame.function].locals as usize)
+    }
+
+    fn pop_bool(&mut self, span: Span) -> Result<bool, CompileError> {
+        match self.pop(span)? {
+            RuntimeValue::Bool(b) => Ok(b),
+            _ => Err(unchecked(span)),
+        }
+    }
+
+    fn pop_array(&mut self, span: Span) -> Result<Items, CompileError> {
+        match self.pop(span)? {
+            RuntimeValue::Array(items) => Ok(items),
+            _ => Err(unchecked(span)),
+        }
+    }
+
+    /// Pops an index and an array, checking that the index is in bounds.
+    fn pop_element(&mut self, span: Span) -> Result<(Items, usize), CompileError> {
+        let RuntimeValue::Int(_, index) = self.pop(span)? else {
+            return Err(unchecked(span));
+        };
+        let items = self.pop_array(span)?;
+        let index = element_index(index, items.borrow().len(), span)?;
+        Ok((items, index))
+    }
+}
+
+fn malformed(span: Span) -> CompileError {
+    CompileError::runtime("the bytecode is malformed", span)
+}
\ No newline at end of file
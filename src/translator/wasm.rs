@@ -0,0 +1,1395 @@
+//! Generates a WebAssembly text (WAT) module for a checked program.
+//!
+//! The module exports `main`, its memory and the functions of the program, as `lamp.NAME`, and
+//! imports `print` and `println` from the host module `lamp`. They take the address of a NUL-terminated UTF-8 string, which the
+//! module formats itself. `math` functions are imported from the host module `math`.
+//!
+//! Memory is managed in areas like in the C backend: `main`, every function and the bodies of `if`s
+//! and loops run in an area of their own, which frees everything allocated in it when it ends.
+//! Strings and arrays that escape to an outer area are promoted to it. Struct values are addresses
+//! of area memory and are copied whenever they are stored, so they behave like the C structs of
+//! the other backends. Runtime errors, such as an index out of bounds, trap.
+
+use std::collections::{HashMap, HashSet};
+
+use super::ast::{Expr, ExprKind, InterpolationPart, Iterable, Stmt, StmtKind, TypedArgument};
+use super::builtins::{Builtin, FormatArgs, Placeholder};
+use super::error::{CompileError, Span};
+use super::symbols::{SymbolId, SymbolTable};
+use super::value::{IntType, Value, ValueType};
+
+/// Where string constants start. Below them are the heads of the allocation lists of up to 128
+/// nested areas, like the C runtime, and at address 512 the head of the list of freed blocks.
+const DATA_START: u32 = 1024;
+
+/// Functions of the runtime written in WAT, in the order they are emitted, with the functions they
+/// call. Every allocation is a block with its size and the next block of its list in the 8 bytes
+/// before it. A block is on the list of the area it belongs to until that area ends, and then on
+/// the free list, where later allocations of at most its size reuse it.
+const RUNTIME: [(&str, &[&str], &str); 16] = [
+    ("area_start", &[], r#"(func $rt.area_start
+    (if (i32.ge_u (global.get $area_depth) (i32.const 128))
+      (then (unreachable)))
+    (i32.store (i32.shl (global.get $area_depth) (i32.const 2)) (i32.const 0))
+    (global.set $area_depth (i32.add (global.get $area_depth) (i32.const 1))))"#),
+    ("area_end", &[], r#"(func $rt.area_end
+    (local $block i32)
+    (local $rest i32)
+    (if (i32.eqz (global.get $area_depth))
+      (then (unreachable)))
+    (global.set $area_depth (i32.sub (global.get $area_depth) (i32.const 1)))
+    (local.set $block (i32.load (i32.shl (global.get $area_depth) (i32.const 2))))
+    (block $done
+      (loop $next
+        (br_if $done (i32.eqz (local.get $block)))
+        (local.set $rest (i32.load (i32.sub (local.get $block) (i32.const 4))))
+        (i32.store (i32.sub (local.get $block) (i32.const 4)) (i32.load (i32.const 512)))
+        (i32.store (i32.const 512) (local.get $block))
+        (local.set $block (local.get $rest))
+        (br $next))))"#),
+    ("area_alloc_in", &[], r#"(func $rt.area_alloc_in (param $area i32) (param $size i32) (result i32)
+    (local $link i32)
+    (local $block i32)
+    (if (i32.ge_u (local.get $area) (global.get $area_depth))
+      (then (unreachable)))
+    (local.set $size (i32.and (i32.add (local.get $size) (i32.const 7)) (i32.const -8)))
+    (local.set $link (i32.const 512))
+    (block $found
+      (loop $next
+        (local.set $block (i32.load (local.get $link)))
+        (br_if $found (i32.eqz (local.get $block)))
+        (if (i32.ge_u (i32.load (i32.sub (local.get $block) (i32.const 8))) (local.get $size))
+          (then
+            (i32.store (local.get $link) (i32.load (i32.sub (local.get $block) (i32.const 4))))
+            (br $found)))
+        (local.set $link (i32.sub (local.get $block) (i32.const 4)))
+        (br $next)))
+    (if (i32.eqz (local.get $block))
+      (then
+        (local.set $block (i32.add (global.get $heap) (i32.const 8)))
+        (global.set $heap (i32.add (local.get $block) (local.get $size)))
+        (if (i32.gt_u (global.get $heap) (i32.shl (memory.size) (i32.const 16)))
+          (then
+            (if (i32.eq (memory.grow (i32.shr_u (i32.sub (i32.add (global.get $heap) (i32.const 65535)) (i32.shl (memory.size) (i32.const 16))) (i32.const 16))) (i32.const -1))
+              (then (unreachable)))))
+        (i32.store (i32.sub (local.get $block) (i32.const 8)) (local.get $size))))
+    (i32.store (i32.sub (local.get $block) (i32.const 4)) (i32.load (i32.shl (local.get $area) (i32.const 2))))
+    (i32.store (i32.shl (local.get $area) (i32.const 2)) (local.get $block))
+    (local.get $block))"#),
+    ("area_alloc", &["area_alloc_in"], r#"(func $rt.area_alloc (param $size i32) (result i32)
+    (call $rt.area_alloc_in (i32.sub (global.get $area_depth) (i32.const 1)) (local.get $size)))"#),
+    ("area_memdup", &["area_alloc"], r#"(func $rt.area_memdup (param $src i32) (param $size i32) (result i32)
+    (local $block i32)
+    (local.set $block (call $rt.area_alloc (local.get $size)))
+    (memory.copy (local.get $block) (local.get $src) (local.get $size))
+    (local.get $block))"#),
+    ("area_memdup_in", &["area_alloc_in"], r#"(func $rt.area_memdup_in (param $area i32) (param $src i32) (param $size i32) (result i32)
+    (local $block i32)
+    (local.set $block (call $rt.area_alloc_in (local.get $area) (local.get $size)))
+    (memory.copy (local.get $block) (local.get $src) (local.get $size))
+    (local.get $block))"#),
+    ("area_strdup_in", &["area_memdup_in", "strlen"], r#"(func $rt.area_strdup_in (param $area i32) (param $s i32) (result i32)
+    (call $rt.area_memdup_in (local.get $area) (local.get $s) (i32.add (call $rt.strlen (local.get $s)) (i32.const 1))))"#),
+    ("area_move_in", &[], r#"(func $rt.area_move_in (param $area i32) (param $block i32)
+    (local $i i32)
+    (local $link i32)
+    (local.set $i (global.get $area_depth))
+    (block $done
+      (loop $areas
+        (local.set $i (i32.sub (local.get $i) (i32.const 1)))
+        (br_if $done (i32.le_s (local.get $i) (local.get $area)))
+        (local.set $link (i32.shl (local.get $i) (i32.const 2)))
+        (block $next_area
+          (loop $blocks
+            (br_if $next_area (i32.eqz (i32.load (local.get $link))))
+            (if (i32.eq (i32.load (local.get $link)) (local.get $block))
+              (then
+                (i32.store (local.get $link) (i32.load (i32.sub (local.get $block) (i32.const 4))))
+                (i32.store (i32.sub (local.get $block) (i32.const 4)) (i32.load (i32.shl (local.get $area) (i32.const 2))))
+                (i32.store (i32.shl (local.get $area) (i32.const 2)) (local.get $block))
+                (return)))
+            (local.set $link (i32.sub (i32.load (local.get $link)) (i32.const 4)))
+            (br $blocks)))
+        (br $areas))))"#),
+    ("array_new", &["area_alloc"], r#"(func $rt.array_new (param $elem_size i32) (param $len i32) (result i32)
+    (local $array i32)
+    (local.set $array (call $rt.area_alloc (i32.const 20)))
+    (i32.store (local.get $array) (call $rt.area_alloc (i32.mul (local.get $elem_size) (local.get $len))))
+    (i32.store offset=4 (local.get $array) (local.get $len))
+    (i32.store offset=8 (local.get $array) (local.get $len))
+    (i32.store offset=12 (local.get $array) (local.get $elem_size))
+    (i32.store offset=16 (local.get $array) (i32.sub (global.get $area_depth) (i32.const 1)))
+    (local.get $array))"#),
+    ("array_at", &[], r#"(func $rt.array_at (param $array i32) (param $index i64) (result i32)
+    (if (i64.ge_u (local.get $index) (i64.extend_i32_u (i32.load offset=4 (local.get $array))))
+      (then (unreachable)))
+    (i32.add (i32.load (local.get $array)) (i32.mul (i32.wrap_i64 (local.get $index)) (i32.load offset=12 (local.get $array)))))"#),
+    ("array_push", &["area_alloc_in"], r#"(func $rt.array_push (param $array i32) (result i32)
+    (local $len i32)
+    (local $size i32)
+    (local $data i32)
+    (local.set $len (i32.load offset=4 (local.get $array)))
+    (local.set $size (i32.load offset=12 (local.get $array)))
+    (if (i32.eq (local.get $len) (i32.load offset=8 (local.get $array)))
+      (then
+        (local.set $data (call $rt.area_alloc_in (i32.load offset=16 (local.get $array)) (i32.mul (local.get $size) (i32.shl (i32.add (local.get $len) (i32.const 1)) (i32.const 1)))))
+        (memory.copy (local.get $data) (i32.load (local.get $array)) (i32.mul (local.get $len) (local.get $size)))
+        (i32.store (local.get $array) (local.get $data))
+        (i32.store offset=8 (local.get $array) (i32.shl (i32.add (local.get $len) (i32.const 1)) (i32.const 1)))))
+    (i32.store offset=4 (local.get $array) (i32.add (local.get $len) (i32.const 1)))
+    (i32.add (i32.load (local.get $array)) (i32.mul (local.get $len) (local.get $size))))"#),
+    ("array_move_in", &["area_move_in"], r#"(func $rt.array_move_in (param $area i32) (param $array i32) (result i32)
+    (if (i32.le_s (i32.load offset=16 (local.get $array)) (local.get $area))
+      (then (return (i32.const 0))))
+    (call $rt.area_move_in (local.get $area) (local.get $array))
+    (call $rt.area_move_in (local.get $area) (i32.load (local.get $array)))
+    (i32.store offset=16 (local.get $array) (local.get $area))
+    (i32.const 1))"#),
+    ("strlen", &[], r#"(func $rt.strlen (param $s i32) (result i32)
+    (local $end i32)
+    (local.set $end (local.get $s))
+    (block $done
+      (loop $next
+        (br_if $done (i32.eqz (i32.load8_u (local.get $end))))
+        (local.set $end (i32.add (local.get $end) (i32.const 1)))
+        (br $next)))
+    (i32.sub (local.get $end) (local.get $s)))"#),
+    ("string_concat", &["area_alloc", "strlen"], r#"(func $rt.string_concat (param $a i32) (param $b i32) (result i32)
+    (local $a_len i32)
+    (local $b_len i32)
+    (local $s i32)
+    (local.set $a_len (call $rt.strlen (local.get $a)))
+    (local.set $b_len (call $rt.strlen (local.get $b)))
+    (local.set $s (call $rt.area_alloc (i32.add (i32.add (local.get $a_len) (local.get $b_len)) (i32.const 1))))
+    (memory.copy (local.get $s) (local.get $a) (local.get $a_len))
+    (memory.copy (i32.add (local.get $s) (local.get $a_len)) (local.get $b) (i32.add (local.get $b_len) (i32.const 1)))
+    (local.get $s))"#),
+    ("format_int", &["area_alloc"], r#"(func $rt.format_int (param $value i64) (param $unsigned i32) (result i32)
+    (local $at i32)
+    (local $negative i32)
+    (local $magnitude i64)
+    (local.set $at (i32.add (call $rt.area_alloc (i32.const 22)) (i32.const 21)))
+    (i32.store8 (local.get $at) (i32.const 0))
+    (local.set $negative (i32.and (i32.eqz (local.get $unsigned)) (i64.lt_s (local.get $value) (i64.const 0))))
+    (local.set $magnitude (select (i64.sub (i64.const 0) (local.get $value)) (local.get $value) (local.get $negative)))
+    (loop $digit
+      (local.set $at (i32.sub (local.get $at) (i32.const 1)))
+      (i32.store8 (local.get $at) (i32.add (i32.const 48) (i32.wrap_i64 (i64.rem_u (local.get $magnitude) (i64.const 10)))))
+      (local.set $magnitude (i64.div_u (local.get $magnitude) (i64.const 10)))
+      (br_if $digit (i64.ne (local.get $magnitude) (i64.const 0))))
+    (if (local.get $negative)
+      (then
+        (local.set $at (i32.sub (local.get $at) (i32.const 1)))
+        (i32.store8 (local.get $at) (i32.const 45))))
+    (local.get $at))"#),
+    ("format_number", &["area_alloc"], r#"(func $rt.format_number (param $x f64) (result i32)
+    (local $buffer i32)
+    (local $at i32)
+    (local $digits i32)
+    (local $count i32)
+    (local $i i32)
+    (local $exponent i32)
+    (local $scale f64)
+    (local $mantissa i64)
+    ;; Formats like `%g` in C: six significant digits without trailing zeros, in scientific
+    ;; notation for exponents below -4 or above 5.
+    (local.set $buffer (call $rt.area_alloc (i32.const 48)))
+    (local.set $at (local.get $buffer))
+    (local.set $digits (i32.add (local.get $buffer) (i32.const 32)))
+    (if (f64.ne (local.get $x) (local.get $x))
+      (then
+        (i32.store (local.get $buffer) (i32.const 0x006E616E))
+        (return (local.get $buffer))))
+    (if (i64.lt_s (i64.reinterpret_f64 (local.get $x)) (i64.const 0))
+      (then
+        (i32.store8 (local.get $at) (i32.const 45))
+        (local.set $at (i32.add (local.get $at) (i32.const 1)))
+        (local.set $x (f64.neg (local.get $x)))))
+    (if (f64.eq (local.get $x) (f64.const inf))
+      (then
+        (i32.store (local.get $at) (i32.const 0x00666E69))
+        (return (local.get $buffer))))
+    (if (f64.eq (local.get $x) (f64.const 0))
+      (then
+        (i32.store16 (local.get $at) (i32.const 0x0030))
+        (return (local.get $buffer))))
+    (local.set $scale (f64.const 1))
+    (block $found
+      (loop $up
+        (br_if $found (f64.lt (local.get $x) (f64.mul (local.get $scale) (f64.const 10))))
+        (local.set $scale (f64.mul (local.get $scale) (f64.const 10)))
+        (local.set $exponent (i32.add (local.get $exponent) (i32.const 1)))
+        (br $up)))
+    (block $found
+      (loop $down
+        (br_if $found (f64.ge (local.get $x) (local.get $scale)))
+        (local.set $scale (f64.div (local.get $scale) (f64.const 10)))
+        (local.set $exponent (i32.sub (local.get $exponent) (i32.const 1)))
+        (br $down)))
+    (local.set $mantissa (i64.trunc_sat_f64_u (f64.nearest (f64.mul (f64.div (local.get $x) (local.get $scale)) (f64.const 100000)))))
+    (if (i64.ge_u (local.get $mantissa) (i64.const 1000000))
+      (then
+        (local.set $mantissa (i64.div_u (local.get $mantissa) (i64.const 10)))
+        (local.set $exponent (i32.add (local.get $exponent) (i32.const 1)))))
+    (local.set $count (i32.const 6))
+    (block $done
+      (loop $trim
+        (br_if $done (i64.ne (i64.rem_u (local.get $mantissa) (i64.const 10)) (i64.const 0)))
+        (local.set $mantissa (i64.div_u (local.get $mantissa) (i64.const 10)))
+        (local.set $count (i32.sub (local.get $count) (i32.const 1)))
+        (br $trim)))
+    ;; The digits are followed by zeros, for numbers like 100000 that have fewer digits than places.
+    (memory.fill (local.get $digits) (i32.const 48) (i32.const 12))
+    (local.set $i (local.get $count))
+    (loop $digit
+      (local.set $i (i32.sub (local.get $i) (i32.const 1)))
+      (i32.store8 (i32.add (local.get $digits) (local.get $i)) (i32.add (i32.const 48) (i32.wrap_i64 (i64.rem_u (local.get $mantissa) (i64.const 10)))))
+      (local.set $mantissa (i64.div_u (local.get $mantissa) (i64.const 10)))
+      (br_if $digit (local.get $i)))
+    (if (i32.or (i32.lt_s (local.get $exponent) (i32.const -4)) (i32.ge_s (local.get $exponent) (i32.const 6)))
+      (then
+        (i32.store8 (local.get $at) (i32.load8_u (local.get $digits)))
+        (local.set $at (i32.add (local.get $at) (i32.const 1)))
+        (if (i32.gt_u (local.get $count) (i32.const 1))
+          (then
+            (i32.store8 (local.get $at) (i32.const 46))
+            (memory.copy (i32.add (local.get $at) (i32.const 1)) (i32.add (local.get $digits) (i32.const 1)) (i32.sub (local.get $count) (i32.const 1)))
+            (local.set $at (i32.add (local.get $at) (local.get $count)))))
+        (i32.store8 (local.get $at) (i32.const 101))
+        (i32.store8 offset=1 (local.get $at) (select (i32.const 45) (i32.const 43) (i32.lt_s (local.get $exponent) (i32.const 0))))
+        (local.set $exponent (select (i32.sub (i32.const 0) (local.get $exponent)) (local.get $exponent) (i32.lt_s (local.get $exponent) (i32.const 0))))
+        (local.set $at (i32.add (local.get $at) (i32.const 2)))
+        (if (i32.ge_u (local.get $exponent) (i32.const 100))
+          (then
+            (i32.store8 (local.get $at) (i32.add (i32.const 48) (i32.div_u (local.get $exponent) (i32.const 100))))
+            (local.set $at (i32.add (local.get $at) (i32.const 1)))))
+        (i32.store8 (local.get $at) (i32.add (i32.const 48) (i32.rem_u (i32.div_u (local.get $exponent) (i32.const 10)) (i32.const 10))))
+        (i32.store8 offset=1 (local.get $at) (i32.add (i32.const 48) (i32.rem_u (local.get $exponent) (i32.const 10))))
+        (i32.store8 offset=2 (local.get $at) (i32.const 0))
+        (return (local.get $buffer))))
+    (if (i32.lt_s (local.get $exponent) (i32.const 0))
+      (then
+        (i32.store16 (local.get $at) (i32.const 0x2E30))
+        (local.set $at (i32.add (local.get $at) (i32.const 2)))
+        (memory.fill (local.get $at) (i32.const 48) (i32.sub (i32.const -1) (local.get $exponent)))
+        (local.set $at (i32.add (local.get $at) (i32.sub (i32.const -1) (local.get $exponent))))
+        (memory.copy (local.get $at) (local.get $digits) (local.get $count))
+        (i32.store8 (i32.add (local.get $at) (local.get $count)) (i32.const 0))
+        (return (local.get $buffer))))
+    (memory.copy (local.get $at) (local.get $digits) (i32.add (local.get $exponent) (i32.const 1)))
+    (local.set $at (i32.add (local.get $at) (i32.add (local.get $exponent) (i32.const 1))))
+    (if (i32.gt_s (local.get $count) (i32.add (local.get $exponent) (i32.const 1)))
+      (then
+        (i32.store8 (local.get $at) (i32.const 46))
+        (memory.copy (i32.add (local.get $at) (i32.const 1)) (i32.add (local.get $digits) (i32.add (local.get $exponent) (i32.const 1))) (i32.sub (local.get $count) (i32.add (local.get $exponent) (i32.const 1))))
+        (local.set $at (i32.add (local.get $at) (i32.sub (local.get $count) (local.get $exponent))))))
+    (i32.store8 (local.get $at) (i32.const 0))
+    (local.get $buffer))"#),
+];
+
+pub struct WasmGenerator {
+    input: Stmt,
+    symbols: SymbolTable,
+    /// The functions and methods the program defines; any other call goes to the host.
+    functions: HashSet<SymbolId>,
+    /// The string constants and their addresses.
+    strings: Vec<(String, u32)>,
+    data_end: u32,
+    /// `import` lines of the host functions the program calls, by function name.
+    imports: Vec<(String, String)>,
+    /// The runtime functions the program calls.
+    runtime: HashSet<&'static str>,
+    /// The function being generated.
+    locals: Vec<String>,
+    lines: Vec<String>,
+    depth: usize,
+    /// Counter for the temporaries and labels of the current function.
+    next_id: usize,
+    /// The WAT local of each variable; struct variables hold the address of their value.
+    names: HashMap<SymbolId, String>,
+    /// The loops around the current statement, innermost last.
+    loops: Vec<LoopLabels>,
+    /// The label of the loop about to be generated.
+    label: Option<String>,
+    /// Number of areas the generated code has opened around the current statement.
+    areas: usize,
+    /// The value of `areas` where each variable was declared, so values assigned to it from a
+    /// nested block can be promoted to its area.
+    var_areas: HashMap<SymbolId, usize>,
+    /// The receiver of the method being generated, whose area the caller passes in `$self_area`.
+    receiver: Option<SymbolId>,
+}
+
+/// Where `break` and `continue` branch to in a loop being generated.
+struct LoopLabels {
+    label: Option<String>,
+    break_to: String,
+    continue_to: String,
+    /// The value of `areas` outside the loop body.
+    areas: usize,
+}
+
+impl WasmGenerator {
+    pub fn new(input: Stmt, symbols: SymbolTable) -> Self {
+        Self {
+            input,
+            symbols,
+            functions: HashSet::new(),
+            strings: vec![],
+            data_end: DATA_START,
+            imports: vec![],
+            runtime: HashSet::new(),
+            locals: vec![],
+            lines: vec![],
+            depth: 0,
+            next_id: 0,
+            names: HashMap::new(),
+            loops: vec![],
+            label: None,
+            areas: 0,
+            var_areas: HashMap::new(),
+            receiver: None,
+        }
+    }
+
+    pub fn generate(&mut self) -> Result<String, CompileError> {
+        let StmtKind::Block(stmts) = self.input.kind.clone() else {
+            return Err(CompileError::codegen("the program is not a block", self.input.span));
+        };
+        for stmt in &stmts {
+            match &stmt.kind {
+                StmtKind::FunctionDef(..) => self.functions.extend(stmt.symbol),
+                StmtKind::Impl(_, methods) => self.functions.extend(methods.iter().filter_map(|method| method.symbol)),
+                _ => {}
+            }
+        }
+
+        let mut functions: Vec<String> = vec![];
+        for stmt in &stmts {
+            if let StmtKind::Struct(name, fields) = &stmt.kind && self.needs_promotion(&ValueType::Struct(name.clone())) {
+                functions.push(self.promote_function(name, fields, stmt.span)?);
+            }
+        }
+        for stmt in &stmts {
+            match &stmt.kind {
+                StmtKind::FunctionDef(name, args, body, return_type) => {
+                    functions.push(self.function(&Self::function_name(name), true, args, body, return_type, false)?);
+                }
+                StmtKind::Impl(owner, methods) => {
+                    for method in methods {
+                        if let StmtKind::FunctionDef(name, args, body, return_type) = &method.kind {
+                            functions.push(self.function(&Self::method_name(owner, name), false, args, body, return_type, true)?);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        functions.push(self.main(&stmts)?);
+
+        let mut module = String::from("(module\n");
+        for (_, import) in &self.imports {
+            module += &format!("  {}\n", import);
+        }
+        module += "  (memory (export \"memory\") 1)\n";
+        module += &format!("  (global $heap (mut i32) (i32.const {}))\n", self.data_end.next_multiple_of(8));
+        module += "  (global $area_depth (mut i32) (i32.const 0))\n";
+        for (s, address) in &self.strings {
+            module += &format!("  (data (i32.const {}) \"{}\\00\")\n", address, Self::escape(s));
+        }
+        let mut used: Vec<&str> = self.runtime.iter().copied().collect();
+        while let Some(name) = used.pop() {
+            for (function, calls, _) in RUNTIME {
+                if function == name {
+                    used.extend(calls.iter().copied().filter(|call| self.runtime.insert(call)));
+                }
+            }
+        }
+        for (name, _, code) in RUNTIME {
+            if self.runtime.contains(name) {
+                module += &format!("  {}\n", code);
+            }
+        }
+        for function in functions {
+            module += &function;
+        }
+        module.pop();
+        module += ")\n";
+        Ok(module)
+    }
+
+    /// Generates a function or method, whose body runs in an area of its own like in C. Methods
+    /// take the address of their receiver, so assignments to fields of `self` change the caller's
+    /// value, and the area the receiver belongs to, for values they store into it.
+    fn function(&mut self, name: &str, export: bool, args: &[TypedArgument], body: &Stmt, return_type: &ValueType, is_method: bool) -> Result<String, CompileError> {
+        self.start_function();
+        self.areas = 1;
+        self.receiver = if is_method { args.first().and_then(|arg| arg.symbol) } else { None };
+        self.runtime.extend(["area_start", "area_end"]);
+        self.emit("(call $rt.area_start)");
+        let mut header = format!("(func ${}", name);
+        if export {
+            header += &format!(" (export \"{}\")", name);
+        }
+        for (i, arg) in args.iter().enumerate() {
+            let Some(id) = arg.symbol else {
+                return Err(CompileError::codegen(format!("parameter `{}` without a symbol", arg.name), body.span));
+            };
+            let local = format!("${}", arg.name);
+            header += &format!(" (param {} {})", local, if is_method && i == 0 { "i32" } else { Self::wasm_type(&arg.typ) });
+            if is_method && i == 0 {
+                header += " (param $self_area i32)";
+            }
+            // A struct argument is the caller's value, so the function works on a copy.
+            if !(is_method && i == 0) && let ValueType::Struct(_) = arg.typ {
+                let size = self.size_of(&arg.typ, body.span)?;
+                self.runtime.insert("area_memdup");
+                self.emit(format!("(local.set {} (call $rt.area_memdup (local.get {}) (i32.const {})))", local, local, size));
+            }
+            self.names.insert(id, local);
+        }
+        if *return_type != ValueType::Unit {
+            header += &format!(" (result {})", Self::wasm_type(return_type));
+        }
+        self.statement(body)?;
+        if *return_type == ValueType::Unit {
+            self.emit("(call $rt.area_end)");
+        } else {
+            self.emit("(unreachable)");
+        }
+        Ok(self.finish_function(header))
+    }
+
+    fn main(&mut self, stmts: &[Stmt]) -> Result<String, CompileError> {
+        self.start_function();
+        self.areas = 1;
+        self.receiver = None;
+        self.runtime.extend(["area_start", "area_end"]);
+        self.emit("(call $rt.area_start)");
+        for stmt in stmts {
+            self.statement(stmt)?;
+        }
+        self.emit("(call $rt.area_end)");
+        Ok(self.finish_function("(func $main (export \"main\")".to_string()))
+    }
+
+    /// Generates `$promote.Name`, which promotes what the fields of the struct at `$value` point
+    /// to into the area `$area`, like `lamp_promote_Name` in C.
+    fn promote_function(&mut self, name: &str, fields: &[TypedArgument], span: Span) -> Result<String, CompileError> {
+        self.start_function();
+        let (offsets, _) = self.layout(fields, span)?;
+        for (field, offset) in fields.iter().zip(offsets) {
+            let code = self.promotion_at(&field.typ, &format!("(i32.add (local.get $value) (i32.const {}))", offset), "$area", span)?;
+            if !code.is_empty() {
+                self.emit(code);
+            }
+        }
+        Ok(self.finish_function(format!("(func $promote.{} (param $value i32) (param $area i32)", name)))
+    }
+
+    /// Whether a value of this type points into an area, so it has to be promoted to outlive it.
+    fn needs_promotion(&self, typ: &ValueType) -> bool {
+        match typ {
+            ValueType::String | ValueType::Array(_, _) => true,
+            // Structs hold each other by value without cycles, which the type checker ensures.
+            ValueType::Struct(name) => self.symbols.struct_fields(name)
+                .is_some_and(|fields| fields.iter().any(|field| self.needs_promotion(&field.typ))),
+            _ => false,
+        }
+    }
+
+    /// Instructions that make the value in `local`, and everything it points to, live in the area
+    /// whose index is in the local `area`: strings are copied there and arrays are moved. A struct
+    /// value is its address, so its fields are promoted where it is.
+    fn promotion(&mut self, local: &str, typ: &ValueType, area: &str, span: Span) -> Result<String, CompileError> {
+        Ok(match typ {
+            ValueType::String => {
+                self.runtime.insert("area_strdup_in");
+                format!("(local.set {} (call $rt.area_strdup_in (local.get {}) (local.get {})))", local, area, local)
+            }
+            // Arrays are shared, so an array is moved to the area rather than copied. Its elements
+            // only need promoting if it moved.
+            ValueType::Array(element, _) => {
+                self.runtime.insert("array_move_in");
+                let move_in = format!("(call $rt.array_move_in (local.get {}) (local.get {}))", area, local);
+                if !self.needs_promotion(element) {
+                    return Ok(format!("(drop {})", move_in));
+                }
+                let index = self.temporary("i32");
+                let address = format!("(i32.add (i32.load (local.get {})) (i32.mul (local.get {}) (i32.const {})))", local, index, self.size_of(element, span)?);
+                let inner = self.promotion_at(element, &address, area, span)?;
+                let n = self.next();
+                let (end, next) = (format!("$promote.end.{}", n), format!("$promote.next.{}", n));
+                format!("(if {} (then (local.set {} (i32.const 0)) (block {} (loop {} (br_if {} (i32.ge_s (local.get {}) (i32.load offset=4 (local.get {})))) {} (local.set {} (i32.add (local.get {}) (i32.const 1))) (br {})))))",
+                        move_in, index, end, next, end, index, local, inner, index, index, next)
+            }
+            ValueType::Struct(name) if self.needs_promotion(typ) => format!("(call $promote.{} (local.get {}) (local.get {}))", name, local, area),
+            _ => String::new(),
+        })
+    }
+
+    /// Like `promotion`, for a value stored at `address`, such as a field or an array element.
+    fn promotion_at(&mut self, typ: &ValueType, address: &str, area: &str, span: Span) -> Result<String, CompileError> {
+        Ok(match typ {
+            ValueType::Struct(name) if self.needs_promotion(typ) => format!("(call $promote.{} {} (local.get {}))", name, address, area),
+            ValueType::String | ValueType::Array(_, _) => {
+                let item = self.temporary("i32");
+                let promotion = self.promotion(&item, typ, area, span)?;
+                format!("(local.set {} (i32.load {})) {} (i32.store {} (local.get {}))", item, address, promotion, address, item)
+            }
+            _ => String::new(),
+        })
+    }
+
+    fn start_function(&mut self) {
+        self.locals.clear();
+        self.lines.clear();
+        self.depth = 2;
+        self.next_id = 0;
+        self.names.clear();
+    }
+
+    fn finish_function(&mut self, header: String) -> String {
+        let mut code = format!("  {}\n", header);
+        for line in self.locals.iter().chain(&self.lines) {
+            code += &format!("{}\n", line);
+        }
+        code.pop();
+        code += ")\n";
+        code
+    }
+
+    fn statement(&mut self, stmt: &Stmt) -> Result<(), CompileError> {
+        match &stmt.kind {
+            StmtKind::VarDef(name, value, typ) => {
+                let Some(id) = stmt.symbol else {
+                    return Err(CompileError::codegen(format!("variable `{}` without a symbol", name), stmt.span));
+                };
+                let mut code = self.expression(value)?;
+                // A new struct is not shared with anything else, every other one is copied.
+                if matches!(typ, ValueType::Struct(_)) && !matches!(value.kind, ExprKind::New(..)) {
+                    self.runtime.insert("area_memdup");
+                    code = format!("(call $rt.area_memdup {} (i32.const {}))", code, self.size_of(typ, stmt.span)?);
+                }
+                let local = self.variable(name, id, typ);
+                self.var_areas.insert(id, self.areas);
+                self.emit(format!("(local.set {} {})", local, code));
+            }
+            StmtKind::Assign(target, value) => {
+                let typ = self.type_of(value)?;
+                let mut target = (**target).clone();
+                let mut inits: Vec<String> = vec![];
+                let area = if self.needs_promotion(&typ) { self.place_area(&mut target, &mut inits)? } else { None };
+                let code = self.expression(value)?;
+                match area {
+                    // The value escapes to a place in an outer area, so it is promoted there.
+                    Some(area) => {
+                        let (value_local, area_local) = (self.temporary(Self::wasm_type(&typ)), self.temporary("i32"));
+                        self.emit(format!("(local.set {} {})", value_local, code));
+                        for init in inits {
+                            self.emit(init);
+                        }
+                        self.emit(format!("(local.set {} {})", area_local, area));
+                        let promotion = self.promotion(&value_local, &typ, &area_local, stmt.span)?;
+                        self.emit(promotion);
+                        let code = self.store(&target, &typ, format!("(local.get {})", value_local))?;
+                        self.emit(code);
+                    }
+                    None => {
+                        let code = self.store(&target, &typ, code)?;
+                        self.emit(code);
+                    }
+                }
+            }
+            StmtKind::If(condition, body, else_body) => {
+                let condition = self.expression(condition)?;
+                self.open(format!("(if {}", condition));
+                self.open("(then");
+                self.area(body)?;
+                self.close();
+                if let Some(else_body) = else_body.as_ref() {
+                    self.open("(else");
+                    self.area(else_body)?;
+                    self.close();
+                }
+                self.close();
+            }
+            StmtKind::Block(stmts) => {
+                for stmt in stmts {
+                    self.statement(stmt)?;
+                }
+            }
+            StmtKind::While(condition, body) => {
+                let id = self.next();
+                let (check, end) = (format!("$while.cond.{}", id), format!("$while.end.{}", id));
+                self.open(format!("(block {}", end));
+                self.open(format!("(loop {}", check));
+                let condition = self.expression(condition)?;
+                self.emit(format!("(br_if {} (i32.eqz {}))", end, condition));
+                self.loop_body(body, &check, &end)?;
+                self.emit(format!("(br {})", check));
+                self.close();
+                self.close();
+            }
+            StmtKind::For(name, iterable, body) => self.for_loop(name, stmt.symbol, iterable, body, stmt.span)?,
+            StmtKind::Labeled(label, body) => {
+                self.label = Some(label.clone());
+                self.statement(body)?;
+            }
+            StmtKind::Break(label) => self.jump(label, true, stmt.span)?,
+            StmtKind::Continue(label) => self.jump(label, false, stmt.span)?,
+            StmtKind::Function(expr) => {
+                let code = self.expression(expr)?;
+                if self.type_of(expr)? == ValueType::Unit {
+                    self.emit(code);
+                } else {
+                    self.emit(format!("(drop {})", code));
+                }
+            }
+            StmtKind::Return(None) => {
+                self.end_areas(self.areas);
+                self.emit("(return)");
+            }
+            StmtKind::Return(Some(value)) => {
+                let typ = self.type_of(value)?;
+                let code = self.expression(value)?;
+                if typ == ValueType::Unit {
+                    self.emit(code);
+                    self.end_areas(self.areas);
+                    self.emit("(return)");
+                    return Ok(());
+                }
+                // The value is promoted to the caller's area before the areas of the function are
+                // closed. A struct value is the address of memory in them, so it is copied first.
+                let local = self.temporary(Self::wasm_type(&typ));
+                self.emit(format!("(local.set {} {})", local, code));
+                if self.needs_promotion(&typ) || matches!(typ, ValueType::Struct(_)) {
+                    let area = self.temporary("i32");
+                    self.emit(format!("(local.set {} {})", area, Self::outer_area(self.areas)));
+                    if matches!(typ, ValueType::Struct(_)) {
+                        self.runtime.insert("area_memdup_in");
+                        let size = self.size_of(&typ, stmt.span)?;
+                        self.emit(format!("(local.set {} (call $rt.area_memdup_in (local.get {}) (local.get {}) (i32.const {})))", local, area, local, size));
+                    }
+                    let promotion = self.promotion(&local, &typ, &area, stmt.span)?;
+                    if !promotion.is_empty() {
+                        self.emit(promotion);
+                    }
+                }
+                self.end_areas(self.areas);
+                self.emit(format!("(return (local.get {}))", local));
+            }
+            StmtKind::FunctionDef(..) | StmtKind::Use(_) | StmtKind::Struct(..) | StmtKind::Impl(..) => {}
+        }
+        Ok(())
+    }
+
+    /// Generates a loop body, which runs in an area of its own.
+    fn loop_body(&mut self, body: &Stmt, continue_to: &str, break_to: &str) -> Result<(), CompileError> {
+        let label = self.label.take();
+        self.loops.push(LoopLabels { label, break_to: break_to.to_string(), continue_to: continue_to.to_string(), areas: self.areas });
+        let result = self.area(body);
+        self.loops.pop();
+        result
+    }
+
+    /// Generates a statement or block that runs in an area of its own.
+    fn area(&mut self, body: &Stmt) -> Result<(), CompileError> {
+        self.emit("(call $rt.area_start)");
+        self.areas += 1;
+        let result = self.statement(body);
+        self.areas -= 1;
+        self.emit("(call $rt.area_end)");
+        result
+    }
+
+    /// Closes the innermost `count` areas, before a jump skips the `area_end` calls at the end of
+    /// their blocks.
+    fn end_areas(&mut self, count: usize) {
+        for _ in 0..count {
+            self.emit("(call $rt.area_end)");
+        }
+    }
+
+    /// The index of the area `levels` areas out from the current one.
+    fn outer_area(levels: usize) -> String {
+        format!("(i32.sub (global.get $area_depth) (i32.const {}))", levels + 1)
+    }
+
+    fn jump(&mut self, label: &Option<String>, is_break: bool, span: Span) -> Result<(), CompileError> {
+        let target = match label {
+            Some(label) => self.loops.iter().rev().find(|labels| labels.label.as_ref() == Some(label)),
+            None => self.loops.last(),
+        };
+        let Some(target) = target else {
+            let keyword = if is_break { "break" } else { "continue" };
+            return Err(CompileError::codegen(format!("`{}` outside of a loop", keyword), span));
+        };
+        // Every area opened inside the target loop, including the one of its body, is closed first.
+        let (count, branch) = (self.areas - target.areas, format!("(br {})", if is_break { &target.break_to } else { &target.continue_to }));
+        self.end_areas(count);
+        self.emit(branch);
+        Ok(())
+    }
+
+    /// The index of the area that a place belongs to, if it may be an outer one: the area its
+    /// variable was declared in, the area of the array holding it, or the area the caller passed
+    /// for `self`. `None` means the current area.
+    ///
+    /// The array holding an element is evaluated once, into a temporary that `place` is changed to
+    /// read. The instructions in `inits` set the temporaries and must run before the place is used.
+    fn place_area(&mut self, place: &mut Expr, inits: &mut Vec<String>) -> Result<Option<String>, CompileError> {
+        Ok(match &mut place.kind {
+            ExprKind::VarUse(_) if place.symbol.is_some() && place.symbol == self.receiver => Some("(local.get $self_area)".to_string()),
+            ExprKind::VarUse(_) => {
+                // Variables without an entry are parameters, which live as long as the function body.
+                let depth = place.symbol.and_then(|id| self.var_areas.get(&id)).copied().unwrap_or(1);
+                (depth < self.areas).then(|| Self::outer_area(self.areas - depth))
+            }
+            ExprKind::Field(object, _) => self.place_area(object, inits)?,
+            ExprKind::Index(array, _) => {
+                let temp = self.temporary("i32");
+                inits.push(format!("(local.set {} {})", temp, self.expression(array)?));
+                **array = Expr { kind: ExprKind::VarUse(temp.clone()), symbol: None, ..(**array).clone() };
+                Some(format!("(i32.load offset=16 (local.get {}))", temp))
+            }
+            _ => None,
+        })
+    }
+
+    /// Lowers a `for` loop. The end of a range, the array and its length are evaluated once, so
+    /// pushing to the array in the body does not extend the loop. The body is a block, so that
+    /// `continue` branches to the code that advances the loop.
+    fn for_loop(&mut self, name: &str, symbol: Option<SymbolId>, iterable: &Iterable, body: &Stmt, span: Span) -> Result<(), CompileError> {
+        let Some(id) = symbol else {
+            return Err(CompileError::codegen(format!("loop variable `{}` without a symbol", name), span));
+        };
+        let n = self.next();
+        let (check, body_block, end) = (format!("$for.cond.{}", n), format!("$for.body.{}", n), format!("$for.end.{}", n));
+        match iterable {
+            Iterable::Range(start, stop) => {
+                let Some(ValueType::Int(int)) = start.typ.clone() else {
+                    return Err(CompileError::codegen("range without an integer type", span));
+                };
+                let typ = ValueType::Int(int);
+                let wasm_type = Self::wasm_type(&typ);
+                let start = self.expression(start)?;
+                let stop = self.expression(stop)?;
+                let local = self.variable(name, id, &typ);
+                let stop_local = self.temporary(wasm_type);
+                self.emit(format!("(local.set {} {})", local, start));
+                self.emit(format!("(local.set {} {})", stop_local, stop));
+                self.open(format!("(block {}", end));
+                self.open(format!("(loop {}", check));
+                let ge = if int.is_signed() { "ge_s" } else { "ge_u" };
+                self.emit(format!("(br_if {} ({}.{} (local.get {}) (local.get {})))", end, wasm_type, ge, local, stop_local));
+                self.open(format!("(block {}", body_block));
+                self.loop_body(body, &body_block, &end)?;
+                self.close();
+                self.emit(format!("(local.set {} ({}.add (local.get {}) ({}.const 1)))", local, wasm_type, local, wasm_type));
+            }
+            Iterable::Array(array) => {
+                let Some(ValueType::Array(element, _)) = array.typ.clone() else {
+                    return Err(CompileError::codegen("iterating over a value that is not an array", span));
+                };
+                let size = self.size_of(&element, span)?;
+                let items = self.expression(array)?;
+                let (items_local, index, len) = (self.temporary("i32"), self.temporary("i32"), self.temporary("i32"));
+                self.emit(format!("(local.set {} {})", items_local, items));
+                self.emit(format!("(local.set {} (i32.const 0))", index));
+                self.emit(format!("(local.set {} (i32.load offset=4 (local.get {})))", len, items_local));
+                let local = self.variable(name, id, &element);
+                if let ValueType::Struct(_) = *element {
+                    self.runtime.insert("area_alloc");
+                    self.emit(format!("(local.set {} (call $rt.area_alloc (i32.const {})))", local, size));
+                }
+                self.open(format!("(block {}", end));
+                self.open(format!("(loop {}", check));
+                self.emit(format!("(br_if {} (i32.ge_s (local.get {}) (local.get {})))", end, index, len));
+                // The data is read again on every iteration, because pushing can move it.
+                let address = format!("(i32.add (i32.load (local.get {})) (i32.mul (local.get {}) (i32.const {})))", items_local, index, size);
+                let value = self.load(&element, address, 0);
+                let code = match *element {
+                    ValueType::Struct(_) => format!("(memory.copy (local.get {}) {} (i32.const {}))", local, value, size),
+                    _ => format!("(local.set {} {})", local, value),
+                };
+                self.emit(code);
+                self.open(format!("(block {}", body_block));
+                self.loop_body(body, &body_block, &end)?;
+                self.close();
+                self.emit(format!("(local.set {} (i32.add (local.get {}) (i32.const 1)))", index, index));
+            }
+        }
+        self.emit(format!("(br {})", check));
+        self.close();
+        self.close();
+        Ok(())
+    }
+
+    /// Returns the folded WAT instructions of an expression. Struct values are their address.
+    fn expression(&mut self, expr: &Expr) -> Result<String, CompileError> {
+        let code = match &expr.kind {
+            ExprKind::Value(value) => self.constant(value, expr.typ.as_ref()),
+            ExprKind::VarUse(name) => format!("(local.get {})", self.local(expr, name)?),
+            ExprKind::Binary(op, left, right) => {
+                let typ = self.type_of(left)?;
+                let left = self.expression(left)?;
+                let right = self.expression(right)?;
+                match &typ {
+                    ValueType::Number => {
+                        let instruction = match op {
+                            '+' => "add",
+                            '-' => "sub",
+                            '*' => "mul",
+                            _ => "div",
+                        };
+                        format!("(f64.{} {} {})", instruction, left, right)
+                    }
+                    ValueType::Int(int) if matches!(op, '/' | '%') && int.is_signed() => self.division(*op == '/', &typ, left, right),
+                    ValueType::Int(int) => {
+                        let instruction = match op {
+                            '+' => "add",
+                            '-' => "sub",
+                            '*' => "mul",
+                            '/' => "div_u",
+                            _ => "rem_u",
+                        };
+                        Self::wrap(*int, format!("({}.{} {} {})", Self::wasm_type(&typ), instruction, left, right))
+                    }
+                    _ => return Err(CompileError::codegen(format!("`{}` on values that are not numbers", op), expr.span)),
+                }
+            }
+            ExprKind::Condition(op, left, right) if op == "&&" => {
+                format!("(if (result i32) {} (then {}) (else (i32.const 0)))", self.expression(left)?, self.expression(right)?)
+            }
+            ExprKind::Condition(op, left, right) if op == "||" => {
+                format!("(if (result i32) {} (then (i32.const 1)) (else {}))", self.expression(left)?, self.expression(right)?)
+            }
+            ExprKind::Condition(op, left, right) => {
+                let typ = self.type_of(left)?;
+                let left = self.expression(left)?;
+                let right = self.expression(right)?;
+                let signed = match &typ {
+                    ValueType::Number => "",
+                    ValueType::Int(int) if !int.is_signed() => "_u",
+                    _ => "_s",
+                };
+                let instruction = match op.as_str() {
+                    "==" => "eq".to_string(),
+                    "!=" => "ne".to_string(),
+                    "<" => format!("lt{}", signed),
+                    ">" => format!("gt{}", signed),
+                    "<=" => format!("le{}", signed),
+                    _ => format!("ge{}", signed),
+                };
+                format!("({}.{} {} {})", Self::wasm_type(&typ), instruction, left, right)
+            }
+            ExprKind::Unary(op, operand) => {
+                let typ = self.type_of(operand)?;
+                let value = self.expression(operand)?;
+                match (op, &typ) {
+                    ('!', _) => format!("(i32.eqz {})", value),
+                    (_, ValueType::Number) => format!("(f64.neg {})", value),
+                    (_, ValueType::Int(int)) => {
+                        let wasm_type = Self::wasm_type(&typ);
+                        Self::wrap(*int, format!("({}.sub ({}.const 0) {})", wasm_type, wasm_type, value))
+                    }
+                    _ => return Err(CompileError::codegen(format!("`{}` on a value that is not a number", op), expr.span)),
+                }
+            }
+            ExprKind::Convert(to, operand) => {
+                let from = self.type_of(operand)?;
+                let value = self.expression(operand)?;
+                Self::convert(value, &from, to)
+            }
+            ExprKind::Functional(name, args) => {
+                if expr.symbol.is_some_and(|id| self.functions.contains(&id)) {
+                    let mut code = format!("(call ${}", Self::function_name(name));
+                    for arg in args {
+                        code += &format!(" {}", self.expression(arg)?);
+                    }
+                    code + ")"
+                } else {
+                    self.host_call(name, expr, args)?
+                }
+            }
+            ExprKind::Builtin(builtin, args) => {
+                let element = match args.first().and_then(|array| array.typ.as_ref()) {
+                    Some(ValueType::Array(element, _)) => (**element).clone(),
+                    _ => return Err(CompileError::codegen(format!("`{}` called on a value that is not an array", builtin.name()), expr.span)),
+                };
+                let array = self.expression(&args[0])?;
+                match builtin {
+                    Builtin::Len => format!("(i32.load offset=4 {})", array),
+                    Builtin::Push => {
+                        let Some(item) = args.get(1) else {
+                            return Err(CompileError::codegen("`push` without an element", expr.span));
+                        };
+                        let value = self.expression(item)?;
+                        let array_local = self.temporary("i32");
+                        let value_local = self.temporary(Self::wasm_type(&element));
+                        self.runtime.insert("array_push");
+                        // The element is promoted to the area of the array, which may be an outer one.
+                        let promotion = if self.needs_promotion(&element) {
+                            let area = self.temporary("i32");
+                            let promotion = self.promotion(&value_local, &element, &area, expr.span)?;
+                            format!(" (local.set {} (i32.load offset=16 (local.get {}))) {}", area, array_local, promotion)
+                        } else {
+                            String::new()
+                        };
+                        // `array_push` returns the address of the new element.
+                        let store = self.store_at(&element, format!("(call $rt.array_push (local.get {}))", array_local), 0, format!("(local.get {})", value_local), expr.span)?;
+                        format!("(block (local.set {} {}) (local.set {} {}){} {})", array_local, array, value_local, value, promotion, store)
+                    }
+                }
+            }
+            ExprKind::Array(elements) => {
+                let Some(ValueType::Array(element, _)) = &expr.typ else {
+                    return Err(CompileError::codegen("array literal without a type", expr.span));
+                };
+                let size = self.size_of(element, expr.span)?;
+                self.runtime.insert("array_new");
+                let array = format!("(call $rt.array_new (i32.const {}) (i32.const {}))", size, elements.len());
+                if elements.is_empty() {
+                    array
+                } else {
+                    let (array_local, data) = (self.temporary("i32"), self.temporary("i32"));
+                    let mut code = format!("(block (result i32) (local.set {} {}) (local.set {} (i32.load (local.get {})))", array_local, array, data, array_local);
+                    for (i, value) in elements.iter().enumerate() {
+                        let value = self.expression(value)?;
+                        code += &format!(" {}", self.store_at(element, format!("(local.get {})", data), i as u32 * size, value, expr.span)?);
+                    }
+                    format!("{} (local.get {}))", code, array_local)
+                }
+            }
+            ExprKind::Interpolation(parts) => {
+                let mut pieces: Vec<String> = vec![];
+                for part in parts {
+                    match part {
+                        InterpolationPart::Text(text) => pieces.push(self.string(text)),
+                        InterpolationPart::Expr(value) => pieces.push(self.display(value)?),
+                    }
+                }
+                self.concat(pieces)
+            }
+            ExprKind::New(name, inits) => {
+                let Some(fields) = self.symbols.struct_fields(name).map(|fields| fields.to_vec()) else {
+                    return Err(CompileError::codegen(format!("unknown struct `{}`", name), expr.span));
+                };
+                let (offsets, size) = self.layout(&fields, expr.span)?;
+                let local = self.temporary("i32");
+                self.runtime.insert("area_alloc");
+                let mut code = format!("(block (result i32) (local.set {} (call $rt.area_alloc (i32.const {})))", local, size);
+                for init in inits {
+                    let Some(index) = fields.iter().position(|field| field.name == init.name) else {
+                        return Err(CompileError::codegen(format!("struct `{}` has no field `{}`", name, init.name), init.value.span));
+                    };
+                    let value = self.expression(&init.value)?;
+                    code += &format!(" {}", self.store_at(&fields[index].typ, format!("(local.get {})", local), offsets[index], value, init.value.span)?);
+                }
+                format!("{} (local.get {}))", code, local)
+            }
+            ExprKind::Field(object, field) => {
+                let (typ, offset) = self.field(object, field)?;
+                let object = self.expression(object)?;
+                self.load(&typ, object, offset)
+            }
+            ExprKind::Index(array, index) => {
+                let typ = self.type_of(expr)?;
+                let address = self.element(array, index)?;
+                self.load(&typ, address, 0)
+            }
+            ExprKind::MethodCall(receiver, name, args) => {
+                let owner = match &receiver.typ {
+                    Some(ValueType::Struct(owner)) => owner.clone(),
+                    _ => return Err(CompileError::codegen(format!("method `{}` called on a value that is not a struct", name), expr.span)),
+                };
+                // A struct value is its address, so the method works on the receiver itself.
+                let mut receiver = (**receiver).clone();
+                let mut inits: Vec<String> = vec![];
+                let area = if receiver.is_place() { self.place_area(&mut receiver, &mut inits)? } else { None };
+                let area = area.unwrap_or_else(|| Self::outer_area(0));
+                let mut code = format!("(call ${} {} {}", Self::method_name(&owner, name), self.expression(&receiver)?, area);
+                for arg in args {
+                    code += &format!(" {}", self.expression(arg)?);
+                }
+                code += ")";
+                if inits.is_empty() {
+                    code
+                } else {
+                    // The temporaries are set before the call reads them.
+                    let result = match self.type_of(expr)? {
+                        ValueType::Unit => String::new(),
+                        typ => format!(" (result {})", Self::wasm_type(&typ)),
+                    };
+                    format!("(block{} {} {})", result, inits.join(" "), code)
+                }
+            }
+        };
+        Ok(code)
+    }
+
+    /// Divides signed integers. A zero divisor traps, like other runtime errors, but the smallest
+    /// value divided by -1, which traps in WebAssembly, wraps around like in the interpreter: the
+    /// quotient is the negated value and the remainder 0.
+    fn division(&mut self, is_div: bool, typ: &ValueType, left: String, right: String) -> String {
+        let wasm_type = Self::wasm_type(typ);
+        let (left_local, right_local) = (self.temporary(wasm_type), self.temporary(wasm_type));
+        let overflowed = if is_div { format!("({}.sub ({}.const 0) (local.get {}))", wasm_type, wasm_type, left_local) } else { format!("({}.const 0)", wasm_type) };
+        let result = format!("({}.{} (local.get {}) (local.get {}))", wasm_type, if is_div { "div_s" } else { "rem_s" }, left_local, right_local);
+        let code = format!("(block (result {}) (local.set {} {}) (local.set {} {}) (if (result {}) ({}.eq (local.get {}) ({}.const -1)) (then {}) (else {})))",
+                           wasm_type, left_local, left, right_local, right, wasm_type, wasm_type, right_local, wasm_type, overflowed, result);
+        match typ {
+            ValueType::Int(int) => Self::wrap(*int, code),
+            _ => code,
+        }
+    }
+
+    /// Calls a function of the `io` or `math` module. The format strings of `io` are split at
+    /// compile time, so the host only ever gets a finished string.
+    fn host_call(&mut self, name: &str, expr: &Expr, args: &[Expr]) -> Result<String, CompileError> {
+        let Some(signature) = self.symbols.signature(expr.symbol).cloned() else {
+            return Err(CompileError::codegen(format!("call of `{}` without a signature", name), expr.span));
+        };
+        match signature.format {
+            Some(FormatArgs::Values) => {
+                let text = match args.first().map(|fmt| &fmt.kind) {
+                    Some(ExprKind::Value(Value::String(fmt))) => self.format(fmt, &args[1..])?,
+                    Some(_) if args.len() == 1 => self.expression(&args[0])?,
+                    _ => return Err(CompileError::codegen(format!("`{}` needs a literal format string on the wasm target", name), expr.span)),
+                };
+                if name == "format" {
+                    return Ok(text);
+                }
+                self.import(name, "lamp", "(param i32)");
+                Ok(format!("(call ${} {})", name, text))
+            }
+            Some(FormatArgs::Targets) => Err(CompileError::codegen(format!("`{}` is not available on the wasm target", name), expr.span)),
+            None => {
+                let params: Vec<String> = signature.params.iter().map(|typ| format!("(param {})", Self::wasm_type(typ))).collect();
+                self.import(name, "math", &format!("{} (result {})", params.join(" "), Self::wasm_type(&signature.return_type)));
+                let mut code = format!("(call ${}", name);
+                for arg in args {
+                    code += &format!(" {}", self.expression(arg)?);
+                }
+                Ok(code + ")")
+            }
+        }
+    }
+
+    /// Substitutes the arguments for the `{s}`, `{i}` and `{f}` placeholders of a format string.
+    fn format(&mut self, fmt: &str, args: &[Expr]) -> Result<String, CompileError> {
+        let chars: Vec<char> = fmt.chars().collect();
+        let mut pieces: Vec<String> = vec![];
+        let mut text = String::new();
+        let mut args = args.iter();
+        let mut i = 0;
+        while i < chars.len() {
+            if chars[i] == '{' && i + 2 < chars.len() && chars[i + 2] == '}' {
+                if !text.is_empty() {
+                    pieces.push(self.string(&std::mem::take(&mut text)));
+                }
+                if let Some(arg) = args.next() {
+                    pieces.push(self.display(arg)?);
+                }
+                i += 3;
+            } else {
+                text.push(chars[i]);
+                i += 1;
+            }
+        }
+        if !text.is_empty() || pieces.is_empty() {
+            pieces.push(self.string(&text));
+        }
+        Ok(self.concat(pieces))
+    }
+
+    /// Converts a value to a string, formatted like the `{i}`, `{f}` and `{s}` placeholders.
+    fn display(&mut self, value: &Expr) -> Result<String, CompileError> {
+        let typ = self.type_of(value)?;
+        let code = self.expression(value)?;
+        Ok(match (Placeholder::for_type(&typ), &typ) {
+            (Some(Placeholder::Integer), ValueType::Int(int)) => {
+                self.runtime.insert("format_int");
+                let value = Self::convert(code, &typ, &ValueType::Int(IntType::I64));
+                format!("(call $rt.format_int {} (i32.const {}))", value, i32::from(*int == IntType::U64))
+            }
+            (Some(Placeholder::Number), _) => {
+                self.runtime.insert("format_number");
+                format!("(call $rt.format_number {})", code)
+            }
+            (Some(_), _) => code,
+            (None, _) => format!("(select {} {} {})", self.string("true"), self.string("false"), code),
+        })
+    }
+
+    fn concat(&mut self, pieces: Vec<String>) -> String {
+        let mut pieces = pieces.into_iter();
+        let first = pieces.next().unwrap_or_else(|| self.string(""));
+        pieces.fold(first, |text, piece| {
+            self.runtime.insert("string_concat");
+            format!("(call $rt.string_concat {} {})", text, piece)
+        })
+    }
+
+    /// Returns the instruction that stores a value into a variable, a field or an array element.
+    fn store(&mut self, place: &Expr, typ: &ValueType, value: String) -> Result<String, CompileError> {
+        match &place.kind {
+            ExprKind::VarUse(name) => {
+                let local = self.local(place, name)?;
+                match typ {
+                    ValueType::Struct(_) => self.store_at(typ, format!("(local.get {})", local), 0, value, place.span),
+                    _ => Ok(format!("(local.set {} {})", local, value)),
+                }
+            }
+            ExprKind::Field(object, field) => {
+                let (typ, offset) = self.field(object, field)?;
+                let object = self.expression(object)?;
+                self.store_at(&typ, object, offset, value, place.span)
+            }
+            ExprKind::Index(array, index) => {
+                let address = self.element(array, index)?;
+                self.store_at(typ, address, 0, value, place.span)
+            }
+            _ => Err(CompileError::codegen("assigning to a value that is not a place", place.span)),
+        }
+    }
+
+    fn store_at(&mut self, typ: &ValueType, address: String, offset: u32, value: String, span: Span) -> Result<String, CompileError> {
+        let offset_immediate = if offset == 0 { String::new() } else { format!(" offset={}", offset) };
+        Ok(match typ {
+            ValueType::Struct(_) => {
+                let address = if offset == 0 { address } else { format!("(i32.add {} (i32.const {}))", address, offset) };
+                format!("(memory.copy {} {} (i32.const {}))", address, value, self.size_of(typ, span)?)
+            }
+            _ => format!("({}.store{} {} {})", Self::wasm_type(typ), offset_immediate, address, value),
+        })
+    }
+
+    fn load(&self, typ: &ValueType, address: String, offset: u32) -> String {
+        match typ {
+            ValueType::Struct(_) if offset == 0 => address,
+            ValueType::Struct(_) => format!("(i32.add {} (i32.const {}))", address, offset),
+            _ if offset == 0 => format!("({}.load {})", Self::wasm_type(typ), address),
+            _ => format!("({}.load offset={} {})", Self::wasm_type(typ), offset, address),
+        }
+    }
+
+    /// Returns the address of an array element, trapping if the index is out of bounds.
+    fn element(&mut self, array: &Expr, index: &Expr) -> Result<String, CompileError> {
+        let index_type = self.type_of(index)?;
+        let array = self.expression(array)?;
+        let index = self.expression(index)?;
+        self.runtime.insert("array_at");
+        Ok(format!("(call $rt.array_at {} {})", array, Self::convert(index, &index_type, &ValueType::Int(IntType::I64))))
+    }
+
+    /// Returns the type and the offset of a field of a struct value.
+    fn field(&self, object: &Expr, field: &str) -> Result<(ValueType, u32), CompileError> {
+        let owner = match &object.typ {
+            Some(ValueType::Struct(owner)) => owner.clone(),
+            _ => return Err(CompileError::codegen(format!("field `{}` of a value that is not a struct", field), object.span)),
+        };
+        let fields = self.symbols.struct_fields(&owner).map(|fields| fields.to_vec()).unwrap_or_default();
+        let Some(index) = fields.iter().position(|f| f.name == field) else {
+            return Err(CompileError::codegen(format!("struct `{}` has no field `{}`", owner, field), object.span));
+        };
+        let (offsets, _) = self.layout(&fields, object.span)?;
+        Ok((fields[index].typ.clone(), offsets[index]))
+    }
+
+    /// Lays out fields like a C compiler: each at a multiple of its alignment, with the size
+    /// rounded up to the largest alignment. Struct fields are stored inline.
+    fn layout(&self, fields: &[TypedArgument], span: Span) -> Result<(Vec<u32>, u32), CompileError> {
+        let mut offsets = vec![];
+        let (mut size, mut align) = (0, 1);
+        for field in fields {
+            let (field_size, field_align) = self.size_align(&field.typ, span)?;
+            size = u32::next_multiple_of(size, field_align);
+            offsets.push(size);
+            size += field_size;
+            align = align.max(field_align);
+        }
+        Ok((offsets, size.next_multiple_of(align)))
+    }
+
+    fn size_of(&self, typ: &ValueType, span: Span) -> Result<u32, CompileError> {
+        Ok(self.size_align(typ, span)?.0)
+    }
+
+    fn size_align(&self, typ: &ValueType, span: Span) -> Result<(u32, u32), CompileError> {
+        match typ {
+            ValueType::Number | ValueType::Int(IntType::I64 | IntType::U64) => Ok((8, 8)),
+            ValueType::Struct(name) => {
+                let Some(fields) = self.symbols.struct_fields(name) else {
+                    return Err(CompileError::codegen(format!("unknown struct `{}`", name), span));
+                };
+                let (_, size) = self.layout(fields, span)?;
+                let align = fields.iter().map(|field| self.size_align(&field.typ, span).map(|(_, align)| align))
+                    .try_fold(1, |max, align| align.map(|align| max.max(align)))?;
+                Ok((size, align))
+            }
+            _ => Ok((4, 4)),
+        }
+    }
+
+    fn constant(&mut self, value: &Value, typ: Option<&ValueType>) -> String {
+        match (value, typ) {
+            (Value::Number(n), _) => format!("(f64.const {:?})", n),
+            (Value::Integer(n), Some(ValueType::Number)) => format!("(f64.const {:?})", *n as f64),
+            (Value::Integer(n), Some(typ)) => format!("({}.const {})", Self::wasm_type(typ), n),
+            (Value::Integer(n), None) => format!("(i32.const {})", n),
+            (Value::Bool(b), _) => format!("(i32.const {})", i32::from(*b)),
+            (Value::String(s), _) => self.string(s),
+        }
+    }
+
+    /// Returns the address of a string constant, adding it to the data the first time.
+    fn string(&mut self, s: &str) -> String {
+        let address = match self.strings.iter().find(|(string, _)| string == s) {
+            Some((_, address)) => *address,
+            None => {
+                let address = self.data_end;
+                self.strings.push((s.to_string(), address));
+                self.data_end += s.len() as u32 + 1;
+                address
+            }
+        };
+        format!("(i32.const {})", address)
+    }
+
+    /// Escapes a string for a WAT string, writing everything outside printable ASCII as hex
+    /// escapes of its UTF-8 bytes.
+    fn escape(s: &str) -> String {
+        let mut escaped = String::new();
+        for byte in s.bytes() {
+            match byte {
+                0x20..=0x7e if byte != b'"' && byte != b'\\' => escaped.push(byte as char),
+                _ => escaped.push_str(&format!("\\{:02x}", byte)),
+            }
+        }
+        escaped
+    }
+
+    fn import(&mut self, name: &str, module: &str, signature: &str) {
+        if !self.imports.iter().any(|(imported, _)| imported == name) {
+            let import = format!("(import \"{}\" \"{}\" (func ${} {}))", module, name, name, signature);
+            self.imports.push((name.to_string(), import));
+        }
+    }
+
+    /// Converts between numeric types. Integers narrower than 32 bits live in an `i32` and are
+    /// kept in their range, like the values of the C types they stand for.
+    fn convert(value: String, from: &ValueType, to: &ValueType) -> String {
+        match (from, to) {
+            (ValueType::Int(from), ValueType::Int(to)) => {
+                let (from_wide, to_wide) = (Self::is_wide(*from), Self::is_wide(*to));
+                let value = match (from_wide, to_wide) {
+                    (false, true) if from.is_signed() => format!("(i64.extend_i32_s {})", value),
+                    (false, true) => format!("(i64.extend_i32_u {})", value),
+                    (true, false) => format!("(i32.wrap_i64 {})", value),
+                    _ => value,
+                };
+                if to_wide { value } else { Self::wrap(*to, value) }
+            }
+            (ValueType::Int(from), ValueType::Number) => {
+                let sign = if from.is_signed() { "s" } else { "u" };
+                format!("(f64.convert_{}_{} {})", Self::wasm_type(&ValueType::Int(*from)), sign, value)
+            }
+            (ValueType::Number, ValueType::Int(to)) => match to {
+                IntType::U32 => format!("(i32.trunc_sat_f64_u {})", value),
+                IntType::I64 => format!("(i64.trunc_sat_f64_s {})", value),
+                IntType::U64 => format!("(i64.trunc_sat_f64_u {})", value),
+                _ => Self::wrap(*to, format!("(i32.trunc_sat_f64_s {})", value)),
+            },
+            _ => value,
+        }
+    }
+
+    /// Wraps the result of an `i32` operation into the range of a narrower integer type.
+    fn wrap(int: IntType, value: String) -> String {
+        match int {
+            IntType::I8 => format!("(i32.extend8_s {})", value),
+            IntType::I16 => format!("(i32.extend16_s {})", value),
+            IntType::U8 => format!("(i32.and {} (i32.const 255))", value),
+            IntType::U16 => format!("(i32.and {} (i32.const 65535))", value),
+            _ => value,
+        }
+    }
+
+    fn is_wide(int: IntType) -> bool {
+        matches!(int, IntType::I64 | IntType::U64)
+    }
+
+    /// The WebAssembly type of a value. Strings, arrays and structs are addresses.
+    fn wasm_type(typ: &ValueType) -> &'static str {
+        match typ {
+            ValueType::Number => "f64",
+            ValueType::Int(int) if Self::is_wide(*int) => "i64",
+            _ => "i32",
+        }
+    }
+
+    /// The name of a function of the program. Lamp names have no `.`, so they cannot clash with
+    /// the `rt.` functions of the runtime or with the imports, which keep their plain names.
+    fn function_name(name: &str) -> String {
+        format!("lamp.{}", name)
+    }
+
+    fn method_name(owner: &str, name: &str) -> String {
+        format!("lamp.{}.{}", owner, name)
+    }
+
+    /// The local of a variable. The temporaries that `place_area` puts in the tree have no symbol
+    /// and are named by their local.
+    fn local(&self, expr: &Expr, name: &str) -> Result<String, CompileError> {
+        match expr.symbol {
+            Some(id) => self.names.get(&id).cloned()
+                .ok_or_else(|| CompileError::codegen(format!("variable `{}` without a local", name), expr.span)),
+            None if name.starts_with("$t.") => Ok(name.to_string()),
+            None => Err(CompileError::codegen(format!("variable `{}` without a symbol", name), expr.span)),
+        }
+    }
+
+    /// Declares the local of a variable, named `$v.NAME.ID` apart from parameters and temporaries.
+    fn variable(&mut self, name: &str, id: SymbolId, typ: &ValueType) -> String {
+        let local = format!("$v.{}.{}", name, id.0);
+        self.locals.push(format!("    (local {} {})", local, Self::wasm_type(typ)));
+        self.names.insert(id, local.clone());
+        local
+    }
+
+    /// Declares a local for an intermediate value.
+    fn temporary(&mut self, wasm_type: &str) -> String {
+        let local = format!("$t.{}", self.next());
+        self.locals.push(format!("    (local {} {})", local, wasm_type));
+        local
+    }
+
+    fn emit(&mut self, line: impl Into<String>) {
+        self.lines.push(format!("{}{}", "  ".repeat(self.depth), line.into()));
+    }
+
+    /// Starts a block, loop or `if` whose instructions are nested on the following lines.
+    fn open(&mut self, line: impl Into<String>) {
+        self.emit(line);
+        self.depth += 1;
+    }
+
+    /// Ends the innermost block by closing its parenthesis on its last line.
+    fn close(&mut self) {
+        self.depth -= 1;
+        if let Some(line) = self.lines.last_mut() {
+            line.push(')');
+        }
+    }
+
+    fn next(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn type_of(&self, expr: &Expr) -> Result<ValueType, CompileError> {
+        expr.typ.clone().ok_or_else(|| CompileError::codegen("expression without a type", expr.span))
+    }
+}